@@ -0,0 +1,133 @@
+use vec3::{Axis, Vec3};
+
+use super::ray::Ray;
+
+// Axis-aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min : Vec3,
+    pub max : Vec3,
+}
+
+pub const AXES : [Axis; 3] = [ Axis::X, Axis::Y, Axis::Z ];
+
+impl Aabb {
+
+    // An inverted box that contains nothing; growing it by anything gives that thing
+    pub const EMPTY : Aabb = Aabb {
+        min : Vec3 { x : f32::INFINITY, y : f32::INFINITY, z : f32::INFINITY },
+        max : Vec3 { x : f32::NEG_INFINITY, y : f32::NEG_INFINITY, z : f32::NEG_INFINITY },
+    };
+
+    // Box spanning two corner points, in any order
+    pub fn new( a : Vec3, b : Vec3 ) -> Aabb {
+        Aabb { min : a.min( &b ), max : a.max( &b ) }
+    }
+
+    pub fn surrounding( a : &Aabb, b : &Aabb ) -> Aabb {
+        Aabb { min : a.min.min( &b.min ), max : a.max.max( &b.max ) }
+    }
+
+    pub fn grow( &mut self, p : Vec3 ) {
+        self.min = self.min.min( &p );
+        self.max = self.max.max( &p );
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn extent( &self ) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn centroid( &self ) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area( &self ) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.extent();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn longest_axis( &self ) -> Axis {
+        let d = self.extent();
+        if d.x > d.y && d.x > d.z {
+            Axis::X
+        } else if d.y > d.z {
+            Axis::Y
+        } else {
+            Axis::Z
+        }
+    }
+
+    // Expands any side thinner than delta, so flat primitives (e.g. an
+    // axis-aligned quad) still have a box a ray can hit.
+    pub fn padded( &self, delta : f32 ) -> Aabb {
+        let mut result = *self;
+        for axis in AXES {
+            let lo = self.min.component( axis );
+            let hi = self.max.component( axis );
+            if hi - lo < delta {
+                result.min.set_component( axis, lo - delta * 0.5 );
+                result.max.set_component( axis, hi + delta * 0.5 );
+            }
+        }
+        result
+    }
+
+    // Slab test. inv_dir is 1/ray.dir, passed in so traversal only computes it once.
+    pub fn hit( &self, ray : &Ray, inv_dir : &Vec3, ray_tmin : f32, ray_tmax : f32 ) -> bool {
+        let mut tmin = ray_tmin;
+        let mut tmax = ray_tmax;
+        for axis in AXES {
+            let inv_d = inv_dir.component( axis );
+            let orig = ray.origin.component( axis );
+            let mut t0 = (self.min.component( axis ) - orig) * inv_d;
+            let mut t1 = (self.max.component( axis ) - orig) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap( &mut t0, &mut t1 );
+            }
+
+            // written so a NaN (0 * inf) slab leaves the interval alone
+            if t0 > tmin { tmin = t0; }
+            if t1 < tmax { tmax = t1; }
+            if tmax < tmin {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surrounding_and_area() {
+        let a = Aabb::new( Vec3::ZERO, Vec3::ONE );
+        let b = Aabb::new( Vec3::new( 2.0, 2.0, 2.0 ), Vec3::new( 1.0, 1.0, 1.0 ) );
+        let c = Aabb::surrounding( &a, &b );
+        assert_eq!( c.min, Vec3::ZERO );
+        assert_eq!( c.max, Vec3::from_float( 2.0 ) );
+        assert_eq!( c.surface_area(), 24.0 );
+        assert_eq!( Aabb::surrounding( &Aabb::EMPTY, &a ), a );
+        assert_eq!( Aabb::EMPTY.surface_area(), 0.0 );
+    }
+
+    #[test]
+    fn ray_hit() {
+        let b = Aabb::new( Vec3::from_float( -1.0 ), Vec3::ONE );
+        let ray = Ray::new( Vec3::new( 0.0, 0.0, -5.0 ), Vec3::new( 0.0, 0.0, 1.0 ) );
+        let inv_dir = Vec3::ONE / ray.dir;
+        assert!( b.hit( &ray, &inv_dir, 0.0, f32::INFINITY ) );
+        assert!( !b.hit( &ray, &inv_dir, 0.0, 3.0 ) );
+
+        let miss = Ray::new( Vec3::new( 2.0, 0.0, -5.0 ), Vec3::new( 0.0, 0.0, 1.0 ) );
+        assert!( !b.hit( &miss, &inv_dir, 0.0, f32::INFINITY ) );
+    }
+}
@@ -0,0 +1,140 @@
+// Arbitrary output variables: everything besides the final color that the
+// film keeps per pixel, for compositing and debugging.
+//
+// The lighting passes split the beauty by what the camera ray hit first
+// (diffuse or specular) and whether the light came straight from a light
+// source after that bounce (direct) or bounced around more (indirect).
+// Emission is light seen directly, the sky included. Together they add up
+// to the beauty pass.
+
+use vec3::Vec3;
+
+use super::film::Film;
+use super::tonemap::{DisplayTransform, ToneMap};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aov {
+    Beauty,
+    Depth,      // distance from the camera, 0 where nothing was hit
+    Normal,     // world space, at the first hit
+    Albedo,
+    ObjectId,   // 1 and up, 0 for the background
+    MaterialId, // same
+    DiffuseDirect,
+    DiffuseIndirect,
+    SpecularDirect,
+    SpecularIndirect,
+    Emission,
+    SampleCount,
+}
+
+impl Aov {
+
+    pub const ALL : [Aov; 12] = [
+        Aov::Beauty, Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId, Aov::MaterialId,
+        Aov::DiffuseDirect, Aov::DiffuseIndirect, Aov::SpecularDirect, Aov::SpecularIndirect,
+        Aov::Emission, Aov::SampleCount,
+    ];
+
+    pub fn name( &self ) -> &'static str {
+        match self {
+            Aov::Beauty => "beauty",
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::DiffuseDirect => "diffuse_direct",
+            Aov::DiffuseIndirect => "diffuse_indirect",
+            Aov::SpecularDirect => "specular_direct",
+            Aov::SpecularIndirect => "specular_indirect",
+            Aov::Emission => "emission",
+            Aov::SampleCount => "sample_count",
+        }
+    }
+
+    pub fn from_name( name : &str ) -> Option<Aov> {
+        Aov::ALL.iter().copied().find( |a| a.name().eq_ignore_ascii_case( name ) )
+    }
+
+    // Comma separated names, e.g. "depth,normal"
+    pub fn parse_list( names : &str ) -> Result<Vec<Aov>, String> {
+        names.split( ',' ).map( |n| n.trim() ).filter( |n| !n.is_empty() )
+            .map( |n| Aov::from_name( n ).ok_or_else( || format!( "unknown AOV '{}'", n ) ) )
+            .collect()
+    }
+
+    // Light, shown through the display transform like the beauty. The rest
+    // are data and are shown as is.
+    pub fn is_radiance( &self ) -> bool {
+        matches!( self, Aov::Beauty | Aov::DiffuseDirect | Aov::DiffuseIndirect |
+                        Aov::SpecularDirect | Aov::SpecularIndirect | Aov::Emission )
+    }
+
+    // Raw value at a pixel. Single channel passes repeat it in x, y and z.
+    pub fn value( &self, film : &Film, x : u32, y : u32 ) -> Vec3 {
+        let m = film.mean( x, y );
+        match self {
+            Aov::Beauty => film.output( x, y ),
+            Aov::Depth => Vec3::from_float( m.depth ),
+            Aov::Normal => if m.normal.length_squared() > 0.0 { m.normal.normalize() } else { m.normal },
+            Aov::Albedo => m.albedo,
+            Aov::ObjectId => Vec3::from_float( m.object_id as f32 ),
+            Aov::MaterialId => Vec3::from_float( m.material_id as f32 ),
+            Aov::DiffuseDirect => m.diffuse_direct,
+            Aov::DiffuseIndirect => m.diffuse_indirect,
+            Aov::SpecularDirect => m.specular_direct,
+            Aov::SpecularIndirect => m.specular_indirect,
+            Aov::Emission => m.emission,
+            Aov::SampleCount => Vec3::from_float( film.samples( x, y ) as f32 ),
+        }
+    }
+
+    // 8-bit color to look at. Data passes are squeezed into 0..1 in a way
+    // that makes sense for each, and skip exposure and tone mapping.
+    pub fn display( &self, film : &Film, x : u32, y : u32, xform : &DisplayTransform ) -> [u8; 3] {
+        let v = self.value( film, x, y );
+        if self.is_radiance() {
+            return xform.apply( v );
+        }
+
+        let shown = match self {
+            Aov::Depth => Vec3::from_float( v.x / (1.0 + v.x) ),
+            Aov::Normal => 0.5 * v + Vec3::from_float( 0.5 ),
+            Aov::ObjectId | Aov::MaterialId => id_color( v.x as u32 ),
+            Aov::SampleCount => Vec3::from_float( v.x / film.max_samples().max( 1 ) as f32 ),
+            _ => v,
+        };
+        DisplayTransform { exposure : 0.0, tone_map : ToneMap::None }.apply( shown )
+    }
+}
+
+// A distinct, stable color per id, black for 0
+fn id_color( id : u32 ) -> Vec3 {
+    if id == 0 {
+        return Vec3::ZERO;
+    }
+    let h = id.wrapping_mul( 0x9e3779b1 ).rotate_left( 13 ).wrapping_mul( 0x85ebca6b );
+    let channel = |shift : u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f32 / 255.0;
+    Vec3::new( channel( 0 ), channel( 8 ), channel( 16 ) )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        for aov in Aov::ALL {
+            assert_eq!( Aov::from_name( aov.name() ), Some( aov ) );
+        }
+        assert_eq!( Aov::parse_list( "depth, normal," ), Ok( vec![ Aov::Depth, Aov::Normal ] ) );
+        assert!( Aov::parse_list( "depth,nope" ).is_err() );
+    }
+
+    #[test]
+    fn id_colors_differ() {
+        assert_eq!( id_color( 0 ), Vec3::ZERO );
+        assert_ne!( id_color( 1 ), id_color( 2 ) );
+    }
+}
@@ -0,0 +1,291 @@
+use vec3::{Axis, Vec3};
+
+use super::aabb::{Aabb, AXES};
+use super::ray::Ray;
+use super::hittable::{HitRecord, Hittable};
+
+// Number of buckets the centroids are binned into when evaluating splits
+const SAH_BINS : usize = 16;
+
+// Relative cost of a box test versus a primitive intersection
+const TRAVERSAL_COST : f32 = 0.5;
+
+// Never put more than this in a leaf, even if the SAH says splitting isn't worth it
+const MAX_LEAF_SIZE : usize = 8;
+
+type Object = Box<dyn Hittable + Send + Sync>;
+
+// Bounding volume hierarchy over a set of hittables, split using a binned
+// surface area heuristic. A node is itself a Hittable so it can go anywhere
+// a single object can.
+pub enum BvhNode {
+    Leaf {
+        bbox : Aabb,
+        objects : Vec<Object>,
+    },
+    Interior {
+        bbox : Aabb,
+        axis : Axis,
+        left : Box<BvhNode>,
+        right : Box<BvhNode>,
+    },
+}
+
+// Object plus its bounds, cached for the duration of the build
+struct BuildItem {
+    bbox : Aabb,
+    centroid : Vec3,
+    object : Object,
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    bbox : Aabb,
+    count : usize,
+}
+
+impl BvhNode {
+
+    pub fn new( objects : Vec<Object> ) -> BvhNode {
+        let items = objects.into_iter().map( |object| {
+            let bbox = object.bounding_box();
+            BuildItem { bbox, centroid : bbox.centroid(), object }
+        }).collect();
+
+        BvhNode::build( items )
+    }
+
+    fn build( items : Vec<BuildItem> ) -> BvhNode {
+        let mut bbox = Aabb::EMPTY;
+        let mut centroid_bounds = Aabb::EMPTY;
+        for item in &items {
+            bbox = Aabb::surrounding( &bbox, &item.bbox );
+            centroid_bounds.grow( item.centroid );
+        }
+
+        if items.len() <= 1 {
+            return BvhNode::make_leaf( bbox, items );
+        }
+
+        match BvhNode::find_split( &items, &bbox, &centroid_bounds ) {
+            Some( (axis, split_bin) ) => {
+                let (left, right) : (Vec<BuildItem>, Vec<BuildItem>) = items.into_iter()
+                    .partition( |item| bin_index( &centroid_bounds, axis, item.centroid ) < split_bin );
+
+                BvhNode::Interior {
+                    bbox,
+                    axis,
+                    left : Box::new( BvhNode::build( left ) ),
+                    right : Box::new( BvhNode::build( right ) ),
+                }
+            },
+            None if items.len() > MAX_LEAF_SIZE => {
+                // All centroids are (nearly) coincident so binning can't separate
+                // them. Fall back to splitting the list in half.
+                let mut left = items;
+                let right = left.split_off( left.len() / 2 );
+                BvhNode::Interior {
+                    bbox,
+                    axis : bbox.longest_axis(),
+                    left : Box::new( BvhNode::build( left ) ),
+                    right : Box::new( BvhNode::build( right ) ),
+                }
+            },
+            None => BvhNode::make_leaf( bbox, items ),
+        }
+    }
+
+    fn make_leaf( bbox : Aabb, items : Vec<BuildItem> ) -> BvhNode {
+        BvhNode::Leaf {
+            bbox,
+            objects : items.into_iter().map( |item| item.object ).collect(),
+        }
+    }
+
+    // Returns the best (axis, bin) to split at, or None if a leaf is cheaper
+    fn find_split( items : &[BuildItem], bbox : &Aabb, centroid_bounds : &Aabb ) -> Option<(Axis, usize)> {
+        let leaf_cost = items.len() as f32;
+        let parent_area = bbox.surface_area();
+        let mut best : Option<(Axis, usize)> = None;
+        let mut best_cost = f32::INFINITY;
+
+        for axis in AXES {
+            let extent = centroid_bounds.max.component( axis ) - centroid_bounds.min.component( axis );
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let mut bins = [ Bin { bbox : Aabb::EMPTY, count : 0 }; SAH_BINS ];
+            for item in items {
+                let b = bin_index( centroid_bounds, axis, item.centroid );
+                bins[b].count += 1;
+                bins[b].bbox = Aabb::surrounding( &bins[b].bbox, &item.bbox );
+            }
+
+            // sweep from the right to get the area/count of everything right of each split
+            let mut right_area = [ 0.0f32; SAH_BINS ];
+            let mut right_count = [ 0usize; SAH_BINS ];
+            let mut acc = Bin { bbox : Aabb::EMPTY, count : 0 };
+            for b in (1..SAH_BINS).rev() {
+                acc.bbox = Aabb::surrounding( &acc.bbox, &bins[b].bbox );
+                acc.count += bins[b].count;
+                right_area[b] = acc.bbox.surface_area();
+                right_count[b] = acc.count;
+            }
+
+            // then from the left, evaluating the cost of splitting before bin b
+            let mut acc = Bin { bbox : Aabb::EMPTY, count : 0 };
+            for b in 1..SAH_BINS {
+                acc.bbox = Aabb::surrounding( &acc.bbox, &bins[b-1].bbox );
+                acc.count += bins[b-1].count;
+                if acc.count == 0 || right_count[b] == 0 {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST + ( acc.bbox.surface_area() * acc.count as f32
+                                            + right_area[b] * right_count[b] as f32 ) / parent_area;
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some( (axis, b) );
+                }
+            }
+        }
+
+        if best_cost < leaf_cost || items.len() > MAX_LEAF_SIZE {
+            best
+        } else {
+            None
+        }
+    }
+
+    pub fn bbox( &self ) -> Aabb {
+        match self {
+            BvhNode::Leaf { bbox, .. } => *bbox,
+            BvhNode::Interior { bbox, .. } => *bbox,
+        }
+    }
+
+    fn hit_node( &self, ray : &Ray, inv_dir : &Vec3, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        match self {
+            BvhNode::Leaf { bbox, objects } => {
+                if !bbox.hit( ray, inv_dir, ray_tmin, ray_tmax ) {
+                    return None;
+                }
+
+                let mut closest_so_far = ray_tmax;
+                let mut result = None;
+                for object in objects {
+                    if let Some(rec) = object.hit( ray, ray_tmin, closest_so_far ) {
+                        closest_so_far = rec.t;
+                        result = Some(rec);
+                    }
+                }
+                result
+            },
+            BvhNode::Interior { bbox, axis, left, right } => {
+                if !bbox.hit( ray, inv_dir, ray_tmin, ray_tmax ) {
+                    return None;
+                }
+
+                // visit the child nearer the ray origin first so the far one can be culled
+                let (near, far) = if ray.dir.component( *axis ) < 0.0 {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+
+                match near.hit_node( ray, inv_dir, ray_tmin, ray_tmax ) {
+                    Some(rec) => {
+                        let t = rec.t;
+                        far.hit_node( ray, inv_dir, ray_tmin, t ).or( Some(rec) )
+                    },
+                    None => far.hit_node( ray, inv_dir, ray_tmin, ray_tmax ),
+                }
+            },
+        }
+    }
+}
+
+fn bin_index( centroid_bounds : &Aabb, axis : Axis, centroid : Vec3 ) -> usize {
+    let lo = centroid_bounds.min.component( axis );
+    let hi = centroid_bounds.max.component( axis );
+    let f = (centroid.component( axis ) - lo) / (hi - lo);
+    ((f * SAH_BINS as f32) as usize).min( SAH_BINS - 1 )
+}
+
+impl Hittable for BvhNode {
+    fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        let inv_dir = Vec3::ONE / ray.dir;
+        self.hit_node( ray, &inv_dir, ray_tmin, ray_tmax )
+    }
+
+    fn bounding_box( &self ) -> Aabb {
+        self.bbox()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HittableList;
+    use crate::sphere::Sphere;
+    use crate::material::{Lambertian, MaterialRef};
+    use std::sync::Arc;
+
+    // tiny LCG, good enough to scatter some test spheres around
+    fn next( state : &mut u32 ) -> f32 {
+        *state = state.wrapping_mul( 1664525 ).wrapping_add( 1013904223 );
+        (*state >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    fn grey() -> MaterialRef {
+        Arc::new( Lambertian::new( Vec3::from_float( 0.5 ) ) )
+    }
+
+    fn random_spheres( n : usize, state : &mut u32 ) -> Vec<Object> {
+        let mat = grey();
+        (0..n).map( |_| {
+            let c = Vec3::new( next(state), next(state), next(state) ) * 20.0 - Vec3::from_float( 10.0 );
+            Box::new( Sphere::new( c, 0.1 + next(state) * 0.5, mat.clone() ) ) as Object
+        }).collect()
+    }
+
+    #[test]
+    fn matches_linear_list() {
+        let mut state = 1234;
+        let bvh = BvhNode::new( random_spheres( 500, &mut state ) );
+        let mut state = 1234;
+        let mut list = HittableList::new();
+        for s in random_spheres( 500, &mut state ) {
+            list.add( s );
+        }
+
+        assert_eq!( bvh.bounding_box(), list.bounding_box() );
+
+        for _ in 0..1000 {
+            let origin = Vec3::new( next(&mut state), next(&mut state), next(&mut state) ) * 40.0 - Vec3::from_float( 20.0 );
+            let dir = Vec3::new( next(&mut state), next(&mut state), next(&mut state) ) - Vec3::from_float( 0.5 );
+            let ray = Ray::new( origin, dir );
+
+            let a = bvh.hit( &ray, 0.001, f32::INFINITY ).map( |rec| rec.t );
+            let b = list.hit( &ray, 0.001, f32::INFINITY ).map( |rec| rec.t );
+            assert_eq!( a, b );
+        }
+    }
+
+    #[test]
+    fn empty_and_coincident() {
+        let empty = BvhNode::new( Vec::new() );
+        let ray = Ray::new( Vec3::ZERO, Vec3::new( 0.0, 0.0, -1.0 ) );
+        assert!( empty.hit( &ray, 0.0, f32::INFINITY ).is_none() );
+
+        // lots of objects at the same spot must still build without recursing forever
+        let mat = grey();
+        let same : Vec<Object> = (0..100)
+            .map( |_| Box::new( Sphere::new( Vec3::new( 0.0, 0.0, -2.0 ), 0.5, mat.clone() ) ) as Object )
+            .collect();
+        let bvh = BvhNode::new( same );
+        let rec = bvh.hit( &ray, 0.0, f32::INFINITY ).unwrap();
+        assert!( (rec.t - 1.5).abs() < 1e-5 );
+    }
+}
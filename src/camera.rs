@@ -0,0 +1,173 @@
+use vec3::Vec3;
+
+use super::ray::Ray;
+use super::rng::Rng;
+
+#[derive(Clone, Debug)]
+pub struct CameraSettings {
+    pub look_from : Vec3,
+    pub look_at : Vec3,
+    pub vup : Vec3,
+
+    // vertical field of view, in degrees
+    pub vfov : f32,
+
+    // lens diameter, 0 is a pinhole (everything in focus)
+    pub aperture : f32,
+
+    // distance to the plane of perfect focus, 0 to focus on look_at
+    pub focus_dist : f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> CameraSettings {
+        CameraSettings {
+            look_from : Vec3::ZERO,
+            look_at : Vec3::new( 0.0, 0.0, -1.0 ),
+            vup : Vec3::new( 0.0, 1.0, 0.0 ),
+            vfov : 90.0,
+            aperture : 0.0,
+            focus_dist : 0.0,
+        }
+    }
+}
+
+// Thin lens camera. Rays start on a disk around look_from and pass through
+// the image plane placed at the focus distance.
+pub struct Camera {
+    pub settings : CameraSettings,
+    pub width : u32,
+    pub height : u32,
+
+    center : Vec3,
+    pixel00_loc : Vec3,
+    pixel_delta_u : Vec3,
+    pixel_delta_v : Vec3,
+
+    // lens disk spanned by these, scaled to the lens radius
+    defocus_disk_u : Vec3,
+    defocus_disk_v : Vec3,
+}
+
+impl Camera {
+
+    pub fn new( width : u32, height : u32, settings : &CameraSettings ) -> Camera
+    {
+        let fwidth = width as f32;
+        let fheight = height as f32;
+
+        let focus_dist = if settings.focus_dist > 0.0 {
+            settings.focus_dist
+        } else {
+            (settings.look_at - settings.look_from).length()
+        };
+
+        let h = (settings.vfov.to_radians() * 0.5).tan();
+        let viewport_height = 2.0 * h * focus_dist;
+        let viewport_width = viewport_height * fwidth / fheight;
+
+        // orthonormal camera frame, w points backwards
+        let w = (settings.look_from - settings.look_at).normalize();
+        let u = Vec3::cross( &settings.vup, &w ).normalize();
+        let v = Vec3::cross( &w, &u );
+
+        let viewport_u = viewport_width * u;
+        let viewport_v = viewport_height * -v;
+        let pixel_delta_u = viewport_u / fwidth;
+        let pixel_delta_v = viewport_v / fheight;
+
+        // upper left
+        let viewport_upper_left = settings.look_from
+                            - focus_dist * w
+                            - viewport_u / 2.0
+                            - viewport_v / 2.0;
+        let pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
+
+        let lens_radius = settings.aperture * 0.5;
+
+        Camera {
+            settings : settings.clone(),
+            width,
+            height,
+            center : settings.look_from,
+            pixel00_loc,
+            pixel_delta_u,
+            pixel_delta_v,
+            defocus_disk_u : u * lens_radius,
+            defocus_disk_v : v * lens_radius,
+        }
+    }
+
+    // Same camera for a different image size
+    pub fn resized( &self, width : u32, height : u32 ) -> Camera {
+        Camera::new( width, height, &self.settings )
+    }
+
+    // Ray through pixel (i, j) offset by (dx, dy) pixels from its center,
+    // starting at (lens_u, lens_v) on the unit lens disk.
+    pub fn ray_at_pixel_offset( &self, i : i32, j : i32, dx : f32, dy : f32, lens_u : f32, lens_v : f32 ) -> Ray
+    {
+        let ii = i as f32 + dx;
+        let jj = j as f32 + dy;
+
+        let pixel_center = 
+            self.pixel00_loc + (ii * self.pixel_delta_u) + (jj * self.pixel_delta_v);
+        let origin = self.center + lens_u * self.defocus_disk_u + lens_v * self.defocus_disk_v;
+
+        Ray::new( origin, pixel_center - origin )
+    }
+
+    // Ray through the center of pixel (i, j), from the center of the lens
+    pub fn ray_at_pixel_loc( &self, i : i32, j : i32 ) -> Ray
+    {
+        self.ray_at_pixel_offset( i, j, 0.0, 0.0, 0.0, 0.0 )
+    }
+
+    // Ray through a random point inside pixel (i, j), from a random point on the lens
+    pub fn ray_at_pixel_jittered( &self, i : i32, j : i32, rng : &mut Rng ) -> Ray
+    {
+        let dx = rng.next_f32() - 0.5;
+        let dy = rng.next_f32() - 0.5;
+        let (lens_u, lens_v) = if self.settings.aperture > 0.0 {
+            rng.in_unit_disk()
+        } else {
+            (0.0, 0.0)
+        };
+        self.ray_at_pixel_offset( i, j, dx, dy, lens_u, lens_v )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn center_ray_hits_look_at() {
+        let settings = CameraSettings {
+            look_from : Vec3::new( 3.0, 2.0, 1.0 ),
+            look_at : Vec3::new( 0.0, 0.5, -1.0 ),
+            vfov : 40.0,
+            aperture : 0.5,
+            ..Default::default()
+        };
+
+        // odd size so there is a pixel exactly in the middle
+        let cam = Camera::new( 101, 51, &settings );
+        for (lu, lv) in [ (0.0, 0.0), (1.0, 0.0), (0.0, -1.0), (0.6, 0.6) ] {
+            let ray = cam.ray_at_pixel_offset( 50, 25, 0.0, 0.0, lu, lv );
+            // every lens position focuses on look_at, which is at the focus distance
+            let t = (settings.look_at - ray.origin).length() / ray.dir.length();
+            assert!( (ray.at( t ) - settings.look_at).length() < 1e-4 );
+        }
+    }
+
+    #[test]
+    fn default_matches_old_viewport() {
+        // the default camera is the original fixed one: 2 units tall at z = -1
+        let cam = Camera::new( 200, 100, &CameraSettings::default() );
+        let top = cam.ray_at_pixel_offset( 100, 0, -0.5, -0.5, 0.0, 0.0 );
+        assert!( (top.dir - Vec3::new( 0.0, 1.0, -1.0 )).length() < 1e-5 );
+        let right = cam.ray_at_pixel_offset( 199, 50, 0.5, -0.5, 0.0, 0.0 );
+        assert!( (right.dir - Vec3::new( 2.0, 0.0, -1.0 )).length() < 1e-5 );
+    }
+}
@@ -0,0 +1,241 @@
+// Command line options, shared by the viewer and headless mode
+
+use std::path::PathBuf;
+
+use super::scheduler;
+use super::tile_order::TileOrder;
+use super::denoise::DenoiseMode;
+use super::tonemap::ToneMap;
+use super::aov::Aov;
+use super::exr::ExrPixel;
+
+pub const USAGE : &str = "\
+usage: rtview [options]
+
+  --scene <file>            scene file to render, an .obj to drop into
+                            the demo scene, or 'demo' (the default)
+  --width <px>              image width (default: from scene, or 320)
+  --height <px>             image height (default: from scene, or 200)
+  --spp <n>                 samples per pixel to stop at
+  --time <seconds>          stop starting new passes after this long
+  --max-depth <n>           maximum bounces per path
+  --threads <n>             render threads (default: one per core)
+  --tile-size <n>           tile edge in pixels
+  --tile-order <order>      scanline, spiral, hilbert or center-out
+  --denoise <mode>          off, final or every-pass
+  --seed <n>                random seed
+  --env <file>              light the scene with an equirectangular .hdr
+                            or .pfm environment map
+  --sun <elevation,azimuth> light the scene with a daylight sky, the sun
+                            this many degrees up and round from -Z (not
+                            together with --env)
+  --no-sky                  no light from the sky, only from emitters
+  --brute-force             don't sample lights directly, only find them
+                            by bouncing around (for checking the result);
+                            point, spot and directional lights can't be
+                            found that way and are still sampled
+  --uniform-lights          pick lights to sample all alike, rather than
+                            by how much each might give (for comparison)
+  --tonemap <name>          none, reinhard, aces or agx (default: none)
+  --exposure <stops>        exposure adjustment (default: 0)
+  --output <file>           render without a window and write the image
+                            (.png, .ppm, .exr, .hdr or .pfm)
+  --exr-pixel <type>        half or float, for light in EXR files
+                            (default: half)
+  --compare <file>          after rendering to --output, print the mean
+                            squared error against a reference image of
+                            the same size (.hdr or .pfm)
+  --aov <name,...>          also write these passes, as layers of an EXR
+                            or else as <file>.<name>.<ext>; in the
+                            viewer the first one is shown. beauty,
+                            depth, normal, albedo, object_id, material_id,
+                            diffuse_direct, diffuse_indirect,
+                            specular_direct, specular_indirect, emission,
+                            sample_count
+  --help                    show this message
+";
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub scene : String,
+    pub width : Option<u32>,
+    pub height : Option<u32>,
+    pub spp : Option<u32>,
+    pub time_limit : Option<f32>,
+    pub max_depth : Option<u32>,
+    pub threads : usize,
+    pub tile_size : Option<u32>,
+    pub tile_order : Option<TileOrder>,
+    pub denoise : Option<DenoiseMode>,
+    pub seed : Option<u64>,
+    pub env : Option<PathBuf>,
+    pub sun : Option<(f32, f32)>,
+    pub no_sky : bool,
+    pub brute_force : bool,
+    pub uniform_lights : bool,
+    pub tone_map : ToneMap,
+    pub exposure : f32,
+
+    // set means headless
+    pub output : Option<PathBuf>,
+    pub aovs : Vec<Aov>,
+    pub exr_pixel : ExrPixel,
+    pub compare : Option<PathBuf>,
+    pub help : bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            scene : "demo".to_string(),
+            width : None,
+            height : None,
+            spp : None,
+            time_limit : None,
+            max_depth : None,
+            threads : scheduler::default_threads(),
+            tile_size : None,
+            tile_order : None,
+            denoise : None,
+            seed : None,
+            env : None,
+            sun : None,
+            no_sky : false,
+            brute_force : false,
+            uniform_lights : false,
+            tone_map : ToneMap::None,
+            exposure : 0.0,
+            output : None,
+            aovs : Vec::new(),
+            exr_pixel : ExrPixel::Half,
+            compare : None,
+            help : false,
+        }
+    }
+}
+
+impl Options {
+
+    // Parses arguments, not including the program name
+    pub fn parse<I>( args : I ) -> Result<Options, String>
+        where I : IntoIterator<Item = String>
+    {
+        let mut opts = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name : &str| args.next().ok_or_else( || format!( "{} needs a value", name ) );
+
+            match arg.as_str() {
+                "--scene" => opts.scene = value( &arg )?,
+                "--width" => opts.width = Some( parse_num( &arg, &value( &arg )? )? ),
+                "--height" => opts.height = Some( parse_num( &arg, &value( &arg )? )? ),
+                "--spp" => opts.spp = Some( parse_num( &arg, &value( &arg )? )? ),
+                "--time" => opts.time_limit = Some( parse_num( &arg, &value( &arg )? )? ),
+                "--max-depth" => opts.max_depth = Some( parse_num( &arg, &value( &arg )? )? ),
+                "--threads" => opts.threads = parse_num( &arg, &value( &arg )? )?,
+                "--seed" => opts.seed = Some( parse_num( &arg, &value( &arg )? )? ),
+                "--env" => opts.env = Some( PathBuf::from( value( &arg )? ) ),
+                "--sun" => {
+                    let angles = value( &arg )?;
+                    let (elevation, azimuth) = angles.split_once( ',' ).unwrap_or( (&angles, "0") );
+                    opts.sun = Some( (parse_num( &arg, elevation.trim() )?, parse_num( &arg, azimuth.trim() )?) );
+                },
+                "--no-sky" => opts.no_sky = true,
+                "--brute-force" => opts.brute_force = true,
+                "--uniform-lights" => opts.uniform_lights = true,
+                "--exposure" => opts.exposure = parse_num( &arg, &value( &arg )? )?,
+                "--tile-size" => opts.tile_size = Some( parse_num( &arg, &value( &arg )? )? ),
+                "--tile-order" => {
+                    let name = value( &arg )?;
+                    opts.tile_order = Some( TileOrder::from_name( &name )
+                        .ok_or_else( || format!( "unknown tile order '{}'", name ) )? );
+                },
+                "--denoise" => {
+                    let name = value( &arg )?;
+                    opts.denoise = Some( DenoiseMode::from_name( &name )
+                        .ok_or_else( || format!( "unknown denoise mode '{}'", name ) )? );
+                },
+                "--tonemap" => {
+                    let name = value( &arg )?;
+                    opts.tone_map = ToneMap::from_name( &name )
+                        .ok_or_else( || format!( "unknown tone map '{}'", name ) )?;
+                },
+                "--exr-pixel" => {
+                    let name = value( &arg )?;
+                    opts.exr_pixel = ExrPixel::from_name( &name )
+                        .ok_or_else( || format!( "unknown EXR pixel type '{}'", name ) )?;
+                },
+                "--aov" => opts.aovs = Aov::parse_list( &value( &arg )? )?,
+                "--output" | "-o" => opts.output = Some( PathBuf::from( value( &arg )? ) ),
+                "--compare" => opts.compare = Some( PathBuf::from( value( &arg )? ) ),
+                "--help" | "-h" => opts.help = true,
+                _ => return Err( format!( "unknown option '{}'", arg ) ),
+            }
+        }
+
+        if opts.tile_size == Some( 0 ) {
+            return Err( "tile size must be at least 1".to_string() );
+        }
+        if opts.width == Some( 0 ) || opts.height == Some( 0 ) {
+            return Err( "image size must be at least 1x1".to_string() );
+        }
+        if opts.threads == 0 {
+            return Err( "need at least one thread".to_string() );
+        }
+        if opts.env.is_some() && opts.sun.is_some() {
+            return Err( "--env and --sun both set the sky, pick one".to_string() );
+        }
+        if opts.compare.is_some() && opts.output.is_none() {
+            return Err( "--compare only works when rendering to --output".to_string() );
+        }
+
+        Ok( opts )
+    }
+}
+
+fn parse_num<T : std::str::FromStr>( name : &str, value : &str ) -> Result<T, String> {
+    value.parse().map_err( |_| format!( "bad value '{}' for {}", value, name ) )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse( args : &[&str] ) -> Result<Options, String> {
+        Options::parse( args.iter().map( |s| s.to_string() ) )
+    }
+
+    #[test]
+    fn options() {
+        let opts = parse( &[ "--width", "64", "--spp", "4", "--seed", "7", "--tonemap", "AgX", "-o", "out.png" ] ).unwrap();
+        assert_eq!( opts.width, Some( 64 ) );
+        assert_eq!( opts.height, None );
+        assert_eq!( opts.spp, Some( 4 ) );
+        assert_eq!( opts.seed, Some( 7 ) );
+        assert_eq!( opts.tone_map, ToneMap::AgX );
+        assert_eq!( opts.output, Some( PathBuf::from( "out.png" ) ) );
+
+        assert!( parse( &[ "--width" ] ).is_err() );
+        assert!( parse( &[ "--width", "abc" ] ).is_err() );
+        assert!( parse( &[ "--threads", "0" ] ).is_err() );
+        assert_eq!( parse( &[ "--tile-order", "hilbert" ] ).unwrap().tile_order, Some( TileOrder::Hilbert ) );
+        assert!( parse( &[ "--tile-order", "zigzag" ] ).is_err() );
+        assert!( parse( &[ "--tile-size", "0" ] ).is_err() );
+        assert_eq!( parse( &[ "--aov", "depth,object_id" ] ).unwrap().aovs, vec![ Aov::Depth, Aov::ObjectId ] );
+        assert!( parse( &[ "--aov", "shadow" ] ).is_err() );
+        assert_eq!( parse( &[ "--exr-pixel", "float" ] ).unwrap().exr_pixel, ExrPixel::Float );
+        assert!( parse( &[ "--exr-pixel", "double" ] ).is_err() );
+        assert!( parse( &[ "--no-sky" ] ).unwrap().no_sky );
+        assert!( parse( &[ "--brute-force" ] ).unwrap().brute_force );
+        assert!( parse( &[ "--uniform-lights" ] ).unwrap().uniform_lights );
+        assert_eq!( parse( &[ "--env", "sky.hdr" ] ).unwrap().env, Some( PathBuf::from( "sky.hdr" ) ) );
+        assert_eq!( parse( &[ "--sun", "30, 120" ] ).unwrap().sun, Some( (30.0, 120.0) ) );
+        assert_eq!( parse( &[ "--sun", "15" ] ).unwrap().sun, Some( (15.0, 0.0) ) );
+        assert!( parse( &[ "--sun", "high,noon" ] ).is_err() );
+        assert!( parse( &[ "--env", "sky.hdr", "--sun", "30" ] ).is_err() );
+        assert_eq!( parse( &[ "--compare", "ref.pfm", "-o", "out.pfm" ] ).unwrap().compare, Some( PathBuf::from( "ref.pfm" ) ) );
+        assert!( parse( &[ "--compare", "ref.pfm" ] ).is_err() );
+        assert!( parse( &[ "--bogus" ] ).is_err() );
+    }
+}
@@ -0,0 +1,295 @@
+// Edge-aware à-trous wavelet denoiser
+//
+// Repeatedly blurs with a 5x5 B-spline kernel whose taps spread out
+// (1, 2, 4, ... pixels apart) each iteration, weighting every tap by how
+// alike the two pixels are: normals, depth, and luminance relative to how
+// noisy the pixels are known to be. Albedo is divided out first and
+// multiplied back at the end, so texture detail isn't blurred as noise.
+//
+// A region can be denoised on its own from the film plus an apron of
+// surrounding pixels; the result is exactly what a full frame pass would
+// give for those pixels.
+
+use vec3::Vec3;
+
+use super::film::Film;
+use super::tonemap::luminance;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DenoiseMode {
+    Off,
+    Final,     // once, after the last pass
+    EveryPass, // after every pass, for cleaner previews
+}
+
+impl DenoiseMode {
+
+    pub const ALL : [DenoiseMode; 3] = [ DenoiseMode::Off, DenoiseMode::Final, DenoiseMode::EveryPass ];
+
+    pub fn name( &self ) -> &'static str {
+        match self {
+            DenoiseMode::Off => "off",
+            DenoiseMode::Final => "final",
+            DenoiseMode::EveryPass => "every-pass",
+        }
+    }
+
+    pub fn from_name( name : &str ) -> Option<DenoiseMode> {
+        DenoiseMode::ALL.iter().copied().find( |m| m.name().eq_ignore_ascii_case( name ) )
+    }
+}
+
+const KERNEL : [f32; 5] = [ 1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0 ];
+const ITERATIONS : u32 = 5;
+
+// edge stopping: luminance differences are measured in standard deviations,
+// normals compared by a power of their dot product, depth relative to distance
+const SIGMA_LUMINANCE : f32 = 4.0;
+const NORMAL_POWER : i32 = 64;
+const SIGMA_DEPTH : f32 = 0.05;
+
+// albedo below this isn't divided out any further
+const MIN_ALBEDO : f32 = 0.01;
+
+// below this many samples the per pixel variance is guessed from the neighbours
+const MIN_VARIANCE_SAMPLES : u32 = 4;
+
+// Pixels around a region that affect its result: the kernel reach of every
+// iteration plus one for its variance blur, and one for the neighbourhood
+// variance at the start
+pub fn apron() -> u32 {
+    2 * ((1 << ITERATIONS) - 1) + ITERATIONS + 1
+}
+
+#[derive(Clone, Copy)]
+struct Guide {
+    normal : Vec3,
+    depth : f32,
+    hit : bool,
+    valid : bool,
+}
+
+// Denoised radiance for the w x h region at (x, y)
+pub fn denoise_region( film : &Film, x : u32, y : u32, w : u32, h : u32 ) -> Vec<Vec3> {
+
+    // the region plus apron, clipped to the image
+    let apron = apron();
+    let (x0, y0) = (x.saturating_sub( apron ), y.saturating_sub( apron ));
+    let (x1, y1) = ((x + w + apron).min( film.width ), (y + h + apron).min( film.height ));
+    let (ww, wh) = ((x1 - x0) as usize, (y1 - y0) as usize);
+
+    let mut guide = Vec::with_capacity( ww * wh );
+    let mut albedo = Vec::with_capacity( ww * wh );
+    let mut color = Vec::with_capacity( ww * wh );
+    let mut variance = Vec::with_capacity( ww * wh );
+    for j in y0..y1 {
+        for i in x0..x1 {
+            let m = film.mean( i, j );
+            let valid = film.samples( i, j ) > 0;
+            let a = m.albedo.max( &Vec3::from_float( MIN_ALBEDO ) );
+            // averaged normals come out short at edges, only the direction matters
+            let normal = if m.normal.length_squared() > 0.0 { m.normal.normalize() } else { m.normal };
+            guide.push( Guide { normal, depth : m.depth, hit : m.depth > 0.0, valid } );
+            albedo.push( a );
+            color.push( m.radiance / a );
+
+            // as seen after dividing out the albedo
+            let scale = luminance( a ).max( MIN_ALBEDO );
+            variance.push( match film.variance( i, j ) {
+                Some(v) if film.samples( i, j ) >= MIN_VARIANCE_SAMPLES => Some( v / (scale * scale) ),
+                _ => None,
+            });
+        }
+    }
+    let mut variance = fill_spatial_variance( &color, &guide, &variance, ww, wh );
+
+    for k in 0..ITERATIONS {
+        let step = 1i64 << k;
+        let mut next_color = color.clone();
+        let mut next_variance = variance.clone();
+
+        for j in 0..wh {
+            for i in 0..ww {
+                let p = j * ww + i;
+                let gp = guide[p];
+                if !gp.valid {
+                    continue;
+                }
+                let lp = luminance( color[p] );
+                let lum_scale = SIGMA_LUMINANCE * blurred_variance( &variance, &guide, i, j, ww, wh ).sqrt() + 1e-4;
+
+                let mut sum = Vec3::ZERO;
+                let mut sum_var = 0.0;
+                let mut sum_w = 0.0;
+                for (ky, hy) in KERNEL.iter().enumerate() {
+                    let qj = j as i64 + (ky as i64 - 2) * step;
+                    if qj < 0 || qj >= wh as i64 {
+                        continue;
+                    }
+                    for (kx, hx) in KERNEL.iter().enumerate() {
+                        let qi = i as i64 + (kx as i64 - 2) * step;
+                        if qi < 0 || qi >= ww as i64 {
+                            continue;
+                        }
+                        let q = qj as usize * ww + qi as usize;
+                        let gq = guide[q];
+                        if !gq.valid || gq.hit != gp.hit {
+                            continue;
+                        }
+
+                        // the center tap always counts in full, so sum_w > 0
+                        let mut wgt = hx * hy;
+                        if q != p {
+                            if gp.hit {
+                                wgt *= Vec3::dot( &gp.normal, &gq.normal ).max( 0.0 ).powi( NORMAL_POWER );
+                                wgt *= (-(gp.depth - gq.depth).abs() / (SIGMA_DEPTH * gp.depth * step as f32 + 1e-4)).exp();
+                            }
+                            wgt *= (-(lp - luminance( color[q] )).abs() / lum_scale).exp();
+                        }
+
+                        sum += wgt * color[q];
+                        sum_var += wgt * wgt * variance[q];
+                        sum_w += wgt;
+                    }
+                }
+
+                next_color[p] = sum / sum_w;
+                next_variance[p] = sum_var / (sum_w * sum_w);
+            }
+        }
+
+        color = next_color;
+        variance = next_variance;
+    }
+
+    // cut the region out of the window and put the albedo back
+    let mut out = Vec::with_capacity( (w * h) as usize );
+    for j in y..(y + h) {
+        for i in x..(x + w) {
+            let p = (j - y0) as usize * ww + (i - x0) as usize;
+            out.push( if guide[p].valid { color[p] * albedo[p] } else { Vec3::ZERO } );
+        }
+    }
+    out
+}
+
+// 3x3 gaussian of the variance around (i, j). A single pixel's estimate is
+// itself noisy, outliers would otherwise keep themselves from being smoothed.
+fn blurred_variance( variance : &[f32], guide : &[Guide], i : usize, j : usize, ww : usize, wh : usize ) -> f32 {
+    const GAUSS : [f32; 2] = [ 0.5, 0.25 ];
+    let (mut sum, mut sum_w) = (0.0, 0.0);
+    for qj in j.saturating_sub( 1 )..(j + 2).min( wh ) {
+        for qi in i.saturating_sub( 1 )..(i + 2).min( ww ) {
+            let q = qj * ww + qi;
+            if guide[q].valid {
+                let w = GAUSS[qi.abs_diff( i )] * GAUSS[qj.abs_diff( j )];
+                sum += w * variance[q];
+                sum_w += w;
+            }
+        }
+    }
+    sum / sum_w
+}
+
+// Where there are too few samples for a per pixel variance, use the
+// variance of the luminance over the 3x3 neighbourhood instead
+fn fill_spatial_variance( color : &[Vec3], guide : &[Guide], variance : &[Option<f32>], ww : usize, wh : usize ) -> Vec<f32> {
+    let mut out = Vec::with_capacity( color.len() );
+    for j in 0..wh {
+        for i in 0..ww {
+            let p = j * ww + i;
+            if let Some(v) = variance[p] {
+                out.push( v );
+                continue;
+            }
+
+            let (mut sum, mut sum_sq, mut n) = (0.0, 0.0, 0.0);
+            for qj in j.saturating_sub( 1 )..(j + 2).min( wh ) {
+                for qi in i.saturating_sub( 1 )..(i + 2).min( ww ) {
+                    let q = qj * ww + qi;
+                    if guide[q].valid {
+                        let l = luminance( color[q] );
+                        sum += l;
+                        sum_sq += l * l;
+                        n += 1.0;
+                    }
+                }
+            }
+            out.push( if n > 1.0 { (sum_sq / n - (sum / n) * (sum / n)).max( 0.0 ) } else { 0.0 } );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::PixelSum;
+
+    // a plane facing the camera, left half dark and right half bright, with
+    // deterministic noise on top
+    fn noisy_film( width : u32, height : u32, spp : u32 ) -> Film {
+        let mut film = Film::new( width, height );
+        let mut state = 12345u32;
+        for _ in 0..spp {
+            let mut sums = Vec::new();
+            for _ in 0..height {
+                for i in 0..width {
+                    state = state.wrapping_mul( 1664525 ).wrapping_add( 1013904223 );
+                    let noise = (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5;
+                    let base = if i < width / 2 { 0.2 } else { 0.8 };
+                    let v = (base + noise * 0.6).max( 0.0 );
+                    let normal = if i < width / 2 { Vec3::new( 0.0, 0.0, 1.0 ) } else { Vec3::new( 1.0, 0.0, 0.0 ) };
+                    sums.push( PixelSum { radiance : Vec3::from_float( v ), lum_sq : v * v,
+                                          albedo : Vec3::ONE, normal, depth : 3.0, ..Default::default() } );
+                }
+            }
+            film.add_tile( 0, 0, width, height, &sums, 1 );
+        }
+        film
+    }
+
+    fn error( film : &Film, img : &[Vec3] ) -> f32 {
+        let mut err = 0.0;
+        for j in 0..film.height {
+            for i in 0..film.width {
+                let truth = if i < film.width / 2 { 0.2 } else { 0.8 };
+                err += (img[(j * film.width + i) as usize].x - truth).powi( 2 );
+            }
+        }
+        err
+    }
+
+    #[test]
+    fn reduces_noise_keeps_edges() {
+        let film = noisy_film( 48, 32, 4 );
+        let noisy : Vec<Vec3> = (0..32).flat_map( |j| (0..48).map( move |i| (i, j) ) ).map( |(i, j)| film.pixel( i, j ) ).collect();
+        let clean = denoise_region( &film, 0, 0, 48, 32 );
+        assert!( error( &film, &clean ) < 0.25 * error( &film, &noisy ) );
+
+        // no bleeding across the normal edge
+        assert!( clean[(16 * 48 + 23) as usize].x < 0.4 );
+        assert!( clean[(16 * 48 + 24) as usize].x > 0.6 );
+    }
+
+    #[test]
+    fn tiles_match_full_frame() {
+        // far enough from the borders that the apron isn't clipped
+        let film = noisy_film( 200, 160, 1 );
+        let full = denoise_region( &film, 0, 0, 200, 160 );
+        let (x, y, w, h) = (80, 70, 16, 20);
+        let tile = denoise_region( &film, x, y, w, h );
+        for j in 0..h {
+            for i in 0..w {
+                assert_eq!( tile[(j * w + i) as usize], full[((y + j) * 200 + x + i) as usize] );
+            }
+        }
+    }
+
+    #[test]
+    fn mode_names() {
+        for mode in DenoiseMode::ALL {
+            assert_eq!( DenoiseMode::from_name( mode.name() ), Some( mode ) );
+        }
+    }
+}
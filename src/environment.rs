@@ -0,0 +1,216 @@
+// Light arriving from infinitely far away, in every direction nothing was hit
+
+use std::f32::consts::PI;
+use std::io;
+
+use vec3::Vec3;
+
+use super::rng::Rng;
+use super::hdr_image::HdrImage;
+use super::tonemap::luminance;
+
+// A background that can also be sampled as a light. Directions don't need
+// to be unit length.
+pub trait Environment : Send + Sync {
+    fn radiance( &self, dir : Vec3 ) -> Vec3;
+
+    // Density of sample() picking dir, per unit solid angle
+    fn pdf( &self, dir : Vec3 ) -> f32;
+
+    // Random direction, favouring the bright parts
+    fn sample( &self, rng : &mut Rng ) -> Vec3;
+}
+
+// Equirectangular (latitude-longitude) HDR image around the scene. The
+// middle of the image is towards -Z, the top row straight up. Directions
+// are sampled per pixel in proportion to its luminance times the solid
+// angle it covers, with a CDF over rows and one over each row's pixels.
+pub struct EnvMap {
+    image : HdrImage,
+
+    // turn about the up axis in radians, and a scale on the light
+    rotation : f32,
+    intensity : f32,
+
+    // running totals of pixel weights within each row, and of whole rows
+    col_cdf : Vec<f32>,
+    row_cdf : Vec<f32>,
+}
+
+impl EnvMap {
+
+    // rotation is in degrees about the up axis, turning the map from -Z
+    // towards +X. An image with no pixels is an error.
+    pub fn new( image : HdrImage, rotation : f32, intensity : f32 ) -> io::Result<EnvMap> {
+        let (w, h) = (image.width as usize, image.height as usize);
+        if w == 0 || h == 0 {
+            return Err( io::Error::new( io::ErrorKind::InvalidData, format!( "empty {}x{} environment map", w, h ) ) );
+        }
+
+        // a black map can't be sampled by brightness, fall back to solid angle
+        let sin_theta = |j : usize| (PI * (j as f32 + 0.5) / h as f32).sin();
+        let mut weights : Vec<f32> = (0..w * h).map( |k| luminance( image.pixels[k] ).max( 0.0 ) * sin_theta( k / w ) ).collect();
+        if !weights.iter().any( |&wt| wt > 0.0 ) {
+            weights = (0..w * h).map( |k| sin_theta( k / w ) ).collect();
+        }
+
+        let mut col_cdf = Vec::with_capacity( w * h );
+        let mut row_cdf = Vec::with_capacity( h );
+        let mut total = 0.0;
+        for row in weights.chunks( w ) {
+            let mut sum = 0.0;
+            for &wt in row {
+                sum += wt;
+                col_cdf.push( sum );
+            }
+            total += sum;
+            row_cdf.push( total );
+        }
+
+        Ok( EnvMap { image, rotation : rotation.to_radians(), intensity, col_cdf, row_cdf } )
+    }
+
+    fn width( &self ) -> usize {
+        self.image.width as usize
+    }
+
+    fn height( &self ) -> usize {
+        self.image.height as usize
+    }
+
+    // (u, v) in 0..1 for a unit direction, v = 0 straight up
+    fn dir_to_uv( &self, dir : Vec3 ) -> (f32, f32) {
+        let phi = dir.x.atan2( -dir.z ) - self.rotation;
+        let theta = dir.y.clamp( -1.0, 1.0 ).acos();
+        ((phi / (2.0 * PI) + 0.5).rem_euclid( 1.0 ), theta / PI)
+    }
+
+    fn uv_to_dir( &self, u : f32, v : f32 ) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation;
+        let theta = v * PI;
+        Vec3::new( theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos() )
+    }
+
+    fn pixel_at( &self, u : f32, v : f32 ) -> (usize, usize) {
+        let i = ((u * self.width() as f32) as usize).min( self.width() - 1 );
+        let j = ((v * self.height() as f32) as usize).min( self.height() - 1 );
+        (i, j)
+    }
+
+    // chance of picking pixel (i, j)
+    fn pixel_probability( &self, i : usize, j : usize ) -> f32 {
+        let k = j * self.width() + i;
+        let weight = if i == 0 { self.col_cdf[k] } else { self.col_cdf[k] - self.col_cdf[k - 1] };
+        weight / self.row_cdf[self.height() - 1]
+    }
+}
+
+impl Environment for EnvMap {
+    fn radiance( &self, dir : Vec3 ) -> Vec3 {
+        let (u, v) = self.dir_to_uv( dir.normalize() );
+        let (i, j) = self.pixel_at( u, v );
+        self.intensity * self.image.pixel( i as u32, j as u32 )
+    }
+
+    // each pixel is spread evenly over its patch of (u, v), which covers
+    // 2 pi^2 sin(theta) of solid angle per unit area
+    fn pdf( &self, dir : Vec3 ) -> f32 {
+        let dir = dir.normalize();
+        let sin_theta = (1.0 - dir.y * dir.y).max( 0.0 ).sqrt();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (u, v) = self.dir_to_uv( dir );
+        let (i, j) = self.pixel_at( u, v );
+        let num_pixels = (self.width() * self.height()) as f32;
+        self.pixel_probability( i, j ) * num_pixels / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn sample( &self, rng : &mut Rng ) -> Vec3 {
+        let (w, h) = (self.width(), self.height());
+
+        let target = rng.next_f32() * self.row_cdf[h - 1];
+        let j = self.row_cdf.partition_point( |&c| c <= target ).min( h - 1 );
+        let row = &self.col_cdf[j * w..(j + 1) * w];
+        let target = rng.next_f32() * row[w - 1];
+        let i = row.partition_point( |&c| c <= target ).min( w - 1 );
+
+        let u = (i as f32 + rng.next_f32()) / w as f32;
+        let v = (j as f32 + rng.next_f32()) / h as f32;
+        self.uv_to_dir( u, v )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // dim, with one bright pixel left of centre a little above the horizon
+    fn spot_map( rotation : f32 ) -> EnvMap {
+        let mut image = HdrImage::new( 16, 8 );
+        for (k, p) in image.pixels.iter_mut().enumerate() {
+            *p = Vec3::from_float( 0.01 * (k % 5) as f32 );
+        }
+        image.pixels[3 * 16 + 6] = Vec3::new( 500.0, 400.0, 300.0 );
+        EnvMap::new( image, rotation, 2.0 ).unwrap()
+    }
+
+    #[test]
+    fn directions_and_rotation() {
+        let map = EnvMap::new( HdrImage::new( 4, 2 ), 0.0, 1.0 ).unwrap();
+        let (u, v) = map.dir_to_uv( Vec3::new( 0.0, 0.0, -1.0 ) );
+        assert!( (u - 0.5).abs() < 1e-6 && (v - 0.5).abs() < 1e-6 );
+        for dir in [ Vec3::new( 1.0, 0.2, 0.0 ), Vec3::new( -0.3, -0.8, 0.5 ), Vec3::new( 0.1, 0.3, 0.9 ) ] {
+            let dir = dir.normalize();
+            let (u, v) = map.dir_to_uv( dir );
+            assert!( (map.uv_to_dir( u, v ) - dir).length() < 1e-5 );
+        }
+
+        // turning the map turns what's seen the same way
+        let (plain, turned) = (spot_map( 0.0 ), spot_map( 90.0 ));
+        let mut rng = Rng::new( 2, 0 );
+        for _ in 0..100 {
+            let dir = rng.unit_vector();
+            let dir_turned = Vec3::new( dir.z, dir.y, -dir.x );
+            assert_eq!( turned.radiance( dir ), plain.radiance( dir_turned ) );
+        }
+        assert_eq!( plain.radiance( plain.uv_to_dir( 6.5 / 16.0, 3.5 / 8.0 ) ), Vec3::new( 1000.0, 800.0, 600.0 ) );
+    }
+
+    #[test]
+    fn empty_map() {
+        for (w, h) in [ (0, 0), (0, 4), (4, 0) ] {
+            let err = EnvMap::new( HdrImage::new( w, h ), 0.0, 1.0 ).err().unwrap();
+            assert_eq!( err.to_string(), format!( "empty {}x{} environment map", w, h ) );
+        }
+    }
+
+    #[test]
+    fn importance_sampling() {
+        let map = spot_map( 30.0 );
+        let mut rng = Rng::new( 4, 0 );
+
+        // nearly every sample goes to the bright pixel, and could have been picked
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let dir = map.sample( &mut rng );
+            assert!( map.pdf( dir ) > 0.0 );
+            if map.radiance( dir ).x > 100.0 {
+                bright += 1;
+            }
+        }
+        assert!( bright > 950, "{}", bright );
+
+        // the pdf integrates to 1 over the sphere
+        let n = 200000;
+        let sum : f32 = (0..n).map( |_| map.pdf( rng.unit_vector() ) ).sum();
+        let integral = sum / n as f32 * 4.0 * PI;
+        assert!( (integral - 1.0).abs() < 0.05, "{}", integral );
+
+        // black maps fall back to uniform, about 1/(4 pi) everywhere
+        let black = EnvMap::new( HdrImage::new( 64, 32 ), 0.0, 1.0 ).unwrap();
+        let pdf = black.pdf( Vec3::new( 0.3, 0.1, -0.7 ) );
+        assert!( (pdf * 4.0 * PI - 1.0).abs() < 0.02, "{}", pdf );
+        assert_eq!( black.radiance( black.sample( &mut rng ) ), Vec3::ZERO );
+    }
+}
@@ -0,0 +1,299 @@
+// OpenEXR output, written by hand like the other formats: a single part
+// scanline file, uncompressed, one scanline per block.
+//
+// The beauty goes in the unprefixed R, G, B channels so any viewer shows it;
+// every other AOV is a layer of its own, "<aov>.<channel>". Light and albedo
+// are half or float as asked, the other data passes are always stored
+// exactly: depth and normals as float, ids and sample counts as uint.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::aov::Aov;
+use super::film::Film;
+
+const MAGIC : [u8; 4] = [ 0x76, 0x2f, 0x31, 0x01 ];
+const VERSION : u32 = 2; // single part scanline, no flags
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExrPixel {
+    Half,
+    Float,
+}
+
+impl ExrPixel {
+
+    pub const ALL : [ExrPixel; 2] = [ ExrPixel::Half, ExrPixel::Float ];
+
+    pub fn name( &self ) -> &'static str {
+        match self {
+            ExrPixel::Half => "half",
+            ExrPixel::Float => "float",
+        }
+    }
+
+    pub fn from_name( name : &str ) -> Option<ExrPixel> {
+        ExrPixel::ALL.iter().copied().find( |p| p.name().eq_ignore_ascii_case( name ) )
+    }
+}
+
+// channel pixel types as numbered in the file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ChannelType {
+    Uint = 0,
+    Half = 1,
+    Float = 2,
+}
+
+impl ChannelType {
+    fn size( &self ) -> usize {
+        match self {
+            ChannelType::Half => 2,
+            ChannelType::Uint | ChannelType::Float => 4,
+        }
+    }
+}
+
+struct Channel {
+    name : String,
+    kind : ChannelType,
+    aov : Aov,
+    component : usize,
+}
+
+// Channel names and types for one AOV
+fn aov_channels( aov : Aov, pixel : ExrPixel ) -> Vec<Channel> {
+    let color = match pixel {
+        ExrPixel::Half => ChannelType::Half,
+        ExrPixel::Float => ChannelType::Float,
+    };
+    let (names, kind) : (&[&str], ChannelType) = match aov {
+        Aov::Depth => (&[ "Z" ], ChannelType::Float),
+        Aov::Normal => (&[ "X", "Y", "Z" ], ChannelType::Float),
+        Aov::ObjectId | Aov::MaterialId => (&[ "id" ], ChannelType::Uint),
+        Aov::SampleCount => (&[ "count" ], ChannelType::Uint),
+        _ => (&[ "R", "G", "B" ], color),
+    };
+    names.iter().enumerate().map( |(component, n)| Channel {
+        name : if aov == Aov::Beauty { n.to_string() } else { format!( "{}.{}", aov.name(), n ) },
+        kind,
+        aov,
+        component,
+    }).collect()
+}
+
+// Writes the beauty plus the given AOVs. attributes go into the header as
+// strings, for render stats and the like.
+pub fn write_exr<W : Write>( out : &mut W, film : &Film, aovs : &[Aov], pixel : ExrPixel,
+                             attributes : &[(String, String)] ) -> io::Result<()> {
+    let mut layers = vec![ Aov::Beauty ];
+    for aov in aovs {
+        if !layers.contains( aov ) {
+            layers.push( *aov );
+        }
+    }
+
+    // channels have to be listed, and stored, sorted by name
+    let mut channels : Vec<Channel> = layers.iter().flat_map( |aov| aov_channels( *aov, pixel ) ).collect();
+    channels.sort_by( |a, b| a.name.cmp( &b.name ) );
+
+    let (width, height) = (film.width, film.height);
+    let mut header = Vec::new();
+    header.extend_from_slice( &MAGIC );
+    header.extend_from_slice( &VERSION.to_le_bytes() );
+
+    let mut chlist = Vec::new();
+    for ch in &channels {
+        chlist.extend_from_slice( ch.name.as_bytes() );
+        chlist.push( 0 );
+        chlist.extend_from_slice( &(ch.kind as i32).to_le_bytes() );
+        chlist.extend_from_slice( &[ 0, 0, 0, 0 ] ); // pLinear, reserved
+        chlist.extend_from_slice( &1i32.to_le_bytes() ); // x and y sampling
+        chlist.extend_from_slice( &1i32.to_le_bytes() );
+    }
+    chlist.push( 0 );
+    write_attribute( &mut header, "channels", "chlist", &chlist );
+    write_attribute( &mut header, "compression", "compression", &[ 0 ] );
+
+    // both windows cover the whole image
+    let window : Vec<u8> = [ 0, 0, width as i32 - 1, height as i32 - 1 ].iter().flat_map( |v| v.to_le_bytes() ).collect();
+    write_attribute( &mut header, "dataWindow", "box2i", &window );
+    write_attribute( &mut header, "displayWindow", "box2i", &window );
+    write_attribute( &mut header, "lineOrder", "lineOrder", &[ 0 ] ); // increasing y
+    write_attribute( &mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes() );
+    write_attribute( &mut header, "screenWindowCenter", "v2f", &[ 0u8; 8 ] );
+    write_attribute( &mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes() );
+    for (name, value) in attributes {
+        write_attribute( &mut header, name, "string", value.as_bytes() );
+    }
+    header.push( 0 );
+
+    // offset table, one entry per scanline, then the scanlines
+    let line_size : usize = channels.iter().map( |ch| ch.kind.size() * width as usize ).sum();
+    let block_size = 8 + line_size;
+    let first_block = header.len() + 8 * height as usize;
+    for y in 0..height as usize {
+        header.extend_from_slice( &((first_block + y * block_size) as u64).to_le_bytes() );
+    }
+    out.write_all( &header )?;
+
+    let mut line = Vec::with_capacity( block_size );
+    for y in 0..height {
+        line.clear();
+        line.extend_from_slice( &(y as i32).to_le_bytes() );
+        line.extend_from_slice( &(line_size as i32).to_le_bytes() );
+        for ch in &channels {
+            for x in 0..width {
+                let v = ch.aov.value( film, x, y );
+                let v = [ v.x, v.y, v.z ][ch.component];
+                match ch.kind {
+                    ChannelType::Uint => line.extend_from_slice( &(v as u32).to_le_bytes() ),
+                    ChannelType::Half => line.extend_from_slice( &f32_to_half( v ).to_le_bytes() ),
+                    ChannelType::Float => line.extend_from_slice( &v.to_le_bytes() ),
+                }
+            }
+        }
+        out.write_all( &line )?;
+    }
+    Ok( () )
+}
+
+pub fn write_exr_file( path : &Path, film : &Film, aovs : &[Aov], pixel : ExrPixel,
+                       attributes : &[(String, String)] ) -> io::Result<()> {
+    let mut out = BufWriter::new( File::create( path )? );
+    write_exr( &mut out, film, aovs, pixel, attributes )?;
+    out.flush()
+}
+
+fn write_attribute( out : &mut Vec<u8>, name : &str, kind : &str, value : &[u8] ) {
+    out.extend_from_slice( name.as_bytes() );
+    out.push( 0 );
+    out.extend_from_slice( kind.as_bytes() );
+    out.push( 0 );
+    out.extend_from_slice( &(value.len() as i32).to_le_bytes() );
+    out.extend_from_slice( value );
+}
+
+// IEEE half, rounded to nearest even. Too big becomes infinity, too small zero.
+pub fn f32_to_half( f : f32 ) -> u16 {
+    let bits = f.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let man = bits & 0x7f_ffff;
+
+    if exp == 0xff {
+        // infinity stays infinity, NaN stays NaN
+        return sign | 0x7c00 | if man != 0 { 0x200 } else { 0 };
+    }
+
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if e <= 0 {
+        // subnormal half, or zero
+        if e < -10 {
+            return sign;
+        }
+        let m = man | 0x80_0000;
+        let shift = (14 - e) as u32;
+        let half = m >> shift;
+        let rest = m & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = rest > halfway || (rest == halfway && half & 1 == 1);
+        return sign | (half + u32::from( round )) as u16;
+    }
+
+    // a carry out of the mantissa correctly bumps the exponent
+    let half = ((e as u32) << 10) | (man >> 13);
+    let rest = man & 0x1fff;
+    let round = rest > 0x1000 || (rest == 0x1000 && half & 1 == 1);
+    sign | (half + u32::from( round )) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::PixelSum;
+    use vec3::Vec3;
+
+    #[test]
+    fn half_conversion() {
+        assert_eq!( f32_to_half( 0.0 ), 0x0000 );
+        assert_eq!( f32_to_half( -0.0 ), 0x8000 );
+        assert_eq!( f32_to_half( 1.0 ), 0x3c00 );
+        assert_eq!( f32_to_half( 0.5 ), 0x3800 );
+        assert_eq!( f32_to_half( -2.0 ), 0xc000 );
+        assert_eq!( f32_to_half( 65504.0 ), 0x7bff );
+        assert_eq!( f32_to_half( 1e6 ), 0x7c00 );
+        assert_eq!( f32_to_half( 2f32.powi( -24 ) ), 0x0001 );
+        assert_eq!( f32_to_half( 2f32.powi( -26 ) ), 0x0000 );
+        assert_eq!( f32_to_half( 1.0 + 2f32.powi( -11 ) ), 0x3c00 ); // tie, to even
+        assert_eq!( f32_to_half( 1.0 + 3.0 * 2f32.powi( -11 ) ), 0x3c02 );
+        assert_eq!( f32_to_half( f32::NAN ) & 0x7c00, 0x7c00 );
+    }
+
+    fn read_i32( data : &[u8], at : usize ) -> i32 {
+        i32::from_le_bytes( data[at..at + 4].try_into().unwrap() )
+    }
+
+    fn read_cstr( data : &[u8], at : &mut usize ) -> String {
+        let end = *at + data[*at..].iter().position( |&b| b == 0 ).unwrap();
+        let s = String::from_utf8( data[*at..end].to_vec() ).unwrap();
+        *at = end + 1;
+        s
+    }
+
+    #[test]
+    fn file_layout() {
+        let mut film = Film::new( 3, 2 );
+        let sums : Vec<PixelSum> = (0..6).map( |i| PixelSum {
+            radiance : Vec3::new( i as f32, 0.5, 2.0 ), depth : 4.0, object_id : 7, ..Default::default()
+        }).collect();
+        film.add_tile( 0, 0, 3, 2, &sums, 1 );
+
+        let mut data = Vec::new();
+        let stats = [ ( "samplesPerPixel".to_string(), "1".to_string() ) ];
+        write_exr( &mut data, &film, &[ Aov::Depth, Aov::ObjectId ], ExrPixel::Half, &stats ).unwrap();
+        assert_eq!( data[0..4], MAGIC );
+        assert_eq!( read_i32( &data, 4 ), 2 );
+
+        // attributes up to the empty name
+        let mut at = 8;
+        let mut attrs = Vec::new();
+        loop {
+            let name = read_cstr( &data, &mut at );
+            if name.is_empty() {
+                break;
+            }
+            let kind = read_cstr( &data, &mut at );
+            let size = read_i32( &data, at ) as usize;
+            attrs.push( (name, kind, data[at + 4..at + 4 + size].to_vec()) );
+            at += 4 + size;
+        }
+        let attr = |name : &str| attrs.iter().find( |a| a.0 == name ).unwrap().2.clone();
+        assert_eq!( attr( "samplesPerPixel" ), b"1" );
+        assert_eq!( attr( "dataWindow" ), [ 0, 0, 2, 1 ].iter().flat_map( |v : &i32| v.to_le_bytes() ).collect::<Vec<u8>>() );
+
+        let chlist = attr( "channels" );
+        let mut names = Vec::new();
+        let mut c = 0;
+        while chlist[c] != 0 {
+            names.push( read_cstr( &chlist, &mut c ) );
+            c += 16;
+        }
+        assert_eq!( names, [ "B", "G", "R", "depth.Z", "object_id.id" ] );
+
+        // second scanline: B G R as half, then depth float, then id uint
+        let offset = u64::from_le_bytes( data[at + 8..at + 16].try_into().unwrap() ) as usize;
+        assert_eq!( read_i32( &data, offset ), 1 );
+        assert_eq!( read_i32( &data, offset + 4 ) as usize, 3 * (2 + 2 + 2 + 4 + 4) );
+        let line = &data[offset + 8..];
+        let r_first = u16::from_le_bytes( [ line[12], line[13] ] );
+        assert_eq!( r_first, f32_to_half( 3.0 ) );
+        assert_eq!( f32::from_le_bytes( line[18..22].try_into().unwrap() ), 4.0 );
+        assert_eq!( read_i32( line, 30 ), 7 );
+        assert_eq!( offset + 8 + 42, data.len() );
+    }
+}
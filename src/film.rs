@@ -0,0 +1,240 @@
+use slint::SharedPixelBuffer;
+use slint::Rgb8Pixel;
+
+use vec3::Vec3;
+
+use std::ops::AddAssign;
+
+use super::tonemap::{luminance, DisplayTransform};
+use super::aov::Aov;
+
+// Everything a pixel accumulates, summed over its samples. Besides the
+// radiance this keeps what the denoiser needs: the squared luminance for
+// the variance, and what the camera ray hit first. The rest are the AOVs,
+// see aov.rs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PixelSum {
+    pub radiance : Vec3,
+    pub lum_sq : f32,
+
+    // first hit; where the camera ray missed the albedo is one, the rest zero
+    pub albedo : Vec3,
+    pub normal : Vec3,
+    pub depth : f32,
+
+    // radiance split up, these add up to it
+    pub diffuse_direct : Vec3,
+    pub diffuse_indirect : Vec3,
+    pub specular_direct : Vec3,
+    pub specular_indirect : Vec3,
+    pub emission : Vec3,
+
+    // ids aren't averaged, the first sample that hit something sets them
+    pub object_id : u32,
+    pub material_id : u32,
+}
+
+impl AddAssign for PixelSum {
+    fn add_assign( &mut self, other : PixelSum ) {
+        self.radiance += other.radiance;
+        self.lum_sq += other.lum_sq;
+        self.albedo += other.albedo;
+        self.normal += other.normal;
+        self.depth += other.depth;
+        self.diffuse_direct += other.diffuse_direct;
+        self.diffuse_indirect += other.diffuse_indirect;
+        self.specular_direct += other.specular_direct;
+        self.specular_indirect += other.specular_indirect;
+        self.emission += other.emission;
+        if self.object_id == 0 {
+            self.object_id = other.object_id;
+            self.material_id = other.material_id;
+        }
+    }
+}
+
+impl PixelSum {
+    fn scaled( &self, s : f32 ) -> PixelSum {
+        PixelSum {
+            radiance : self.radiance * s,
+            lum_sq : self.lum_sq * s,
+            albedo : self.albedo * s,
+            normal : self.normal * s,
+            depth : self.depth * s,
+            diffuse_direct : self.diffuse_direct * s,
+            diffuse_indirect : self.diffuse_indirect * s,
+            specular_direct : self.specular_direct * s,
+            specular_indirect : self.specular_indirect * s,
+            emission : self.emission * s,
+            object_id : self.object_id,
+            material_id : self.material_id,
+        }
+    }
+}
+
+// Floating point accumulation buffer. Holds the running sums and the number
+// of samples for each pixel, so passes can keep adding samples and the
+// average is always available. A denoised version of the image can be
+// stored alongside, and is shown in place of the average once there.
+#[derive(Clone)]
+pub struct Film {
+    pub width : u32,
+    pub height : u32,
+    sum : Vec<PixelSum>,
+    count : Vec<u32>,
+    denoised : Option<Vec<Vec3>>,
+}
+
+impl Film {
+
+    pub fn new( width : u32, height : u32 ) -> Film {
+        let n = (width * height) as usize;
+        Film {
+            width,
+            height,
+            sum : vec![ PixelSum::default(); n ],
+            count : vec![ 0; n ],
+            denoised : None,
+        }
+    }
+
+    pub fn clear( &mut self ) {
+        self.sum.fill( PixelSum::default() );
+        self.count.fill( 0 );
+        self.denoised = None;
+    }
+
+    // Adds a block of sums, each pixel the sum of spp samples
+    pub fn add_tile( &mut self, x : u32, y : u32, w : u32, h : u32, sums : &[PixelSum], spp : u32 ) {
+        for j in 0..h {
+            for i in 0..w {
+                let ndx = ((y + j) * self.width + (x + i)) as usize;
+                self.sum[ndx] += sums[(j * w + i) as usize];
+                self.count[ndx] += spp;
+            }
+        }
+    }
+
+    // Mean radiance at a pixel, black if it has no samples yet
+    pub fn pixel( &self, x : u32, y : u32 ) -> Vec3 {
+        self.mean( x, y ).radiance
+    }
+
+    // Mean of everything accumulated at a pixel
+    pub fn mean( &self, x : u32, y : u32 ) -> PixelSum {
+        let ndx = (y * self.width + x) as usize;
+        match self.count[ndx] {
+            0 => PixelSum::default(),
+            n => self.sum[ndx].scaled( 1.0 / n as f32 ),
+        }
+    }
+
+    // Variance of the mean luminance, how far it's likely off. Needs at
+    // least two samples to say anything, None before that.
+    pub fn variance( &self, x : u32, y : u32 ) -> Option<f32> {
+        let ndx = (y * self.width + x) as usize;
+        let n = self.count[ndx];
+        if n < 2 {
+            return None;
+        }
+        let mean = luminance( self.sum[ndx].radiance ) / n as f32;
+        let sample_var = (self.sum[ndx].lum_sq - n as f32 * mean * mean).max( 0.0 ) / (n - 1) as f32;
+        Some( sample_var / n as f32 )
+    }
+
+    pub fn samples( &self, x : u32, y : u32 ) -> u32 {
+        self.count[(y * self.width + x) as usize]
+    }
+
+    // Most samples any pixel has
+    pub fn max_samples( &self ) -> u32 {
+        self.count.iter().copied().max().unwrap_or( 0 )
+    }
+
+    // Stores denoised radiance for a region. Until a region gets some, it
+    // shows the plain average.
+    pub fn set_denoised( &mut self, x : u32, y : u32, w : u32, h : u32, radiance : &[Vec3] ) {
+        if self.denoised.is_none() {
+            let all = (0..self.height).flat_map( |j| (0..self.width).map( move |i| (i, j) ) );
+            self.denoised = Some( all.map( |(i, j)| self.pixel( i, j ) ).collect() );
+        }
+        let width = self.width;
+        let denoised = self.denoised.as_mut().unwrap();
+        for j in 0..h {
+            for i in 0..w {
+                denoised[((y + j) * width + (x + i)) as usize] = radiance[(j * w + i) as usize];
+            }
+        }
+    }
+
+    pub fn is_denoised( &self ) -> bool {
+        self.denoised.is_some()
+    }
+
+    // What to show for a pixel: denoised if there is one, else the mean
+    pub fn output( &self, x : u32, y : u32 ) -> Vec3 {
+        match &self.denoised {
+            Some(denoised) => denoised[(y * self.width + x) as usize],
+            None => self.pixel( x, y ),
+        }
+    }
+
+    // Writes the region of one AOV, as displayed, into an 8-bit buffer of the same size as the film
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_pixel_buffer( &self, buffer : &mut SharedPixelBuffer<Rgb8Pixel>,
+                                x : u32, y : u32, w : u32, h : u32, aov : Aov, xform : &DisplayTransform ) {
+        let stride = buffer.width();
+        let bytes = buffer.make_mut_bytes();
+        for j in y..(y + h) {
+            for i in x..(x + w) {
+                let ndx = ((j * stride + i) * 3) as usize;
+                bytes[ndx..ndx+3].copy_from_slice( &aov.display( self, i, j, xform ) );
+            }
+        }
+    }
+
+    pub fn to_pixel_buffer( &self, aov : Aov, xform : &DisplayTransform ) -> SharedPixelBuffer<Rgb8Pixel> {
+        let mut buffer = SharedPixelBuffer::<Rgb8Pixel>::new( self.width, self.height );
+        self.update_pixel_buffer( &mut buffer, 0, 0, self.width, self.height, aov, xform );
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample( v : f32 ) -> PixelSum {
+        PixelSum { radiance : Vec3::from_float( v ), lum_sq : v * v, depth : 2.0, ..Default::default() }
+    }
+
+    #[test]
+    fn mean_and_variance() {
+        let mut film = Film::new( 2, 1 );
+        film.add_tile( 0, 0, 1, 1, &[ sample( 1.0 ) ], 1 );
+        assert_eq!( film.variance( 0, 0 ), None );
+
+        film.add_tile( 0, 0, 1, 1, &[ sample( 3.0 ) ], 1 );
+        assert_eq!( film.pixel( 0, 0 ), Vec3::from_float( 2.0 ) );
+        assert_eq!( film.mean( 0, 0 ).depth, 2.0 );
+
+        // samples 1 and 3: sample variance 2, of the mean 1
+        assert!( (film.variance( 0, 0 ).unwrap() - 1.0).abs() < 1e-5 );
+        assert_eq!( film.pixel( 1, 0 ), Vec3::ZERO );
+    }
+
+    #[test]
+    fn denoised_replaces_output() {
+        let mut film = Film::new( 2, 1 );
+        film.add_tile( 0, 0, 2, 1, &[ sample( 1.0 ), sample( 2.0 ) ], 1 );
+        assert!( !film.is_denoised() );
+
+        film.set_denoised( 1, 0, 1, 1, &[ Vec3::from_float( 5.0 ) ] );
+        assert_eq!( film.output( 0, 0 ), Vec3::from_float( 1.0 ) );
+        assert_eq!( film.output( 1, 0 ), Vec3::from_float( 5.0 ) );
+        assert_eq!( film.pixel( 1, 0 ), Vec3::from_float( 2.0 ) );
+
+        film.clear();
+        assert!( !film.is_denoised() );
+    }
+}
@@ -0,0 +1,462 @@
+// Floating point images in the formats the lighting tools exchange:
+// Radiance RGBE (.hdr) and portable float maps (.pfm). Written by hand like
+// the other formats. Used to save the film without clamping it to 8 bits,
+// and to load environment maps.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use vec3::Vec3;
+
+use super::aov::Aov;
+use super::film::Film;
+
+// Linear RGB, rows top to bottom
+#[derive(Clone, Debug, PartialEq)]
+pub struct HdrImage {
+    pub width : u32,
+    pub height : u32,
+    pub pixels : Vec<Vec3>,
+}
+
+impl HdrImage {
+
+    pub fn new( width : u32, height : u32 ) -> HdrImage {
+        HdrImage { width, height, pixels : vec![ Vec3::ZERO; width as usize * height as usize ] }
+    }
+
+    // Raw values of one AOV, no display transform
+    pub fn from_film( film : &Film, aov : Aov ) -> HdrImage {
+        let pixels = (0..film.height).flat_map( |j| (0..film.width).map( move |i| (i, j) ) )
+            .map( |(i, j)| aov.value( film, i, j ) )
+            .collect();
+        HdrImage { width : film.width, height : film.height, pixels }
+    }
+
+    pub fn pixel( &self, x : u32, y : u32 ) -> Vec3 {
+        self.pixels[y as usize * self.width as usize + x as usize]
+    }
+
+    // Mean squared error against a reference of the same size, over all
+    // pixels and channels. None if the sizes differ.
+    pub fn mse( &self, reference : &HdrImage ) -> Option<f64> {
+        if (self.width, self.height) != (reference.width, reference.height) || self.pixels.is_empty() {
+            return None;
+        }
+        let sum : f64 = self.pixels.iter().zip( &reference.pixels )
+            .map( |(a, b)| {
+                let d = *a - *b;
+                (d.x as f64).powi( 2 ) + (d.y as f64).powi( 2 ) + (d.z as f64).powi( 2 )
+            })
+            .sum();
+        Some( sum / (3 * self.pixels.len()) as f64 )
+    }
+}
+
+fn invalid( msg : String ) -> io::Error {
+    io::Error::new( io::ErrorKind::InvalidData, msg )
+}
+
+// Reads a .hdr or .pfm, picked by the extension
+pub fn load( path : &Path ) -> io::Result<HdrImage> {
+    let ext = path.extension().and_then( |e| e.to_str() ).unwrap_or( "" ).to_ascii_lowercase();
+    let mut data = Vec::new();
+    File::open( path )?.read_to_end( &mut data )?;
+    match ext.as_str() {
+        "hdr" | "rgbe" => read_hdr( &data ),
+        "pfm" => read_pfm( &data ),
+        _ => Err( io::Error::new( io::ErrorKind::InvalidInput, format!( "don't know how to read '.{}' files", ext ) ) ),
+    }
+}
+
+// Writes a .hdr or .pfm, picked by the extension
+pub fn save( path : &Path, img : &HdrImage ) -> io::Result<()> {
+    let ext = path.extension().and_then( |e| e.to_str() ).unwrap_or( "" ).to_ascii_lowercase();
+    let mut out = BufWriter::new( File::create( path )? );
+    match ext.as_str() {
+        "hdr" | "rgbe" => write_hdr( &mut out, img )?,
+        "pfm" => write_pfm( &mut out, img )?,
+        _ => return Err( io::Error::new( io::ErrorKind::InvalidInput,
+                            format!( "don't know how to write '.{}' files", ext ) ) ),
+    }
+    out.flush()
+}
+
+// --- Radiance RGBE ---
+
+// Shared 8-bit mantissas and an exponent. Anything too dark is black.
+fn to_rgbe( c : Vec3 ) -> [u8; 4] {
+    let v = c.x.max( c.y ).max( c.z );
+    if v.is_nan() || v < 1e-32 {
+        return [ 0, 0, 0, 0 ];
+    }
+    let v = v.min( f32::MAX );
+
+    // v = m * 2^e with m in [0.5, 1)
+    let e = (((v.to_bits() >> 23) & 0xff) as i32 - 126).min( 127 );
+    let scale = 256.0 / 2f32.powi( e );
+    let byte = |x : f32| (x.max( 0.0 ) * scale).min( 255.0 ) as u8;
+    [ byte( c.x ), byte( c.y ), byte( c.z ), (e + 128) as u8 ]
+}
+
+// The middle of the interval each mantissa stands for
+fn from_rgbe( rgbe : [u8; 4] ) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3::ZERO;
+    }
+    let f = 2f32.powi( rgbe[3] as i32 - (128 + 8) );
+    Vec3::new( (rgbe[0] as f32 + 0.5) * f, (rgbe[1] as f32 + 0.5) * f, (rgbe[2] as f32 + 0.5) * f )
+}
+
+// Run length encoded scanlines, each component on its own
+pub fn write_hdr<W : Write>( out : &mut W, img : &HdrImage ) -> io::Result<()> {
+    write!( out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", img.height, img.width )?;
+
+    let width = img.width as usize;
+    let mut comp = Vec::with_capacity( width );
+    for row in img.pixels.chunks( width.max( 1 ) ) {
+        let rgbe : Vec<[u8; 4]> = row.iter().map( |c| to_rgbe( *c ) ).collect();
+
+        // the encoding can't describe lines this short or long
+        if !(8..=0x7fff).contains( &width ) {
+            for p in &rgbe {
+                out.write_all( p )?;
+            }
+            continue;
+        }
+
+        out.write_all( &[ 2, 2, (width >> 8) as u8, (width & 0xff) as u8 ] )?;
+        for n in 0..4 {
+            comp.clear();
+            comp.extend( rgbe.iter().map( |p| p[n] ) );
+            write_rle( out, &comp )?;
+        }
+    }
+    Ok( () )
+}
+
+// Runs of 4 or more repeats become a count above 128 and the byte, the rest
+// is copied in chunks of up to 128 behind their length
+fn write_rle<W : Write>( out : &mut W, data : &[u8] ) -> io::Result<()> {
+    const MIN_RUN : usize = 4;
+    let n = data.len();
+    let mut cur = 0;
+    while cur < n {
+        // find the next long enough run
+        let mut beg_run = cur;
+        let (mut run, mut old_run) = (0, 0);
+        while run < MIN_RUN && beg_run < n {
+            beg_run += run;
+            old_run = run;
+            run = 1;
+            while beg_run + run < n && run < 127 && data[beg_run] == data[beg_run + run] {
+                run += 1;
+            }
+        }
+
+        // a short run right before it is still worth one
+        if old_run > 1 && old_run == beg_run - cur {
+            out.write_all( &[ 128 + old_run as u8, data[cur] ] )?;
+            cur = beg_run;
+        }
+        while cur < beg_run {
+            let literal = (beg_run - cur).min( 128 );
+            out.write_all( &[ literal as u8 ] )?;
+            out.write_all( &data[cur..cur + literal] )?;
+            cur += literal;
+        }
+        if run >= MIN_RUN {
+            out.write_all( &[ 128 + run as u8, data[beg_run] ] )?;
+            cur += run;
+        }
+    }
+    Ok( () )
+}
+
+pub fn read_hdr( data : &[u8] ) -> io::Result<HdrImage> {
+    let mut at = 0;
+    let mut next_line = || -> io::Result<&[u8]> {
+        let len = data[at..].iter().position( |&b| b == b'\n' ).ok_or_else( || invalid( "truncated header".to_string() ) )?;
+        let line = &data[at..at + len];
+        at += len + 1;
+        Ok( line )
+    };
+
+    let magic = next_line()?;
+    if !magic.starts_with( b"#?" ) {
+        return Err( invalid( "not a Radiance HDR file".to_string() ) );
+    }
+    let mut exposure = 1.0;
+    loop {
+        let line = String::from_utf8_lossy( next_line()? ).trim().to_string();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix( "FORMAT=" ) {
+            if format != "32-bit_rle_rgbe" {
+                return Err( invalid( format!( "unsupported HDR format '{}'", format ) ) );
+            }
+        } else if let Some(value) = line.strip_prefix( "EXPOSURE=" ) {
+            exposure *= value.trim().parse::<f32>().map_err( |_| invalid( format!( "bad exposure '{}'", value ) ) )?;
+        }
+    }
+
+    // only the usual orientations, rows top or bottom first, left to right
+    let res = String::from_utf8_lossy( next_line()? ).to_string();
+    let fields : Vec<&str> = res.split_whitespace().collect();
+    let (flip, height, width) = match fields.as_slice() {
+        [ y @ ("-Y" | "+Y"), h, "+X", w ] => (*y == "+Y", h.parse::<u32>(), w.parse::<u32>()),
+        _ => return Err( invalid( format!( "unsupported HDR resolution '{}'", res ) ) ),
+    };
+    let (Ok(height), Ok(width)) = (height, width) else {
+        return Err( invalid( format!( "bad HDR resolution '{}'", res ) ) );
+    };
+
+    // before allocating anything: even run length encoded, each scanline
+    // takes 2 bytes per component for every 127 pixels
+    let w = width as usize;
+    let truncated = || invalid( "truncated HDR pixel data".to_string() );
+    w.checked_mul( height as usize ).ok_or_else( || invalid( format!( "HDR resolution '{}' too large", res ) ) )?;
+    let min_line = if (8..=0x7fff).contains( &w ) { 4 + 8 * w.div_ceil( 127 ) } else { 4 * w };
+    if (data.len() - at) / min_line.max( 1 ) < height as usize {
+        return Err( truncated() );
+    }
+
+    let mut img = HdrImage::new( width, height );
+    let mut line = vec![ [ 0u8; 4 ]; w ];
+    for row in 0..height {
+        let rle = (8..=0x7fff).contains( &w ) && data.len() >= at + 4 && data[at] == 2 && data[at + 1] == 2 && data[at + 2] & 0x80 == 0;
+        if rle {
+            if ((data[at + 2] as usize) << 8 | data[at + 3] as usize) != w {
+                return Err( invalid( "HDR scanline width mismatch".to_string() ) );
+            }
+            at += 4;
+            for n in 0..4 {
+                let mut x = 0;
+                while x < w {
+                    let count = *data.get( at ).ok_or_else( truncated )? as usize;
+                    at += 1;
+                    if count > 128 {
+                        let count = count - 128;
+                        let value = *data.get( at ).ok_or_else( truncated )?;
+                        at += 1;
+                        if x + count > w {
+                            return Err( invalid( "HDR run overruns scanline".to_string() ) );
+                        }
+                        line[x..x + count].iter_mut().for_each( |p| p[n] = value );
+                        x += count;
+                    } else {
+                        if count == 0 || x + count > w {
+                            return Err( invalid( "bad HDR literal run".to_string() ) );
+                        }
+                        let bytes = data.get( at..at + count ).ok_or_else( truncated )?;
+                        line[x..x + count].iter_mut().zip( bytes ).for_each( |(p, b)| p[n] = *b );
+                        at += count;
+                        x += count;
+                    }
+                }
+            }
+        } else {
+            let bytes = data.get( at..at + 4 * w ).ok_or_else( truncated )?;
+            for (p, b) in line.iter_mut().zip( bytes.chunks( 4 ) ) {
+                p.copy_from_slice( b );
+            }
+            at += 4 * w;
+        }
+
+        let y = if flip { height - 1 - row } else { row };
+        for (x, p) in line.iter().enumerate() {
+            img.pixels[y as usize * w + x] = from_rgbe( *p ) / exposure;
+        }
+    }
+    Ok( img )
+}
+
+// --- PFM ---
+
+// Little endian, rows bottom to top as the format wants
+pub fn write_pfm<W : Write>( out : &mut W, img : &HdrImage ) -> io::Result<()> {
+    write!( out, "PF\n{} {}\n-1.0\n", img.width, img.height )?;
+    let mut row_bytes = Vec::with_capacity( img.width as usize * 12 );
+    for row in img.pixels.chunks( img.width.max( 1 ) as usize ).rev() {
+        row_bytes.clear();
+        for c in row {
+            for v in [ c.x, c.y, c.z ] {
+                row_bytes.extend_from_slice( &v.to_le_bytes() );
+            }
+        }
+        out.write_all( &row_bytes )?;
+    }
+    Ok( () )
+}
+
+// Color (PF) or greyscale (Pf), either byte order
+pub fn read_pfm( data : &[u8] ) -> io::Result<HdrImage> {
+
+    // three whitespace separated header fields, then a single whitespace byte
+    let mut at = 0;
+    let mut token = || -> io::Result<String> {
+        while at < data.len() && data[at].is_ascii_whitespace() {
+            at += 1;
+        }
+        let start = at;
+        while at < data.len() && !data[at].is_ascii_whitespace() {
+            at += 1;
+        }
+        if start == at {
+            return Err( invalid( "truncated PFM header".to_string() ) );
+        }
+        Ok( String::from_utf8_lossy( &data[start..at] ).to_string() )
+    };
+
+    let channels = match token()?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        other => return Err( invalid( format!( "not a PFM file ('{}')", other ) ) ),
+    };
+    let width : u32 = token()?.parse().map_err( |_| invalid( "bad PFM width".to_string() ) )?;
+    let height : u32 = token()?.parse().map_err( |_| invalid( "bad PFM height".to_string() ) )?;
+    let scale : f32 = token()?.parse().map_err( |_| invalid( "bad PFM scale".to_string() ) )?;
+    at += 1;
+
+    let count = (width as usize).checked_mul( height as usize ).and_then( |n| n.checked_mul( 4 * channels ) )
+        .ok_or_else( || invalid( format!( "PFM size {}x{} too large", width, height ) ) )?;
+    let bytes = data.get( at..at.saturating_add( count ) ).ok_or_else( || invalid( "truncated PFM pixel data".to_string() ) )?;
+    let floats : Vec<f32> = bytes.chunks( 4 ).map( |b| {
+        let b = [ b[0], b[1], b[2], b[3] ];
+        if scale < 0.0 { f32::from_le_bytes( b ) } else { f32::from_be_bytes( b ) }
+    }).collect();
+
+    let mut img = HdrImage::new( width, height );
+    for (row, values) in floats.chunks( (width as usize * channels).max( 1 ) ).enumerate().take( height as usize ) {
+        let y = height as usize - 1 - row;
+        for x in 0..width as usize {
+            let v = &values[x * channels..(x + 1) * channels];
+            img.pixels[y * width as usize + x] = if channels == 3 { Vec3::new( v[0], v[1], v[2] ) } else { Vec3::from_float( v[0] ) };
+        }
+    }
+    Ok( img )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgbe_round_trip() {
+        for c in [ Vec3::new( 1.0, 0.5, 0.25 ), Vec3::new( 1000.0, 3.0, 0.0 ), Vec3::from_float( 1e-3 ) ] {
+            let back = from_rgbe( to_rgbe( c ) );
+            let max = c.x.max( c.y ).max( c.z );
+            assert!( (back - c).length() <= max / 128.0, "{:?} -> {:?}", c, back );
+        }
+        assert_eq!( to_rgbe( Vec3::ZERO ), [ 0, 0, 0, 0 ] );
+        assert_eq!( from_rgbe( [ 0, 0, 0, 0 ] ), Vec3::ZERO );
+    }
+
+    fn data_file( name : &str ) -> std::path::PathBuf {
+        Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "data" ).join( name )
+    }
+
+    // An 8 pixel scanline put together by hand from the Radiance format
+    // description rather than by write_hdr(): new style run length encoding,
+    // then each component as runs (count above 128, then the byte) and
+    // literals (count, then the bytes).
+    const HAND_HDR : &[u8] = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n\
+        \x02\x02\x00\x08\
+        \x88\x80\
+        \x08\x00\x10\x20\x30\x40\x50\x60\x70\
+        \x84\x00\x04\x01\x02\x03\x04\
+        \x88\x81";
+
+    #[test]
+    fn hand_built_hdr() {
+        // data/hand_built.hdr holds those bytes. Exponent 129 scales
+        // mantissas by 2^(129 - 136), decoded to the middle of each step.
+        let path = data_file( "hand_built.hdr" );
+        assert_eq!( std::fs::read( &path ).unwrap(), HAND_HDR );
+        let img = load( &path ).unwrap();
+        assert_eq!( (img.width, img.height), (8, 1) );
+        let blue = [ 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 4.0 ];
+        for x in 0..8 {
+            let want = Vec3::new( 128.5, 16.0 * x as f32 + 0.5, blue[x as usize] + 0.5 ) / 128.0;
+            assert_eq!( img.pixel( x, 0 ), want, "at {}", x );
+        }
+
+        // values that are exactly a mantissa encode to the same bytes
+        let exact = HdrImage { width : 8, height : 1,
+                               pixels : (0..8).map( |x| Vec3::new( 128.0, 16.0 * x as f32, blue[x] ) / 128.0 ).collect() };
+        let out = std::env::temp_dir().join( "rtview_hand_built.hdr" );
+        save( &out, &exact ).unwrap();
+        assert_eq!( std::fs::read( &out ).unwrap(), HAND_HDR );
+        assert_eq!( load( &out ).unwrap(), img );
+
+        // flat pixels, bottom row first, and an exposure to undo
+        let flat = b"#?RADIANCE\nEXPOSURE=2\n\n+Y 2 +X 1\n\x80\x40\x00\x81\x00\x00\x80\x80";
+        let img = read_hdr( flat ).unwrap();
+        assert_eq!( img.pixel( 0, 1 ), Vec3::new( 128.5, 64.5, 0.5 ) / 128.0 / 2.0 );
+        assert_eq!( img.pixel( 0, 0 ), Vec3::new( 0.5, 0.5, 128.5 ) / 256.0 / 2.0 );
+    }
+
+    #[test]
+    fn hand_built_pfm() {
+        // data/hand_built.pfm is 2x2, little endian as the negative scale
+        // says, bottom row first
+        let values = [ [ 1.0, 2.0, 3.0 ], [ -4.0, 0.5, 1e6 ], [ 0.0, 0.25, 7.0 ], [ 8.0, 9.0, 10.0 ] ];
+        let mut pfm = b"PF\n2 2\n-1.0\n".to_vec();
+        for v in values.iter().flatten() {
+            pfm.extend_from_slice( &f32::to_le_bytes( *v ) );
+        }
+        let path = data_file( "hand_built.pfm" );
+        assert_eq!( std::fs::read( &path ).unwrap(), pfm );
+
+        let img = load( &path ).unwrap();
+        assert_eq!( (img.width, img.height), (2, 2) );
+        let at = |k : usize| Vec3::new( values[k][0], values[k][1], values[k][2] );
+        assert_eq!( img.pixels, vec![ at( 2 ), at( 3 ), at( 0 ), at( 1 ) ] );
+
+        let out = std::env::temp_dir().join( "rtview_hand_built.pfm" );
+        save( &out, &img ).unwrap();
+        assert_eq!( std::fs::read( &out ).unwrap(), pfm );
+        assert_eq!( load( &out ).unwrap(), img );
+    }
+
+    #[test]
+    fn flat_hdr_and_greyscale_pfm() {
+        // too narrow for run length encoding
+        let img = HdrImage { width : 2, height : 1, pixels : vec![ Vec3::new( 1.0, 2.0, 3.0 ), Vec3::ZERO ] };
+        let mut out = Vec::new();
+        write_hdr( &mut out, &img ).unwrap();
+        assert_eq!( read_hdr( &out ).unwrap().pixel( 1, 0 ), Vec3::ZERO );
+
+        let mut pf = b"Pf\n2 1\n1.0\n".to_vec();
+        pf.extend_from_slice( &0.5f32.to_be_bytes() );
+        pf.extend_from_slice( &4.0f32.to_be_bytes() );
+        let img = read_pfm( &pf ).unwrap();
+        assert_eq!( img.pixels, vec![ Vec3::from_float( 0.5 ), Vec3::from_float( 4.0 ) ] );
+        assert!( read_pfm( b"PF\n2 1\n-1.0\n" ).is_err() );
+    }
+
+    #[test]
+    fn mean_squared_error() {
+        let mut a = HdrImage::new( 2, 1 );
+        let b = a.clone();
+        assert_eq!( a.mse( &b ), Some( 0.0 ) );
+
+        // one channel of one pixel off by 3, averaged over all six
+        a.pixels[1] = Vec3::new( 0.0, 3.0, 0.0 );
+        assert_eq!( a.mse( &b ), Some( 1.5 ) );
+        assert_eq!( b.mse( &a ), Some( 1.5 ) );
+        assert_eq!( a.mse( &HdrImage::new( 1, 2 ) ), None );
+    }
+
+    #[test]
+    fn huge_sizes() {
+        // sizes that overflow 32 bits, or want far more data than there is
+        let err = |r : io::Result<HdrImage>| r.unwrap_err().to_string();
+        assert_eq!( err( read_hdr( b"#?RADIANCE\n\n-Y 70000 +X 70000\n\x02\x02" ) ), "truncated HDR pixel data" );
+        assert_eq!( err( read_hdr( b"#?RADIANCE\n\n-Y 4294967295 +X 4294967295\n" ) ), "truncated HDR pixel data" );
+        assert_eq!( err( read_pfm( b"PF\n70000 70000\n-1.0\n" ) ), "truncated PFM pixel data" );
+        assert_eq!( err( read_pfm( b"PF\n4294967295 4294967295\n-1.0\n" ) ), "PFM size 4294967295x4294967295 too large" );
+    }
+}
@@ -0,0 +1,201 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use vec3::Vec3;
+
+use super::ray::Ray;
+use super::rng::Rng;
+use super::aabb::Aabb;
+use super::material::{Material, MaterialRef};
+use super::light_tree::LightBounds;
+use super::tonemap::luminance;
+
+pub struct HitRecord<'a> {
+    pub p : Vec3,
+    pub normal : Vec3,
+    pub t : f32,
+    pub front_face : bool,
+
+    // surface (texture) coordinates at p
+    pub u : f32,
+    pub v : f32,
+
+    pub mat : &'a dyn Material,
+
+    // set by Tagged for the AOVs, 0 if untagged
+    pub object_id : u32,
+    pub material_id : u32,
+}
+
+impl HitRecord<'_> {
+
+    // Orients the normal against the incoming ray. outward_n is expected
+    // to point out of the surface and be unit length.
+    pub fn face_normal( ray : &Ray, outward_n : Vec3 ) -> (Vec3, bool)
+    {
+        if Vec3::dot( &ray.dir, &outward_n ) > 0.0 {
+            (-outward_n, false)
+        } else {
+            (outward_n, true)
+        }
+    }
+}
+
+pub trait Hittable {
+    fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>>;
+
+    // World space bounds, used to build acceleration structures
+    fn bounding_box( &self ) -> Aabb;
+
+    // The one material everything in this object uses, if there is one
+    fn material( &self ) -> Option<&MaterialRef> {
+        None
+    }
+
+    // Density, per unit solid angle, of random_direction() picking dir from
+    // origin. 0 for shapes that can't be sampled as lights.
+    fn pdf_value( &self, _origin : Vec3, _dir : Vec3 ) -> f32 {
+        0.0
+    }
+
+    // Random direction from origin towards the surface
+    fn random_direction( &self, _origin : Vec3, _rng : &mut Rng ) -> Vec3 {
+        Vec3::new( 1.0, 0.0, 0.0 )
+    }
+
+    // Where the object is as a light, how much it gives off and which way,
+    // for the light tree. Shapes that know better than a box giving off
+    // light everywhere should say so.
+    fn light_bounds( &self ) -> LightBounds {
+        let bbox = self.bounding_box();
+        let radiance = self.material().map_or( 0.0, |mat| luminance( mat.emission() ) );
+        LightBounds::omni( bbox, radiance * PI * bbox.surface_area() )
+    }
+
+    // Pieces to sample one by one when the object is a light, so each can
+    // be judged on its own. Empty to sample it whole.
+    fn light_parts( &self ) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        Vec::new()
+    }
+}
+
+// Lets one object be in the world and in the scene's light list at once
+impl<T : Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        self.as_ref().hit( ray, ray_tmin, ray_tmax )
+    }
+
+    fn bounding_box( &self ) -> Aabb {
+        self.as_ref().bounding_box()
+    }
+
+    fn material( &self ) -> Option<&MaterialRef> {
+        self.as_ref().material()
+    }
+
+    fn pdf_value( &self, origin : Vec3, dir : Vec3 ) -> f32 {
+        self.as_ref().pdf_value( origin, dir )
+    }
+
+    fn random_direction( &self, origin : Vec3, rng : &mut Rng ) -> Vec3 {
+        self.as_ref().random_direction( origin, rng )
+    }
+
+    fn light_bounds( &self ) -> LightBounds {
+        self.as_ref().light_bounds()
+    }
+
+    fn light_parts( &self ) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.as_ref().light_parts()
+    }
+}
+
+// Stamps object and material ids onto the hits of the object it wraps
+pub struct Tagged {
+    pub object : Box<dyn Hittable + Send + Sync>,
+    pub object_id : u32,
+    pub material_id : u32,
+}
+
+impl Hittable for Tagged {
+    fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        self.object.hit( ray, ray_tmin, ray_tmax ).map( |rec| HitRecord {
+            object_id : self.object_id,
+            material_id : self.material_id,
+            ..rec
+        })
+    }
+
+    fn bounding_box( &self ) -> Aabb {
+        self.object.bounding_box()
+    }
+
+    fn material( &self ) -> Option<&MaterialRef> {
+        self.object.material()
+    }
+
+    fn pdf_value( &self, origin : Vec3, dir : Vec3 ) -> f32 {
+        self.object.pdf_value( origin, dir )
+    }
+
+    fn random_direction( &self, origin : Vec3, rng : &mut Rng ) -> Vec3 {
+        self.object.random_direction( origin, rng )
+    }
+
+    fn light_bounds( &self ) -> LightBounds {
+        self.object.light_bounds()
+    }
+
+    fn light_parts( &self ) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.object.light_parts()
+    }
+}
+
+// A flat list of objects, tested one after another. Returns the closest hit.
+#[derive(Default)]
+pub struct HittableList {
+    pub objects : Vec<Box<dyn Hittable + Send + Sync>>,
+}
+
+impl HittableList {
+
+    pub fn new() -> HittableList {
+        HittableList { objects : Vec::new() }
+    }
+
+    pub fn add( &mut self, object : Box<dyn Hittable + Send + Sync> ) {
+        self.objects.push( object );
+    }
+
+    pub fn clear( &mut self ) {
+        self.objects.clear();
+    }
+
+    pub fn len( &self ) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+impl Hittable for HittableList {
+    fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        let mut closest_so_far = ray_tmax;
+        let mut result = None;
+
+        for object in &self.objects {
+            if let Some(rec) = object.hit( ray, ray_tmin, closest_so_far ) {
+                closest_so_far = rec.t;
+                result = Some(rec);
+            }
+        }
+
+        result
+    }
+
+    fn bounding_box( &self ) -> Aabb {
+        self.objects.iter().fold( Aabb::EMPTY, |bbox, object| Aabb::surrounding( &bbox, &object.bounding_box() ) )
+    }
+}
@@ -0,0 +1,211 @@
+// IES LM-63 photometric files, the luminous intensity distribution that
+// luminaire manufacturers publish. Only type C photometry, which is what
+// architectural fixtures use: vertical angles run from 0 (straight down,
+// the nadir) to 180, horizontal angles go round the vertical axis.
+
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IesProfile {
+    // angles in degrees, ascending
+    pub vertical : Vec<f32>,
+    pub horizontal : Vec<f32>,
+
+    // in candela, all the vertical angles for each horizontal angle in turn,
+    // with the file's multiplier and ballast factor applied
+    pub candela : Vec<f32>,
+}
+
+fn invalid( msg : String ) -> io::Error {
+    io::Error::new( io::ErrorKind::InvalidData, msg )
+}
+
+pub fn load( path : &Path ) -> io::Result<IesProfile> {
+    parse( &fs::read_to_string( path )? )
+}
+
+pub fn parse( text : &str ) -> io::Result<IesProfile> {
+
+    // keyword lines, up to and including TILT
+    let mut lines = text.lines();
+    let tilt = loop {
+        match lines.next() {
+            Some(line) => if let Some(tilt) = line.trim().strip_prefix( "TILT=" ) {
+                break tilt.trim().to_string();
+            },
+            None => return Err( invalid( "no TILT= line".to_string() ) ),
+        }
+    };
+
+    let numbers = lines
+        .flat_map( |line| line.split( |c : char| c.is_whitespace() || c == ',' ) )
+        .filter( |s| !s.is_empty() )
+        .map( |s| s.parse::<f32>().map_err( |_| invalid( format!( "bad number '{}'", s ) ) ) )
+        .collect::<io::Result<Vec<f32>>>()?;
+    let ends_early = || invalid( "file ends early".to_string() );
+    let used = Cell::new( 0 );
+    let next = || {
+        let number = numbers.get( used.get() ).copied().ok_or_else( ends_early );
+        used.set( used.get() + 1 );
+        number
+    };
+
+    // counts come from the file, so check they fit what's left of it before
+    // looping or allocating on them
+    let left = || numbers.len().saturating_sub( used.get() );
+
+    // lamp tilt tables only matter for lamps that change output with angle, skip them
+    match tilt.as_str() {
+        "NONE" => {},
+        "INCLUDE" => {
+            let _geometry = next()?;
+            let count = next()? as usize;
+            if count > left() / 2 {
+                return Err( ends_early() );
+            }
+            for _ in 0..2 * count {
+                next()?;
+            }
+        },
+        other => return Err( invalid( format!( "TILT={} (a separate tilt file) is not supported", other ) ) ),
+    }
+
+    let _num_lamps = next()?;
+    let _lumens_per_lamp = next()?;
+    let multiplier = next()?;
+    let num_vertical = next()? as usize;
+    let num_horizontal = next()? as usize;
+    let photometric_type = next()? as u32;
+    let _units = next()?;
+    let _width = next()?;
+    let _length = next()?;
+    let _height = next()?;
+    let ballast_factor = next()?;
+    let _future_use = next()?;
+    let _input_watts = next()?;
+
+    if photometric_type != 1 {
+        return Err( invalid( format!( "photometric type {} is not supported, only type C (1)", photometric_type ) ) );
+    }
+    if num_vertical == 0 || num_horizontal == 0 {
+        return Err( invalid( "no angles".to_string() ) );
+    }
+    let num_candela = num_vertical.checked_mul( num_horizontal )
+        .filter( |&n| n.saturating_add( num_vertical ).saturating_add( num_horizontal ) <= left() )
+        .ok_or_else( ends_early )?;
+
+    let vertical = (0..num_vertical).map( |_| next() ).collect::<io::Result<Vec<f32>>>()?;
+    let horizontal = (0..num_horizontal).map( |_| next() ).collect::<io::Result<Vec<f32>>>()?;
+    let candela = (0..num_candela)
+        .map( |_| next().map( |cd| cd * multiplier * ballast_factor ) )
+        .collect::<io::Result<Vec<f32>>>()?;
+
+    if !vertical.windows( 2 ).all( |w| w[0] < w[1] ) || !horizontal.windows( 2 ).all( |w| w[0] < w[1] ) {
+        return Err( invalid( "angles should be in increasing order".to_string() ) );
+    }
+
+    Ok( IesProfile { vertical, horizontal, candela } )
+}
+
+impl IesProfile {
+
+    // Intensity in candela towards vertical angle theta (from the nadir)
+    // and horizontal angle phi, both in degrees. Files only cover part of
+    // the horizontal circle when the fixture is symmetric, the rest is
+    // folded onto that part.
+    pub fn candela( &self, theta : f32, phi : f32 ) -> f32 {
+        let (v_first, v_last) = (self.vertical[0], self.vertical[self.vertical.len() - 1]);
+        if theta < v_first || theta > v_last {
+            return 0.0;
+        }
+
+        let mut phi = phi.rem_euclid( 360.0 );
+        let last = self.horizontal[self.horizontal.len() - 1];
+        if last == 0.0 {
+            // same all round
+            phi = 0.0;
+        } else if last == 90.0 || last == 180.0 {
+            // mirrored in the C0-C180 plane, and for 90 in the C90-C270 one too
+            if phi > 180.0 {
+                phi = 360.0 - phi;
+            }
+            if last == 90.0 && phi > 90.0 {
+                phi = 180.0 - phi;
+            }
+        }
+
+        let (h, hf) = bracket( &self.horizontal, phi );
+        let (v, vf) = bracket( &self.vertical, theta );
+        let n = self.vertical.len();
+        let at = |hi : usize, vi : usize| self.candela[hi * n + vi];
+        let h1 = (h + 1).min( self.horizontal.len() - 1 );
+        let v1 = (v + 1).min( n - 1 );
+
+        let lo = at( h, v ) * (1.0 - vf) + at( h, v1 ) * vf;
+        let hi = at( h1, v ) * (1.0 - vf) + at( h1, v1 ) * vf;
+        lo * (1.0 - hf) + hi * hf
+    }
+
+    pub fn max_candela( &self ) -> f32 {
+        self.candela.iter().copied().fold( 0.0, f32::max )
+    }
+}
+
+// Index of the last angle at or below x and how far x is towards the next
+// one, clamped to the ends of the list
+fn bracket( angles : &[f32], x : f32 ) -> (usize, f32) {
+    let i = angles.partition_point( |&a| a <= x ).saturating_sub( 1 ).min( angles.len() - 1 );
+    if i + 1 >= angles.len() {
+        return (i, 0.0);
+    }
+    let f = ((x - angles[i]) / (angles[i + 1] - angles[i])).clamp( 0.0, 1.0 );
+    (i, f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downlight_file() {
+        let profile = load( &Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "data" ).join( "downlight.ies" ) ).unwrap();
+        assert_eq!( profile.vertical, vec![ 0.0, 15.0, 30.0, 45.0, 60.0, 75.0, 90.0 ] );
+        assert_eq!( profile.horizontal, vec![ 0.0 ] );
+        assert_eq!( profile.max_candela(), 1200.0 );
+
+        // same whichever way round, interpolated between angles, dark above
+        assert_eq!( profile.candela( 0.0, 123.0 ), 1200.0 );
+        assert_eq!( profile.candela( 7.5, 0.0 ), 0.5 * (1200.0 + 1140.0) );
+        assert_eq!( profile.candela( 90.0, 0.0 ), 0.0 );
+        assert_eq!( profile.candela( 120.0, 0.0 ), 0.0 );
+    }
+
+    #[test]
+    fn symmetry_and_tilt() {
+        // quadrant symmetric, brighter along C90, with a tilt table to skip
+        let text = "IESNA:LM-63-2002\n[TEST] quadrant\nTILT=INCLUDE\n1\n2\n0 90\n1 1\n\
+                    1 -1 2 3 2 1 2 0 0 0\n1 1 50\n0 45 90\n0, 90\n100 50 0\n300 150 0\n";
+        let profile = parse( text ).unwrap();
+        assert_eq!( profile.candela( 0.0, 0.0 ), 200.0 );
+        assert_eq!( profile.candela( 45.0, 90.0 ), 300.0 );
+        assert_eq!( profile.candela( 45.0, 270.0 ), 300.0 );
+        assert_eq!( profile.candela( 45.0, 180.0 ), 100.0 );
+        assert_eq!( profile.candela( 0.0, 135.0 ), 400.0 );
+
+        let err = |text : &str| parse( text ).unwrap_err().to_string();
+        assert_eq!( err( "[TEST] x\n" ), "no TILT= line" );
+        assert_eq!( err( "TILT=lamp.tlt\n" ), "TILT=lamp.tlt (a separate tilt file) is not supported" );
+        assert_eq!( err( "TILT=NONE\n1 -1 1 1 1 2 1 0 0 0 1 1 50 0 0 10\n" ), "photometric type 2 is not supported, only type C (1)" );
+        assert_eq!( err( "TILT=NONE\n1 -1 1 2 1 1 1 0 0 0 1 1 50 0 90 0 10\n" ), "file ends early" );
+        assert_eq!( err( "TILT=NONE\n1 -1 1 1 1 1 1 0 0 0 1 1 50 zero\n" ), "bad number 'zero'" );
+
+        // counts that don't fit the file, some too big to even multiply
+        assert_eq!( err( "TILT=INCLUDE\n1 4e38\n" ), "file ends early" );
+        assert_eq!( err( "TILT=NONE\n1 -1 1 3e9 3e9 1 1 0 0 0 1 1 50 0 0 10\n" ), "file ends early" );
+        assert_eq!( err( "TILT=NONE\n1 -1 1 1e20 1e20 1 1 0 0 0 1 1 50 0 0 10\n" ), "file ends early" );
+        assert_eq!( err( "TILT=NONE\n1 -1 1 1 1e20 1 1 0 0 0 1 1 50 0 0 10\n" ), "file ends early" );
+    }
+}
@@ -0,0 +1,155 @@
+// Image file output. Everything is written by hand so there are no
+// dependencies; the format is picked from the file extension. Float
+// formats get the raw values, see hdr_image.rs.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::film::Film;
+use super::tonemap::DisplayTransform;
+use super::aov::Aov;
+use super::hdr_image::{self, HdrImage};
+
+// 8-bit RGB image, rows top to bottom
+pub struct Rgb8Image {
+    pub width : u32,
+    pub height : u32,
+    pub data : Vec<u8>,
+}
+
+impl Rgb8Image {
+    pub fn from_film( film : &Film, aov : Aov, xform : &DisplayTransform ) -> Rgb8Image {
+        let mut data = Vec::with_capacity( (film.width * film.height * 3) as usize );
+        for j in 0..film.height {
+            for i in 0..film.width {
+                data.extend_from_slice( &aov.display( film, i, j, xform ) );
+            }
+        }
+        Rgb8Image { width : film.width, height : film.height, data }
+    }
+}
+
+// Writes one AOV of the film, as displayed for PNG or PPM, as is for HDR or
+// PFM, depending on the extension
+pub fn write_image( path : &Path, film : &Film, aov : Aov, xform : &DisplayTransform ) -> io::Result<()> {
+    let ext = path.extension().and_then( |e| e.to_str() ).unwrap_or( "" ).to_ascii_lowercase();
+    if matches!( ext.as_str(), "hdr" | "rgbe" | "pfm" ) {
+        return hdr_image::save( path, &HdrImage::from_film( film, aov ) );
+    }
+    let img = Rgb8Image::from_film( film, aov, xform );
+
+    let mut out = BufWriter::new( File::create( path )? );
+    match ext.as_str() {
+        "png" => write_png( &mut out, &img )?,
+        "ppm" => write_ppm( &mut out, &img )?,
+        _ => return Err( io::Error::new( io::ErrorKind::InvalidInput,
+                            format!( "don't know how to write '.{}' files", ext ) ) ),
+    }
+    out.flush()
+}
+
+// Binary (P6) PPM
+pub fn write_ppm<W : Write>( out : &mut W, img : &Rgb8Image ) -> io::Result<()> {
+    write!( out, "P6\n{} {}\n255\n", img.width, img.height )?;
+    out.write_all( &img.data )
+}
+
+// PNG, 8-bit RGB. The zlib stream uses uncompressed (stored) deflate
+// blocks, so files are big but trivially correct.
+pub fn write_png<W : Write>( out : &mut W, img : &Rgb8Image ) -> io::Result<()> {
+    out.write_all( &[ 0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n' ] )?;
+
+    let mut ihdr = Vec::with_capacity( 13 );
+    ihdr.extend_from_slice( &img.width.to_be_bytes() );
+    ihdr.extend_from_slice( &img.height.to_be_bytes() );
+    ihdr.extend_from_slice( &[ 8, 2, 0, 0, 0 ] ); // 8 bits, RGB, deflate, no filter, no interlace
+    write_png_chunk( out, b"IHDR", &ihdr )?;
+
+    // each scanline is prefixed by its filter type, 0 = none
+    let row_bytes = (img.width * 3) as usize;
+    let mut raw = Vec::with_capacity( (row_bytes + 1) * img.height as usize );
+    for row in img.data.chunks( row_bytes ) {
+        raw.push( 0 );
+        raw.extend_from_slice( row );
+    }
+    write_png_chunk( out, b"IDAT", &zlib_stored( &raw ) )?;
+    write_png_chunk( out, b"IEND", &[] )
+}
+
+fn write_png_chunk<W : Write>( out : &mut W, kind : &[u8; 4], data : &[u8] ) -> io::Result<()> {
+    out.write_all( &(data.len() as u32).to_be_bytes() )?;
+    out.write_all( kind )?;
+    out.write_all( data )?;
+
+    let crc = crc32_update( crc32_update( 0xffffffff, kind ), data ) ^ 0xffffffff;
+    out.write_all( &crc.to_be_bytes() )
+}
+
+fn crc32_update( mut crc : u32, data : &[u8] ) -> u32 {
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn adler32( data : &[u8] ) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks( 5552 ) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+// zlib wrapper around stored deflate blocks of at most 64k each
+fn zlib_stored( data : &[u8] ) -> Vec<u8> {
+    let mut out = Vec::with_capacity( data.len() + data.len() / 65535 * 5 + 16 );
+    out.extend_from_slice( &[ 0x78, 0x01 ] );
+
+    let mut blocks = data.chunks( 65535 ).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice( &[ 1, 0, 0, 0xff, 0xff ] );
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push( last as u8 );
+        out.extend_from_slice( &len.to_le_bytes() );
+        out.extend_from_slice( &(!len).to_le_bytes() );
+        out.extend_from_slice( block );
+    }
+
+    out.extend_from_slice( &adler32( data ).to_be_bytes() );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!( crc32_update( 0xffffffff, b"123456789" ) ^ 0xffffffff, 0xcbf43926 );
+        assert_eq!( adler32( b"Wikipedia" ), 0x11e60398 );
+    }
+
+    #[test]
+    fn png_layout() {
+        let img = Rgb8Image { width : 2, height : 1, data : vec![ 255, 0, 0, 0, 255, 0 ] };
+        let mut bytes = Vec::new();
+        write_png( &mut bytes, &img ).unwrap();
+
+        assert_eq!( &bytes[0..8], b"\x89PNG\r\n\x1a\n" );
+        assert_eq!( &bytes[12..16], b"IHDR" );
+        // IEND chunk with its well known CRC at the very end
+        assert_eq!( &bytes[bytes.len()-12..], b"\0\0\0\0IEND\xae\x42\x60\x82" );
+    }
+}
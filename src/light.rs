@@ -0,0 +1,279 @@
+// The emitters of a scene, gathered so paths can aim at them directly
+
+use std::sync::{Arc, OnceLock};
+
+use vec3::Vec3;
+
+use super::rng::Rng;
+use super::hittable::Hittable;
+use super::environment::Environment;
+use super::punctual::PunctualLight;
+use super::light_tree::{LightBounds, LightTree};
+
+// Objects with an emissive material. They're in the world as well, this
+// only keeps a second reference for picking one to sample. Punctual lights
+// are only here, and the environment, if there is one, counts as one more.
+// Add lights with add() and add_punctual(), which keep the tree up to date.
+#[derive(Default, Clone)]
+pub struct LightList {
+    pub lights : Vec<Arc<dyn Hittable + Send + Sync>>,
+    pub punctual : Vec<PunctualLight>,
+    pub environment : Option<Arc<dyn Environment>>,
+
+    // built the first time it's needed
+    selection : OnceLock<Selection>,
+}
+
+// The lights in a tree, numbered lights first then punctual ones, apart
+// from directional lights which are everywhere and picked like the
+// environment
+#[derive(Clone)]
+struct Selection {
+    tree : LightTree,
+    directional : Vec<usize>,
+}
+
+// What sampling the lights came up with
+pub enum LightSample {
+    // towards a light that scattered rays can find too, see LightList::pdf
+    Direction(Vec3),
+
+    // a punctual light: unit direction and distance to it, the irradiance
+    // it gives, and the chance it was the one picked
+    Delta { dir : Vec3, dist : f32, light : Vec3, probability : f32 },
+}
+
+impl LightList {
+
+    pub fn new() -> LightList {
+        LightList { lights : Vec::new(), punctual : Vec::new(), environment : None, selection : OnceLock::new() }
+    }
+
+    pub fn add( &mut self, light : Arc<dyn Hittable + Send + Sync> ) {
+        self.lights.push( light );
+        self.selection = OnceLock::new();
+    }
+
+    pub fn add_punctual( &mut self, light : PunctualLight ) {
+        self.punctual.push( light );
+        self.selection = OnceLock::new();
+    }
+
+    pub fn len( &self ) -> usize {
+        self.lights.len() + self.punctual.len() + self.environment.is_some() as usize
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.len() == 0
+    }
+
+    fn selection( &self ) -> &Selection {
+        self.selection.get_or_init( || {
+            let mut bounded : Vec<(LightBounds, usize)> = self.lights.iter().enumerate()
+                .map( |(i, light)| (light.light_bounds(), i) )
+                .collect();
+            let mut directional = Vec::new();
+            for (i, light) in self.punctual.iter().enumerate() {
+                match light.light_bounds() {
+                    Some(bounds) => bounded.push( (bounds, self.lights.len() + i) ),
+                    None => directional.push( i ),
+                }
+            }
+            Selection { tree : LightTree::new( bounded ), directional }
+        })
+    }
+
+    // How many lights are everywhere (directional ones and the
+    // environment), and the chance of picking one of those over the tree,
+    // which counts the same as one of them
+    fn infinite( &self, selection : &Selection ) -> (usize, f32) {
+        let n = selection.directional.len() + self.environment.is_some() as usize;
+        if n == 0 {
+            return (0, 0.0);
+        }
+        (n, n as f32 / (n + !selection.tree.is_empty() as usize) as f32)
+    }
+
+    // Picks one of the lights and aims at it from origin. With use_tree
+    // lights are picked by how much each might give origin, otherwise all
+    // with the same probability. None if no light can reach origin.
+    pub fn sample( &self, origin : Vec3, use_tree : bool, rng : &mut Rng ) -> Option<LightSample> {
+        if self.is_empty() {
+            return None;
+        }
+        if !use_tree {
+            return Some( self.sample_uniform( origin, rng ) );
+        }
+
+        let selection = self.selection();
+        let (num_infinite, p_infinite) = self.infinite( selection );
+        if rng.next_f32() < p_infinite {
+            let ndx = ((rng.next_f32() * num_infinite as f32) as usize).min( num_infinite - 1 );
+            let probability = p_infinite / num_infinite as f32;
+            return Some( match (selection.directional.get( ndx ), &self.environment) {
+                (Some(&i), _) => delta( &self.punctual[i], origin, probability ),
+                (None, Some(env)) => LightSample::Direction( env.sample( rng ) ),
+                (None, None) => return None,
+            });
+        }
+
+        let (ndx, probability) = selection.tree.sample( origin, rng )?;
+        let probability = probability * (1.0 - p_infinite);
+        Some( match self.lights.get( ndx ) {
+            Some(light) => LightSample::Direction( light.random_direction( origin, rng ) ),
+            None => delta( &self.punctual[ndx - self.lights.len()], origin, probability ),
+        })
+    }
+
+    // Picks one of the punctual lights alike, for when the others are left
+    // to be found by scattering. None if there aren't any.
+    pub fn sample_punctual( &self, origin : Vec3, rng : &mut Rng ) -> Option<LightSample> {
+        let n = self.punctual.len();
+        if n == 0 {
+            return None;
+        }
+        let ndx = ((rng.next_f32() * n as f32) as usize).min( n - 1 );
+        Some( delta( &self.punctual[ndx], origin, 1.0 / n as f32 ) )
+    }
+
+    fn sample_uniform( &self, origin : Vec3, rng : &mut Rng ) -> LightSample {
+        let n = self.len();
+        let ndx = ((rng.next_f32() * n as f32) as usize).min( n - 1 );
+        if let Some(light) = self.lights.get( ndx ) {
+            return LightSample::Direction( light.random_direction( origin, rng ) );
+        }
+        if let Some(light) = self.punctual.get( ndx - self.lights.len() ) {
+            return delta( light, origin, 1.0 / n as f32 );
+        }
+        LightSample::Direction( self.environment.as_ref().map_or( Vec3::new( 1.0, 0.0, 0.0 ), |env| env.sample( rng ) ) )
+    }
+
+    // Density of sample() picking dir, per unit solid angle. Punctual
+    // lights are never in the way of a direction, but do count for how
+    // often the others are picked.
+    pub fn pdf( &self, origin : Vec3, dir : Vec3, use_tree : bool ) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        if !use_tree {
+            let sum : f32 = self.lights.iter().map( |light| light.pdf_value( origin, dir ) ).sum();
+            let env = self.environment.as_ref().map_or( 0.0, |env| env.pdf( dir ) );
+            return (sum + env) / self.len() as f32;
+        }
+
+        let selection = self.selection();
+        let (num_infinite, p_infinite) = self.infinite( selection );
+        let env = self.environment.as_ref().map_or( 0.0, |env| env.pdf( dir ) * p_infinite / num_infinite as f32 );
+        let tree = selection.tree.pdf( origin, dir, |ndx| self.lights.get( ndx ).map_or( 0.0, |light| light.pdf_value( origin, dir ) ) );
+        env + (1.0 - p_infinite) * tree
+    }
+}
+
+fn delta( light : &PunctualLight, origin : Vec3, probability : f32 ) -> LightSample {
+    let (dir, dist, light) = light.illuminate( origin );
+    LightSample::Delta { dir, dist, light, probability }
+}
+
+// MIS weight for a sample drawn with pdf a, when it could also have been
+// drawn by a strategy with pdf b (Veach's power heuristic, beta = 2)
+pub fn power_heuristic( a : f32, b : f32 ) -> f32 {
+    let (a_sq, b_sq) = (a * a, b * b);
+    if a_sq + b_sq <= 0.0 {
+        return 0.0;
+    }
+    a_sq / (a_sq + b_sq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quad::Quad;
+    use crate::material::DiffuseLight;
+
+    #[test]
+    fn pdf_is_average_of_lights() {
+        let mat = Arc::new( DiffuseLight::new( Vec3::ONE ) );
+        let mut lights = LightList::new();
+        assert_eq!( lights.pdf( Vec3::ZERO, Vec3::new( 0.0, 0.0, -1.0 ), false ), 0.0 );
+
+        // 1 x 1 squares 1 unit in front and 1 unit behind
+        lights.add( Arc::new( Quad::new( Vec3::new( -0.5, -0.5, -1.0 ), Vec3::new( 1.0, 0.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ), mat.clone() ) ) );
+        lights.add( Arc::new( Quad::new( Vec3::new( -0.5, -0.5, 1.0 ), Vec3::new( 0.0, 1.0, 0.0 ), Vec3::new( 1.0, 0.0, 0.0 ), mat ) ) );
+        assert_eq!( lights.len(), 2 );
+        assert!( (lights.pdf( Vec3::ZERO, Vec3::new( 0.0, 0.0, -1.0 ), false ) - 0.5).abs() < 1e-6 );
+        assert_eq!( lights.pdf( Vec3::ZERO, Vec3::new( 1.0, 0.0, 0.0 ), false ), 0.0 );
+
+        let mut rng = Rng::new( 5, 0 );
+        let (mut front, mut back) = (0, 0);
+        for _ in 0..200 {
+            match lights.sample( Vec3::ZERO, false, &mut rng ).unwrap() {
+                LightSample::Direction(dir) => if dir.z < 0.0 { front += 1 } else { back += 1 },
+                LightSample::Delta { .. } => panic!( "no punctual lights" ),
+            }
+        }
+        assert!( front > 50 && back > 50 );
+
+        // a point light makes the area lights a third each
+        lights.add_punctual( PunctualLight::point( Vec3::new( 0.0, 3.0, 0.0 ), Vec3::from_float( 9.0 ) ) );
+        assert!( (lights.pdf( Vec3::ZERO, Vec3::new( 0.0, 0.0, -1.0 ), false ) - 1.0 / 3.0).abs() < 1e-6 );
+        let delta = (0..30).filter_map( |_| match lights.sample( Vec3::ZERO, false, &mut rng ).unwrap() {
+            LightSample::Delta { dir, dist, light, probability } => Some( (dir, dist, light, probability) ),
+            LightSample::Direction(_) => None,
+        }).next().unwrap();
+        assert_eq!( delta, (Vec3::new( 0.0, 1.0, 0.0 ), 3.0, Vec3::ONE, 1.0 / 3.0) );
+    }
+
+    #[test]
+    fn tree_picks_by_importance() {
+        let mat = Arc::new( DiffuseLight::new( Vec3::ONE ) );
+        let mut lights = LightList::new();
+
+        // 1 x 1 squares facing the origin 1 and 8 away, one beside it facing away
+        lights.add( Arc::new( Quad::new( Vec3::new( -0.5, -0.5, -1.0 ), Vec3::new( 1.0, 0.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ), mat.clone() ) ) );
+        lights.add( Arc::new( Quad::new( Vec3::new( -0.5, -0.5, 8.0 ), Vec3::new( 0.0, 1.0, 0.0 ), Vec3::new( 1.0, 0.0, 0.0 ), mat.clone() ) ) );
+        lights.add( Arc::new( Quad::new( Vec3::new( 3.0, -0.5, -0.5 ), Vec3::new( 0.0, 1.0, 0.0 ), Vec3::new( 0.0, 0.0, 1.0 ), mat ) ) );
+        lights.add_punctual( PunctualLight::point( Vec3::new( 0.0, 3.0, 0.0 ), Vec3::from_float( 9.0 ) ) );
+        lights.add_punctual( PunctualLight::directional( Vec3::new( 0.0, -1.0, 0.0 ), Vec3::ONE ) );
+
+        // 1/pdf over the samples adds up to the solid angle the two squares
+        // facing the origin cover, and 1/probability to the number of
+        // punctual lights, whichever way lights are picked
+        let solid_angle = 4.0 * (0.25 / 1.5f32.sqrt()).atan() + 4.0 * (0.25 / (8.0 * 64.5f32.sqrt())).atan();
+        let mut rng = Rng::new( 11, 0 );
+        let n = 40000;
+        for use_tree in [ false, true ] {
+            let (mut near, mut far, mut away) = (0, 0, 0);
+            let (mut area_sum, mut delta_sum) = (0.0, 0.0);
+            for _ in 0..n {
+                match lights.sample( Vec3::ZERO, use_tree, &mut rng ) {
+                    Some( LightSample::Direction(dir) ) if dir.x > 2.0 => away += 1,
+                    Some( LightSample::Direction(dir) ) => {
+                        if dir.z < 0.0 { near += 1 } else { far += 1 }
+                        area_sum += 1.0 / lights.pdf( Vec3::ZERO, dir, use_tree );
+                    },
+                    Some( LightSample::Delta { probability, .. } ) => delta_sum += 1.0 / probability,
+                    None => {},
+                }
+            }
+            let (area_sum, delta_sum) = (area_sum / n as f32, delta_sum / n as f32);
+            assert!( (area_sum / solid_angle - 1.0).abs() < 0.03, "{}: {} vs {}", use_tree, area_sum, solid_angle );
+            assert!( (delta_sum - 2.0).abs() < 0.05, "{}: {}", use_tree, delta_sum );
+
+            // the tree never aims at the square facing away, and rarely the far one
+            if use_tree {
+                assert_eq!( away, 0 );
+                assert!( near > 20 * far, "{} {}", near, far );
+            } else {
+                assert!( away > 0 && far > 0 );
+            }
+        }
+    }
+
+    #[test]
+    fn power_heuristic_weights_sum_to_one() {
+        assert_eq!( power_heuristic( 1.0, 0.0 ), 1.0 );
+        assert_eq!( power_heuristic( 0.0, 0.0 ), 0.0 );
+        assert!( (power_heuristic( 3.0, 1.0 ) - 0.9).abs() < 1e-6 );
+        assert!( (power_heuristic( 0.4, 2.5 ) + power_heuristic( 2.5, 0.4 ) - 1.0).abs() < 1e-6 );
+    }
+}
@@ -0,0 +1,393 @@
+// A bounding volume hierarchy over lights, for picking one to sample in
+// proportion to roughly how much it could light a given point (Conty
+// Estevez & Kulla 2018, as done in pbrt-v4). Every node bounds where its
+// lights are, their total power and the directions they shine in, which is
+// enough to judge a whole group at once without looking at each light.
+
+use std::f32::consts::PI;
+
+use vec3::{Axis, Vec3};
+
+use super::aabb::{Aabb, AXES};
+use super::ray::Ray;
+use super::rng::Rng;
+
+// Number of buckets the light centroids are binned into when evaluating splits
+const SPLIT_BINS : usize = 12;
+
+// Where a light, or a group of them, is, how much it gives off and which
+// way. The surface normals are within theta_o of axis and light leaves each
+// within theta_e of its normal, which is at most 90 degrees; theta_o is pi
+// for lights that shine every way.
+#[derive(Copy, Clone, Debug)]
+pub struct LightBounds {
+    pub bbox : Aabb,
+
+    // total power, as luminance; only ever compared with other lights'
+    pub power : f32,
+
+    pub axis : Vec3,
+    pub theta_o : f32,
+    pub theta_e : f32,
+}
+
+impl LightBounds {
+
+    // shining every way from somewhere in bbox
+    pub fn omni( bbox : Aabb, power : f32 ) -> LightBounds {
+        LightBounds { bbox, power, axis : Vec3::new( 0.0, 1.0, 0.0 ), theta_o : PI, theta_e : PI / 2.0 }
+    }
+
+    // a flat emitter lighting the side normal points to
+    pub fn facing( bbox : Aabb, power : f32, normal : Vec3 ) -> LightBounds {
+        LightBounds { bbox, power, axis : normal.normalize(), theta_o : 0.0, theta_e : PI / 2.0 }
+    }
+
+    fn union( a : &LightBounds, b : &LightBounds ) -> LightBounds {
+        // lights that give nothing off can't widen where the light comes from
+        if a.power <= 0.0 {
+            return *b;
+        }
+        if b.power <= 0.0 {
+            return *a;
+        }
+        let (axis, theta_o) = cone_union( a.axis, a.theta_o, b.axis, b.theta_o );
+        LightBounds {
+            bbox : Aabb::surrounding( &a.bbox, &b.bbox ),
+            power : a.power + b.power,
+            axis,
+            theta_o,
+            theta_e : a.theta_e.max( b.theta_e ),
+        }
+    }
+
+    // How much light could reach p, up to a scale shared by all lights:
+    // power over squared distance, times the cosine of the smallest angle
+    // between the emission cone and p, allowing for the lights being
+    // anywhere in the box. Zero only where none of them can reach.
+    pub fn importance( &self, p : Vec3 ) -> f32 {
+        if self.power <= 0.0 {
+            return 0.0;
+        }
+        let radius = 0.5 * self.bbox.extent().length();
+        let to_p = p - self.bbox.centroid();
+        let dist_sq = to_p.length_squared();
+
+        // close up, distance is clamped to keep it from blowing up; pbrt
+        // uses half the box's diagonal, which as a squared distance is
+        // just a guard and not a length
+        let falloff = self.power / dist_sq.max( radius ).max( 1e-8 );
+
+        // inside the bounding sphere, every direction is possible
+        if dist_sq <= radius * radius {
+            return falloff;
+        }
+
+        let dist = dist_sq.sqrt();
+        let theta_w = (Vec3::dot( &self.axis, &to_p ) / dist).clamp( -1.0, 1.0 ).acos();
+        let theta_b = (radius / dist).min( 1.0 ).asin();
+        let theta = (theta_w - self.theta_o - theta_b).max( 0.0 );
+        if theta >= self.theta_e {
+            return 0.0;
+        }
+        falloff * theta.cos()
+    }
+}
+
+// Smallest cone around both of two cones, given as axis and half angle
+fn cone_union( a : Vec3, theta_a : f32, b : Vec3, theta_b : f32 ) -> (Vec3, f32) {
+    if theta_a >= PI || theta_b >= PI {
+        return (a, PI);
+    }
+    let theta_d = Vec3::dot( &a, &b ).clamp( -1.0, 1.0 ).acos();
+    if (theta_d + theta_b).min( PI ) <= theta_a {
+        return (a, theta_a);
+    }
+    if (theta_d + theta_a).min( PI ) <= theta_b {
+        return (b, theta_b);
+    }
+
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    let turn = Vec3::cross( &a, &b );
+    if theta_o >= PI || turn.length_squared() == 0.0 {
+        return (a, PI);
+    }
+
+    // turn a towards b until the cone just reaches both
+    let theta_r = theta_o - theta_a;
+    let side = Vec3::cross( &turn.normalize(), &a );
+    (a * theta_r.cos() + side * theta_r.sin(), theta_o)
+}
+
+// Solid angle a cone of normals spreads light over, the orientation part
+// of the split cost
+fn orientation_measure( theta_o : f32, theta_e : f32 ) -> f32 {
+    let theta_w = (theta_o + theta_e).min( PI );
+    let (sin_o, cos_o) = theta_o.sin_cos();
+    2.0 * PI * (1.0 - cos_o)
+        + 0.5 * PI * (2.0 * theta_w * sin_o - (theta_o - 2.0 * theta_w).cos() - 2.0 * theta_o * sin_o + cos_o)
+}
+
+#[derive(Clone)]
+enum LightNode {
+    Leaf {
+        bounds : LightBounds,
+        light : usize,
+    },
+    Interior {
+        bounds : LightBounds,
+        left : Box<LightNode>,
+        right : Box<LightNode>,
+    },
+}
+
+impl LightNode {
+
+    fn bounds( &self ) -> &LightBounds {
+        match self {
+            LightNode::Leaf { bounds, .. } | LightNode::Interior { bounds, .. } => bounds,
+        }
+    }
+
+    fn build( mut lights : Vec<(LightBounds, usize)> ) -> LightNode {
+        if lights.len() == 1 {
+            let (bounds, light) = lights[0];
+            return LightNode::Leaf { bounds, light };
+        }
+
+        let bounds = lights[1..].iter().fold( lights[0].0, |b, (l, _)| LightBounds::union( &b, l ) );
+        let mut centroid_bounds = Aabb::EMPTY;
+        for (l, _) in &lights {
+            centroid_bounds.grow( l.bbox.centroid() );
+        }
+
+        // all in one place, any split is as good as another
+        let right = match find_split( &lights, &bounds.bbox, &centroid_bounds ) {
+            Some( (axis, split_bin) ) => {
+                let (left, right) = lights.into_iter()
+                    .partition( |(l, _)| bin_index( &centroid_bounds, axis, l.bbox.centroid() ) < split_bin );
+                lights = left;
+                right
+            },
+            None => lights.split_off( lights.len() / 2 ),
+        };
+
+        LightNode::Interior {
+            bounds,
+            left : Box::new( LightNode::build( lights ) ),
+            right : Box::new( LightNode::build( right ) ),
+        }
+    }
+}
+
+fn bin_index( centroid_bounds : &Aabb, axis : Axis, centroid : Vec3 ) -> usize {
+    let lo = centroid_bounds.min.component( axis );
+    let extent = centroid_bounds.max.component( axis ) - lo;
+    (((centroid.component( axis ) - lo) / extent * SPLIT_BINS as f32) as usize).min( SPLIT_BINS - 1 )
+}
+
+// Cost of a group in a split: power, times the spread of its directions,
+// times its surface area (the surface area orientation heuristic)
+fn split_cost( bounds : &LightBounds ) -> f32 {
+    bounds.power * orientation_measure( bounds.theta_o, bounds.theta_e ) * bounds.bbox.surface_area()
+}
+
+// Cheapest axis and bin to split at, the lights in bins below it going
+// left. None if the centroids can't be told apart.
+fn find_split( lights : &[(LightBounds, usize)], bbox : &Aabb, centroid_bounds : &Aabb ) -> Option<(Axis, usize)> {
+    let extent = bbox.extent();
+    let max_extent = extent.x.max( extent.y ).max( extent.z );
+
+    let mut best = None;
+    let mut best_cost = f32::INFINITY;
+    for axis in AXES {
+        if centroid_bounds.max.component( axis ) <= centroid_bounds.min.component( axis ) {
+            continue;
+        }
+
+        let mut bins : [Option<LightBounds>; SPLIT_BINS] = [None; SPLIT_BINS];
+        for (l, _) in lights {
+            let bin = &mut bins[bin_index( centroid_bounds, axis, l.bbox.centroid() )];
+            *bin = Some( bin.map_or( *l, |b| LightBounds::union( &b, l ) ) );
+        }
+
+        // everything below each split, then everything above it
+        let merge = |acc : Option<LightBounds>, bin : &Option<LightBounds>| match (acc, bin) {
+            (Some(a), Some(b)) => Some( LightBounds::union( &a, b ) ),
+            (a, b) => a.or( *b ),
+        };
+        let mut below = [None; SPLIT_BINS];
+        let mut acc = None;
+        for (sum, bin) in below.iter_mut().zip( &bins ) {
+            acc = merge( acc, bin );
+            *sum = acc;
+        }
+
+        // stretched boxes split across their length are favoured
+        let stretch = max_extent / extent.component( axis );
+        let mut above = None;
+        for split in (1..SPLIT_BINS).rev() {
+            above = merge( above, &bins[split] );
+            if let (Some(lo), Some(hi)) = (below[split - 1], above) {
+                let cost = stretch * (split_cost( &lo ) + split_cost( &hi ));
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some( (axis, split) );
+                }
+            }
+        }
+    }
+    best
+}
+
+// The tree, over lights handed in as their bounds and an index that
+// sampling reports back
+#[derive(Clone, Default)]
+pub struct LightTree {
+    root : Option<LightNode>,
+}
+
+impl LightTree {
+
+    pub fn new( lights : Vec<(LightBounds, usize)> ) -> LightTree {
+        LightTree { root : if lights.is_empty() { None } else { Some( LightNode::build( lights ) ) } }
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.root.is_none()
+    }
+
+    // Walks down from the root, taking each child in proportion to its
+    // importance at p. Returns the light picked and the chance of picking
+    // it, or None if no light can reach p.
+    pub fn sample( &self, p : Vec3, rng : &mut Rng ) -> Option<(usize, f32)> {
+        let mut node = self.root.as_ref()?;
+        let mut probability = 1.0;
+        loop {
+            match node {
+                LightNode::Leaf { bounds, light } => {
+                    return if bounds.importance( p ) > 0.0 { Some( (*light, probability) ) } else { None };
+                },
+                LightNode::Interior { left, right, .. } => {
+                    let (l, r) = (left.bounds().importance( p ), right.bounds().importance( p ));
+                    if l + r <= 0.0 {
+                        return None;
+                    }
+                    if rng.next_f32() * (l + r) < l {
+                        node = left;
+                        probability *= l / (l + r);
+                    } else {
+                        node = right;
+                        probability *= r / (l + r);
+                    }
+                },
+            }
+        }
+    }
+
+    // Sum over the lights of the chance sample() picks each from p, times
+    // density( light ). Only lights whose bounds the ray from p along dir
+    // passes through are visited, so density should be zero for the rest.
+    pub fn pdf( &self, p : Vec3, dir : Vec3, density : impl Fn( usize ) -> f32 ) -> f32 {
+        let root = match &self.root {
+            Some(root) => root,
+            None => return 0.0,
+        };
+        if let LightNode::Leaf { bounds, light } = root {
+            return if bounds.importance( p ) > 0.0 { density( *light ) } else { 0.0 };
+        }
+        let ray = Ray::new( p, dir );
+        pdf_below( root, &ray, &(Vec3::ONE / dir), 1.0, &density )
+    }
+}
+
+fn pdf_below( node : &LightNode, ray : &Ray, inv_dir : &Vec3, probability : f32, density : &dyn Fn( usize ) -> f32 ) -> f32 {
+    match node {
+        LightNode::Leaf { light, .. } => probability * density( *light ),
+        LightNode::Interior { left, right, .. } => {
+            let (l, r) = (left.bounds().importance( ray.origin ), right.bounds().importance( ray.origin ));
+            let mut pdf = 0.0;
+            for (child, importance) in [ (left, l), (right, r) ] {
+                if importance > 0.0 && child.bounds().bbox.hit( ray, inv_dir, 0.0, f32::INFINITY ) {
+                    pdf += pdf_below( child, ray, inv_dir, probability * importance / (l + r), density );
+                }
+            }
+            pdf
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point( p : Vec3, power : f32 ) -> LightBounds {
+        LightBounds::omni( Aabb::new( p, p ), power )
+    }
+
+    #[test]
+    fn importance() {
+        // falls off with distance, scales with power
+        let light = point( Vec3::ZERO, 4.0 );
+        assert_eq!( light.importance( Vec3::new( 0.0, 2.0, 0.0 ) ), 1.0 );
+        assert_eq!( point( Vec3::ZERO, 8.0 ).importance( Vec3::new( 0.0, 0.0, -4.0 ) ), 0.5 );
+
+        // a panel facing down lights nothing above it, a little to the side
+        let bbox = Aabb::new( Vec3::new( -0.5, 2.0, -0.5 ), Vec3::new( 0.5, 2.0, 0.5 ) ).padded( 1e-4 );
+        let panel = LightBounds::facing( bbox, 1.0, Vec3::new( 0.0, -1.0, 0.0 ) );
+        assert!( panel.importance( Vec3::ZERO ) > 0.0 );
+        assert!( panel.importance( Vec3::new( 3.0, 1.9, 0.0 ) ) > 0.0 );
+        assert_eq!( panel.importance( Vec3::new( 0.0, 4.0, 0.0 ) ), 0.0 );
+
+        // which still counts once it's grouped with one facing up
+        let up = LightBounds::facing( bbox, 1.0, Vec3::new( 0.0, 1.0, 0.0 ) );
+        let both = LightBounds::union( &panel, &up );
+        assert!( both.importance( Vec3::new( 0.0, 4.0, 0.0 ) ) > 0.0 );
+        assert_eq!( both.power, 2.0 );
+
+        // grouped with one facing sideways, the normals are in a cone halfway between
+        let side = LightBounds::facing( bbox, 1.0, Vec3::new( 1.0, 0.0, 0.0 ) );
+        let both = LightBounds::union( &panel, &side );
+        assert!( (both.theta_o - PI / 4.0).abs() < 1e-5 );
+        assert!( (both.axis - Vec3::new( 1.0, -1.0, 0.0 ).normalize()).length() < 1e-5 );
+    }
+
+    #[test]
+    fn sampling_matches_probabilities() {
+        // a row of lights, brighter further along
+        let lights : Vec<(LightBounds, usize)> = (0..40)
+            .map( |i| {
+                let c = Vec3::new( i as f32, 1.0, 0.0 );
+                (LightBounds::omni( Aabb::new( c, c ).padded( 0.01 ), 1.0 + i as f32 ), i)
+            })
+            .collect();
+        let tree = LightTree::new( lights );
+        let p = Vec3::new( 5.0, 0.0, 0.0 );
+
+        // the chances add up to one, and are what sample() reports
+        let mut rng = Rng::new( 7, 0 );
+        let mut counts = [0u32; 40];
+        let mut reported = [0.0f32; 40];
+        let n = 100000;
+        for _ in 0..n {
+            let (light, probability) = tree.sample( p, &mut rng ).unwrap();
+            counts[light] += 1;
+            reported[light] = probability;
+        }
+        let total : f32 = reported.iter().sum();
+        assert!( (total - 1.0).abs() < 1e-4, "{}", total );
+        for i in 0..40 {
+            let seen = counts[i] as f32 / n as f32;
+            assert!( (seen - reported[i]).abs() < 0.01, "light {}: {} vs {}", i, seen, reported[i] );
+        }
+
+        // nearby lights are picked more often than far ones
+        assert!( reported[5] > 10.0 * reported[39] );
+
+        // pdf() gives the same chance for the one light the ray passes
+        let dir = Vec3::new( 2.0, 1.0, 0.0 );
+        assert!( (tree.pdf( p, dir, |i| if i == 7 { 1.0 } else { 0.0 } ) - reported[7]).abs() < 1e-6 );
+        assert_eq!( tree.pdf( p, -dir, |_| 1.0 ), 0.0 );
+        assert!( LightTree::new( Vec::new() ).sample( p, &mut rng ).is_none() );
+    }
+}
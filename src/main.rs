@@ -1,5 +1,7 @@
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread;
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc};
 
 use slint::SharedPixelBuffer;
 use slint::Rgb8Pixel;
@@ -7,98 +9,50 @@ use slint::Image;
 use slint::Timer;
 use slint::TimerMode;
 
-use vec3::Vec3;
-
 pub mod ray;
-use ray::Ray;
 
 pub mod scene;
 use scene::Scene;
 
-#[warn(non_snake_case)]
-
-enum TileStatus {
-    Clear, // Reset or clear the tile
-    //Rendering,
-    //Denoising,
-    Finished
-} 
-
-struct Tile {
-    x : u32,
-    y : u32,
-    w : u32,
-    h : u32,
-    //status : TileStatus,
-    pixels : Option<SharedPixelBuffer<Rgb8Pixel>>,
-}
-
-pub struct HitRecord {
-    p : Vec3,
-    normal : Vec3,
-    t: f32,
-    front_face : bool,
-}
-
-pub trait Hittable {
-    fn hit( &self, ray : Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord>;
-}
-
-pub struct Sphere {
-    center : Vec3,
-    radius : f32,
-}
-
-// fn face_normal( ray : Ray, out_n : Vec3 ) -> Vec3 
-// {
-//     let front_ Vec3::dot( ray.dir, out_n ) < 0.0 { }
-// }
-
-impl Hittable for Sphere {
-    fn hit( &self, ray : Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
-        let a = ray.dir.length_squared();
-        let half_b = Vec3::dot( &oc, &ray.dir );
-        let c = oc.length_squared() - self.radius*self.radius;
-
-        let discriminant = half_b*half_b - a*c;
-        if (discriminant < 0.0) {
-            return None;
-        }
-        let sqrtd = discriminant.sqrt();
-
-        let root = (-half_b - sqrtd) / a;
-        if (root <= ray_tmin || ray_tmax <= root) {
-            let root = (-half_b + sqrtd) / a;
-            if (root <= ray_tmin || ray_tmax <= root) {
-                return None;
-            }
-        }
-
-        
-
-        let p = ray.at(  root );
-        let outward_n =  (p - self.center) / self.radius;
-        let mut front_face : bool;
-        let mut nrm;
-        if (Vec3::dot( &ray.dir, &outward_n ) > 0.0) {
-            nrm = -outward_n;
-            front_face = false;
-        } else {
-            nrm = outward_n;
-            front_face = true;
-        }
-
-        Some(HitRecord {
-            t : root,
-            p : p,
-            front_face : front_face,
-            normal: nrm
-            
-        })
-    }
-}
-
+pub mod hittable;
+pub mod sphere;
+pub mod quad;
+pub mod aabb;
+pub mod bvh;
+pub mod mesh;
+pub mod obj;
+pub mod material;
+pub mod microfacet;
+pub mod light;
+pub mod light_tree;
+pub mod environment;
+pub mod sky;
+pub mod punctual;
+pub mod ies;
+pub mod rng;
+pub mod camera;
+pub mod film;
+pub mod tonemap;
+pub mod render;
+pub mod scheduler;
+pub mod tile_order;
+pub mod overlay;
+pub mod denoise;
+pub mod aov;
+pub mod cli;
+pub mod image_io;
+pub mod exr;
+pub mod hdr_image;
+pub mod scene_file;
+use tonemap::{DisplayTransform, ToneMap};
+use tile_order::TileOrder;
+use overlay::TileOverlay;
+use denoise::DenoiseMode;
+use aov::Aov;
+use render::{RenderJob, TileStatus};
+use cli::Options;
+
+#[allow(dead_code)]
 fn mk_col32( r : f32, g : f32, b : f32 ) -> u32 {
     let r = ((r * 255.0) as u32) & 0xff;
     let g = ((g * 255.0) as u32) & 0xff;
@@ -108,252 +62,339 @@ fn mk_col32( r : f32, g : f32, b : f32 ) -> u32 {
     (r << 16) | (g << 8) | b
 }
 
-fn ray_color( ray: &Ray ) -> Vec3 {
-
-    let t = hit_sphere( &Vec3::new(0.0, 0.0, -1.0), 0.5, ray);
-    if t > 0.0 { 
+// Builds the scene named on the command line and applies the render overrides
+fn build_scene( opts : &Options ) -> Result<Scene, String> {
+    let path = Path::new( &opts.scene );
+    let is_obj = path.extension().is_some_and( |e| e.eq_ignore_ascii_case( "obj" ) );
 
-        let N = Vec3::normalize( ray.at(t) - Vec3::new( 0.0, 0.0, -1.0 ) );
-        return 0.5 * Vec3::new( N.x+1.0, N.y+1.0, N.z+1.0 );   
-    }
+    let mut scene = if opts.scene == "demo" || is_obj {
+        let mut scene = Scene::demo( opts.width.unwrap_or( 320 ), opts.height.unwrap_or( 200 ) );
 
-    let unit_dir = ray.dir.normalize();    
-    let a = 0.5 * unit_dir.y + 1.0;
-    
-    (1.0-a)*Vec3::ONE + a*Vec3::new( 0.5, 0.7, 1.0)
-}
-
-fn hit_sphere( center : &Vec3, radius : f32, ray : &Ray ) -> f32 {
-    let oc = ray.origin - center;
-    let a = ray.dir.length_squared();
-    //let a = Vec3::dot( &ray.dir, &ray.dir );
-    let half_b = Vec3::dot( &oc, &ray.dir );
-    //let c =  Vec3::dot( &oc, &oc ) - radius*radius;
-    let c = oc.length_squared() - radius*radius;
-    //let discriminant = b*b - 4.0*a*c;
-    let discriminant = half_b*half_b - a*c;
-
-    
-    if discriminant < 0.0 {
-        return -1.0;
+        // OBJ files are dropped into the demo scene
+        if is_obj {
+            let num_tris = scene.load_obj( path ).map_err( |err| err.to_string() )?;
+            println!( "Loaded {} triangles from {}", num_tris, opts.scene );
+            scene.build_bvh();
+        }
+        scene
     } else {
-        //return (-b - discriminant.sqrt()) / (2.0*a);
-        return (-half_b - discriminant.sqrt()) / a;
+        scene_file::load_scene( path ).map_err( |err| err.to_string() )?
+    };
+
+    // command line wins over the scene file
+    if opts.width.is_some() || opts.height.is_some() {
+        let width = opts.width.unwrap_or( scene.camera.width );
+        let height = opts.height.unwrap_or( scene.camera.height );
+        scene.camera = scene.camera.resized( width, height );
     }
-}
 
-fn do_render(width: u32, height: u32, buffer: &mut [u8]) {
-    
+    if let Some(spp) = opts.spp {
+        scene.settings.samples_per_pixel = spp;
+    }
+    if let Some(max_depth) = opts.max_depth {
+        scene.settings.max_depth = max_depth;
+    }
+    if let Some(seed) = opts.seed {
+        scene.settings.seed = seed;
+    }
+    if let Some(time_limit) = opts.time_limit {
+        scene.settings.time_limit = time_limit;
+    }
+    if let Some(tile_size) = opts.tile_size {
+        scene.settings.tile_size = tile_size;
+    }
+    if let Some(tile_order) = opts.tile_order {
+        scene.settings.tile_order = tile_order;
+    }
+    if let Some(denoise) = opts.denoise {
+        scene.settings.denoise = denoise;
+    }
+    if let Some(env) = &opts.env {
+        let env_map = hdr_image::load( env ).and_then( |image| environment::EnvMap::new( image, 0.0, 1.0 ) )
+            .map_err( |err| format!( "{}: {}", env.display(), err ) )?;
+        scene.set_environment( Arc::new( env_map ) );
+    }
+    if let Some( (sun_elevation, sun_azimuth) ) = opts.sun {
+        scene.set_environment( Arc::new( sky::PreethamSky::new( &sky::SkySettings { sun_elevation, sun_azimuth, ..Default::default() } ) ) );
+    }
+    if opts.no_sky {
+        scene.settings.sky = false;
+    }
+    if opts.brute_force {
+        scene.settings.light_sampling = false;
+    }
+    if opts.uniform_lights {
+        scene.settings.light_tree = false;
+    }
+    scene.drop_environment_if_sky_off();
 
-    let scene = Scene::new( width, height );
+    Ok( scene )
+}
 
+// Renders with the worker threads but no window, then writes the image
+fn render_headless( opts : &Options, scene : Scene, output : &Path ) -> Result<(), String> {
 
-    for j in 0..height {        
-        for i in 0..width {            
-            let ndx : usize = usize::try_from((j*width+i) * 3).unwrap();
+    // same passes as the viewer, so both give the same image
+    let (tx_done_tiles, rx_done_tiles) = mpsc::channel();
+    let mut job = RenderJob::start( Arc::new( scene ), opts.threads, 0, tx_done_tiles );
 
-            
-            let ray = scene.ray_at_pixel_loc( i as i32, j as i32);
+    // the channel closes once the last pass is in and the workers have exited
+    for tile in rx_done_tiles.iter() {
+        job.tile_done( &tile );
+        eprint!( "\r{}    ", job.status() );
+    }
+    eprintln!();
 
-            let col = ray_color( &ray );
+    if !job.is_finished() {
+        return Err( "render stopped before the last pass finished".to_string() );
+    }
+    eprintln!( "Threads: {}", job.utilization_summary() );
+    for (i, util) in job.utilization().iter().enumerate() {
+        eprintln!( "  thread {:3}: {:5} tiles, {:.2}s busy, {:3.0}%", i, util.tiles, util.busy.as_secs_f32(), util.fraction * 100.0 );
+    }
+    let mut stats = job.stats();
+    stats.push( ( "scene".to_string(), opts.scene.clone() ) );
+    let film = job.join()?;
+
+    // how far off a reference, to compare ways of sampling at the same spp
+    if let Some(reference) = &opts.compare {
+        let reference_image = hdr_image::load( reference ).map_err( |err| format!( "{}: {}", reference.display(), err ) )?;
+        let image = hdr_image::HdrImage::from_film( &film, Aov::Beauty );
+        let mse = image.mse( &reference_image ).ok_or_else( || format!( "{}: is {}x{}, the render is {}x{}",
+            reference.display(), reference_image.width, reference_image.height, image.width, image.height ) )?;
+        eprintln!( "MSE against {}: {:.6}", reference.display(), mse );
+    }
 
+    // an EXR holds everything, AOVs as layers
+    if output.extension().is_some_and( |e| e.eq_ignore_ascii_case( "exr" ) ) {
+        exr::write_exr_file( output, &film, &opts.aovs, opts.exr_pixel, &stats )
+            .map_err( |err| format!( "{}: {}", output.display(), err ) )?;
+        eprintln!( "Wrote {}", output.display() );
+        return Ok( () );
+    }
 
-            buffer[ndx+0] = (col.x * 255.0) as u8;
-            buffer[ndx+1] = (col.y * 255.0) as u8;
-            buffer[ndx+2] = (col.z * 255.0) as u8;
-        }
+    let xform = DisplayTransform { exposure : opts.exposure, tone_map : opts.tone_map };
+    image_io::write_image( output, &film, Aov::Beauty, &xform )
+        .map_err( |err| format!( "{}: {}", output.display(), err ) )?;
+    eprintln!( "Wrote {}", output.display() );
+
+    // extra passes next to it, out.png -> out.depth.png
+    for aov in &opts.aovs {
+        let stem = output.file_stem().and_then( |s| s.to_str() ).unwrap_or( "" );
+        let ext = output.extension().and_then( |e| e.to_str() ).unwrap_or( "" );
+        let path = output.with_file_name( format!( "{}.{}.{}", stem, aov.name(), ext ) );
+        image_io::write_image( &path, &film, *aov, &xform )
+            .map_err( |err| format!( "{}: {}", path.display(), err ) )?;
+        eprintln!( "Wrote {}", path.display() );
     }
-    
-}
 
-// todo: replace with better
-fn rand_hash( x : f32, y : f32, z : f32 ) -> f32{
-    (Vec3::dot( 
-        &Vec3::new( x,y,z ),
-        &Vec3::new( 1.0, 113.0, 21.5) ).sin() * 43758.5453123 ).fract()
+    Ok( () )
 }
 
-fn render_tile( scene : &Scene, tile : &mut Tile ) {
-    let mut tile_px = SharedPixelBuffer::<Rgb8Pixel>::new( tile.w, tile.h);
-    
-    let buffer = tile_px.make_mut_bytes();
-
-    // "random" color per tile
-    // return fract(sin(dot(p, vec2(1.0,113.0)))*43758.5453123);
-
-    // let col = Vec3::new( 
-    //     rand_hash(tile.x as f32, tile.y as f32, 0.0),
-    //     rand_hash(tile.x as f32, tile.y as f32, 1.0),
-    //     rand_hash(tile.x as f32, tile.y as f32, 2.0) );
-
-    for j in 0..tile.h {                
-        for i in 0..tile.w {
-            
-            let ndx : usize = usize::try_from((j*tile.w+i) * 3).unwrap();
-
-            let ray = scene.ray_at_pixel_loc( (tile.x + i) as i32, (tile.y + j) as i32);
-            let col = ray_color( &ray );
+fn main() {
 
-            buffer[ndx+0] = (col.x * 255.0) as u8;
-            buffer[ndx+1] = (col.y * 255.0) as u8;
-            buffer[ndx+2] = (col.z * 255.0) as u8;
+    let opts = match Options::parse( std::env::args().skip( 1 ) ) {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!( "Error: {}\n\n{}", err, cli::USAGE );
+            std::process::exit( 2 );
         }
+    };
+    if opts.help {
+        print!( "{}", cli::USAGE );
+        return;
     }
 
-    tile.pixels = Some( tile_px );
+    // Set up the scene class. No mutex needed since we won't ever modify it from a render thread.
+    let scene = match build_scene( &opts ) {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!( "Error: {}", err );
+            std::process::exit( 1 );
+        }
+    };
 
-    // todo return Result
-}
+    if let Some(output) = &opts.output {
+        if let Err(err) = render_headless( &opts, scene, output ) {
+            eprintln!( "Error: {}", err );
+            std::process::exit( 1 );
+        }
+        return;
+    }
 
-fn ceil_div( a : u32, b : u32 ) -> u32 {
-    (a + b - 1 ) / b
+    run_viewer( &opts, scene );
 }
 
-fn main() {
+fn run_viewer( opts : &Options, scene : Scene ) {
 
     //MainWindow::new().unwrap().run().unwrap();
     let main_window = MainWindow::new().unwrap();
 
-    let mut pixel_buffer = SharedPixelBuffer::<Rgb8Pixel>::new(320, 200);
-
-    let tile_sz = 32;
-    let W = pixel_buffer.width();
-    let H = pixel_buffer.height();
-    let num_tiles_x = ceil_div( W, tile_sz);
-    let num_tiles_y = ceil_div(H, tile_sz);
-    println!("Num tiles {} x {} img {} x {}", num_tiles_x, num_tiles_y, 
-                num_tiles_x * tile_sz, num_tiles_y * tile_sz );
-
-    // do_render(pixel_buffer.width(), pixel_buffer.height(),
-    //                 pixel_buffer.make_mut_bytes());
-
+    let (width, height) = (scene.camera.width, scene.camera.height);
+    let pixel_buffer = SharedPixelBuffer::<Rgb8Pixel>::new( width, height );
 
     let image = Image::from_rgb8(pixel_buffer.clone() );
     main_window.set_render_img( image );
 
-    // queue for tiles to render
-    let (tx_todo_tiles, rx_todo_tiles ) = mpsc::channel();
-
-    for tj in 0..num_tiles_y {
-        for ti in 0..num_tiles_x {
-            let tile = Tile {
-                    x : ti * tile_sz,
-                    y : tj * tile_sz,
-                    w : std::cmp::min( tile_sz, W.checked_sub( (ti+0)*tile_sz).unwrap_or(0) ),
-                    h : std::cmp::min( tile_sz, H.checked_sub( (tj+0)*tile_sz).unwrap_or(0) ),
-                    //status: TileStatus::Clear,
-                    pixels : None
-            };
-
-            tx_todo_tiles.send( tile );
-        }
-    }
-
-    // queue for finished tiles
+    // Finished tiles accumulate into the job's film, one AOV of which is
+    // shown through the display transform. All of these live on the UI thread.
+    let pixel_buffer = Rc::new( RefCell::new( pixel_buffer ) );
+    let display = Rc::new( RefCell::new( DisplayTransform { exposure : opts.exposure, tone_map : opts.tone_map } ) );
+    let shown_aov = Rc::new( Cell::new( opts.aovs.first().copied().unwrap_or( Aov::Beauty ) ) );
+
+    main_window.set_tonemap_names( slint::ModelRc::new( slint::VecModel::from(
+        ToneMap::ALL.iter().map( |tm| slint::SharedString::from( tm.name() ) ).collect::<Vec<_>>() ) ) );
+    main_window.set_tonemap_index( ToneMap::ALL.iter().position( |tm| *tm == opts.tone_map ).unwrap_or( 0 ) as i32 );
+    main_window.set_exposure( opts.exposure );
+
+    main_window.set_tile_order_names( slint::ModelRc::new( slint::VecModel::from(
+        TileOrder::ALL.iter().map( |o| slint::SharedString::from( o.name() ) ).collect::<Vec<_>>() ) ) );
+    main_window.set_tile_order_index( TileOrder::ALL.iter().position( |o| *o == scene.settings.tile_order ).unwrap_or( 0 ) as i32 );
+    main_window.set_denoise_names( slint::ModelRc::new( slint::VecModel::from(
+        DenoiseMode::ALL.iter().map( |m| slint::SharedString::from( m.name() ) ).collect::<Vec<_>>() ) ) );
+    main_window.set_denoise_index( DenoiseMode::ALL.iter().position( |m| *m == scene.settings.denoise ).unwrap_or( 0 ) as i32 );
+    main_window.set_aov_names( slint::ModelRc::new( slint::VecModel::from(
+        Aov::ALL.iter().map( |a| slint::SharedString::from( a.name() ) ).collect::<Vec<_>>() ) ) );
+    main_window.set_aov_index( Aov::ALL.iter().position( |a| *a == shown_aov.get() ).unwrap_or( 0 ) as i32 );
+
+    // queue for finished tiles, shared by every job so restarts can reuse it
     let (tx_done_tiles, rx_done_tiles) = mpsc::channel();
 
-    // Wrap the todo channel in an Arc and a Mutex
-    let rx_todo_tiles = Arc::new(Mutex::new(rx_todo_tiles));
-
-    // Set up the scene class. No mutex needed since we won't ever modify it from a render thread.
-    let scene = Arc::new( Scene::new( W, H ) );
-    
-
-
-    // spawn a thread to render the tiles
-    let num_threads = 8;
-    for i in 0..num_threads {
-        let tx_done_tiles2 = tx_done_tiles.clone();
-
-        let rx_todo_clone = Arc::clone(&rx_todo_tiles);
-        let scene_clone = scene.clone();
-        thread::spawn( move || {
-
-            let mut rx_todo = rx_todo_clone.lock().unwrap();
+    // spawn threads to render the tiles
+    println!( "Rendering img {} x {}", width, height );
+    let job = Rc::new( RefCell::new( RenderJob::start( Arc::new( scene ), opts.threads, 0, tx_done_tiles.clone() ) ) );
+
+    // tone map, exposure or pass changed, redo the whole display image
+    {
+        let ui_handle = main_window.as_weak();
+        let job = job.clone();
+        let pixel_buffer = pixel_buffer.clone();
+        let display = display.clone();
+        let shown_aov = shown_aov.clone();
+        main_window.on_display_changed( move || {
+            let ui = ui_handle.unwrap();
+            let xform = DisplayTransform {
+                exposure : ui.get_exposure(),
+                tone_map : ToneMap::ALL[ ui.get_tonemap_index().clamp( 0, ToneMap::ALL.len() as i32 - 1 ) as usize ],
+            };
+            *display.borrow_mut() = xform;
+            shown_aov.set( Aov::ALL[ ui.get_aov_index().clamp( 0, Aov::ALL.len() as i32 - 1 ) as usize ] );
 
-            //for tile in rx_todo_tiles2 {
-            while let Ok(tile) = rx_todo.recv() {
+            let buffer = job.borrow().film().to_pixel_buffer( shown_aov.get(), &xform );
+            ui.set_render_img( Image::from_rgb8( buffer.clone() ) );
+            *pixel_buffer.borrow_mut() = buffer;
+        });
+    }
 
-                drop(rx_todo); // release the mutex
+    {
+        let ui_handle = main_window.as_weak();
+        let job = job.clone();
+        main_window.on_pause_toggled( move || {
+            let mut job = job.borrow_mut();
+            let paused = !job.is_paused();
+            job.set_paused( paused );
+            ui_handle.unwrap().set_paused( job.is_paused() );
+        });
+    }
+    {
+        let job = job.clone();
+        main_window.on_cancel_clicked( move || job.borrow_mut().cancel() );
+    }
 
-                println!("Got tile {} {} in render thread", tile.x, tile.y );
+    // reload the scene (the file may have been edited) and render it from scratch
+    {
+        let ui_handle = main_window.as_weak();
+        let job = job.clone();
+        let pixel_buffer = pixel_buffer.clone();
+        let display = display.clone();
+        let shown_aov = shown_aov.clone();
+        let opts = opts.clone();
+        main_window.on_restart_clicked( move || {
+            let ui = ui_handle.unwrap();
+            let mut scene = match build_scene( &opts ) {
+                Ok(scene) => scene,
+                Err(err) => {
+                    eprintln!( "Error: {}", err );
+                    ui.set_status( format!( "error: {}", err ).into() );
+                    return;
+                }
+            };
 
-                // render tile
-                // TODO: reuse tile?
-                let mut rndrTile = Tile { 
-                    // status : TileStatus::Finished,
-                    x : tile.x, 
-                    y : tile.y,
-                    w : tile.w,
-                    h : tile.h,
-                    pixels: None
-                };
-                
-                let scene : &Scene = Arc::as_ref( &scene_clone );
-                render_tile( scene, &mut rndrTile );
+            // what's picked in the UI wins over the command line
+            scene.settings.tile_order = TileOrder::ALL[ ui.get_tile_order_index().clamp( 0, TileOrder::ALL.len() as i32 - 1 ) as usize ];
+            scene.settings.denoise = DenoiseMode::ALL[ ui.get_denoise_index().clamp( 0, DenoiseMode::ALL.len() as i32 - 1 ) as usize ];
+
+            // the new job starts with an empty film, maybe of a new size
+            let mut job = job.borrow_mut();
+            job.restart( Arc::new( scene ), opts.threads, tx_done_tiles.clone() );
+            let buffer = job.film().to_pixel_buffer( shown_aov.get(), &display.borrow() );
+            ui.set_render_img( Image::from_rgb8( buffer.clone() ) );
+            *pixel_buffer.borrow_mut() = buffer;
+            ui.set_paused( false );
+        });
+    }
 
-                thread::sleep(std::time::Duration::from_millis(250));      
-                //println!( $"Rendered tile, pixels is {")
-                
-                tx_done_tiles2.send( rndrTile );
 
-                // reaquire the lock on the todo list
-                rx_todo = rx_todo_clone.lock().unwrap();
-            }        
-            print!("Thread {i} finished...");
+    // everything the film has, as a multi-layer EXR named after the scene
+    {
+        let ui_handle = main_window.as_weak();
+        let job = job.clone();
+        let opts = opts.clone();
+        main_window.on_save_clicked( move || {
+            let job = job.borrow();
+            let stem = Path::new( &opts.scene ).file_stem().and_then( |s| s.to_str() ).unwrap_or( "render" );
+            let path = Path::new( stem ).with_extension( "exr" );
+            let mut stats = job.stats();
+            stats.push( ( "scene".to_string(), opts.scene.clone() ) );
+            let message = match exr::write_exr_file( &path, job.film(), &Aov::ALL, opts.exr_pixel, &stats ) {
+                Ok(()) => format!( "Wrote {}", path.display() ),
+                Err(err) => format!( "Error: {}: {}", path.display(), err ),
+            };
+            eprintln!( "{}", message );
+            ui_handle.unwrap().set_save_message( message.into() );
         });
     }
 
-
     // Set up a timer to update the tiles
     let ui_handle = main_window.as_weak();
     let timer = Timer::default();
+    let mut overlay = TileOverlay::new();
     timer.start( TimerMode::Repeated, 
         std::time::Duration::from_millis(200), move || {
             let ui = ui_handle.unwrap();
-            println!("Ping from timer...");
 
-            //for tile in rx_done_tiles {
-            //    println!("got tile {}, {}", tile.x, tile.y );
-            //}    
-
-            
-                
-            //let tile = rx_done_tiles.try_recv();            
-            //println!("Got tile {}, {} in update", tile.x, tile.y );
-            
+            let mut job = job.borrow_mut();
+            let mut changed = !overlay.is_empty();
             while let Ok(tile) = rx_done_tiles.try_recv() {
 
-                if tile.pixels.is_some() 
-                {                    
-                    let stride = pixel_buffer.width();
-                    let buff = pixel_buffer.make_mut_bytes();
-
-                    let mut tileImg = tile.pixels.unwrap();
-                    let tw = tileImg.width();
-                    let th = tileImg.height();
-                    let tilebuf = tileImg.make_mut_bytes();
-
-                    // for ij copy tilebug into buff
-                    println!("draw tile at {}, {} sz {} {}", tile.x, tile.y, tile.w, tile.h );
-                    for j in 0..th {
-                        for i in 0..tw {
-                            let ndx: usize = ((((stride * (j+tile.y)) + (i+tile.x))) * 3) as usize;
-                            let tile_ndx: usize = ((j * tw + i) * 3 + 0) as usize;
-
-                            buff[ ndx + 0 ] = tilebuf[  tile_ndx + 0 ];
-                            buff[ ndx + 1 ] = tilebuf[  tile_ndx + 1 ];
-                            buff[ ndx + 2 ] = tilebuf[  tile_ndx + 2 ];
-                        }
-                    }
+                // left over from a cancelled job
+                if !job.tile_done( &tile ) {
+                    continue;
+                }
+                overlay.update( &tile );
+                changed = true;
 
-                    let upd_buffer = pixel_buffer.clone();                
-                    let image = Image::from_rgb8( upd_buffer );
+                if tile.status == TileStatus::Finished
+                {
+                    let mut pixel_buffer = pixel_buffer.borrow_mut();
+                    job.film().update_pixel_buffer( &mut pixel_buffer, tile.x, tile.y, tile.w, tile.h, shown_aov.get(), &display.borrow() );
+                }
+            }
+
+            // tiles of a cancelled job never finish, don't leave their marks up
+            if job.is_finished() {
+                overlay.clear();
+            }
 
-                    // let image = Image::from_rgb8( tile.pixels.unwrap() );
-                    ui.set_render_img( image );      
+            if changed {
+                // marks go on a copy, the pixel buffer only ever holds the image
+                let mut upd_buffer = pixel_buffer.borrow().clone();
+                if ui.get_show_overlay() {
+                    overlay.draw( &mut upd_buffer );
                 }
-            }                  
+                ui.set_render_img( Image::from_rgb8( upd_buffer ) );
+            }
+            ui.set_status( format!( "{} | {}", job.status(), job.utilization_summary() ).into() );
         });
 
 
@@ -362,11 +403,35 @@ fn main() {
 }
 
 slint::slint! {
-    import { VerticalBox, HorizontalBox, Button } from "std-widgets.slint";
+    import { VerticalBox, HorizontalBox, Button, CheckBox, ComboBox, Slider } from "std-widgets.slint";
 
     export component MainWindow inherits Window {
         in property render-img <=> render.source;
 
+        // display transform
+        in property <[string]> tonemap-names;
+        in-out property <int> tonemap-index: 0;
+        in-out property <float> exposure: 0.0;
+        callback display-changed();
+
+        // render progress and control
+        in property <string> status;
+        in property <bool> paused;
+        callback pause-toggled();
+        callback cancel-clicked();
+        callback restart-clicked();
+        callback save-clicked();
+        in property <string> save-message;
+        in-out property <bool> show-overlay: true;
+        in property <[string]> tile-order-names;
+        in-out property <int> tile-order-index: 0;
+        in property <[string]> denoise-names;
+        in-out property <int> denoise-index: 0;
+
+        // which AOV is shown
+        in property <[string]> aov-names;
+        in-out property <int> aov-index: 0;
+
         VerticalBox {
 
             spacing: 0;
@@ -384,6 +449,64 @@ slint::slint! {
                     Button { text: "scene"; width: 40px;}                    
                     Button { text: "view"; }                    
                     Button { text: "insp"; }
+                    Button {
+                        text: root.paused ? "resume" : "pause";
+                        clicked => { root.pause-toggled(); }
+                    }
+                    Button {
+                        text: "cancel";
+                        clicked => { root.cancel-clicked(); }
+                    }
+                    Button {
+                        text: "restart";
+                        clicked => { root.restart-clicked(); }
+                    }
+                    Button {
+                        text: "save";
+                        clicked => { root.save-clicked(); }
+                    }
+                    // picking an order starts the render over
+                    ComboBox {
+                        model: root.tile-order-names;
+                        current-index <=> root.tile-order-index;
+                        selected => { root.restart-clicked(); }
+                    }
+                    ComboBox {
+                        model: root.denoise-names;
+                        current-index <=> root.denoise-index;
+                        selected => { root.restart-clicked(); }
+                    }
+                    CheckBox {
+                        text: "tiles";
+                        checked <=> root.show-overlay;
+                    }
+                    ComboBox {
+                        model: root.aov-names;
+                        current-index <=> root.aov-index;
+                        selected => { root.display-changed(); }
+                    }
+                    ComboBox {
+                        model: root.tonemap-names;
+                        current-index <=> root.tonemap-index;
+                        selected => { root.display-changed(); }
+                    }
+                    Slider {
+                        width: 120px;
+                        minimum: -5.0;
+                        maximum: 5.0;
+                        value <=> root.exposure;
+                        changed => { root.display-changed(); }
+                    }
+                    Text {
+                        text: root.save-message;
+                        color: white;
+                        vertical-alignment: center;
+                    }
+                    Text {
+                        text: root.status;
+                        color: white;
+                        vertical-alignment: center;
+                    }
                 }
             }
             
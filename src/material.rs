@@ -0,0 +1,202 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use vec3::Vec3;
+
+use super::ray::Ray;
+use super::rng::Rng;
+use super::hittable::HitRecord;
+
+pub struct Scatter {
+    pub attenuation : Vec3,
+    pub ray : Ray,
+
+    // bounce in one exact direction, like a mirror's, rather than spread
+    // over a lobe; only spread ones can be lit by sampling lights, and the
+    // AOVs count them as diffuse
+    pub specular : bool,
+}
+
+pub trait Material : Send + Sync {
+    // Returns the ray continuing the path and how much it is attenuated,
+    // or None if the ray was absorbed.
+    fn scatter( &self, ray_in : &Ray, rec : &HitRecord, rng : &mut Rng ) -> Option<Scatter>;
+
+    // Base color, as a feature for the denoiser
+    fn albedo( &self, _rec : &HitRecord ) -> Vec3 {
+        Vec3::ONE
+    }
+
+    // Light given off at the hit, towards where the ray came from
+    fn emitted( &self, _rec : &HitRecord ) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    // Whether anything can ever be emitted, so the object goes in the light list
+    fn is_emissive( &self ) -> bool {
+        false
+    }
+
+    // Typical radiance given off, for judging how bright a light is
+    // without a hit to look at
+    fn emission( &self ) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    // For light arriving from dir: the BSDF times the cosine, and the pdf of
+    // scatter() picking dir. None for materials that only scatter in
+    // discrete directions, which can't be lit by sampling lights.
+    fn eval( &self, _ray_in : &Ray, _rec : &HitRecord, _dir : Vec3 ) -> Option<(Vec3, f32)> {
+        None
+    }
+}
+
+pub type MaterialRef = Arc<dyn Material>;
+
+pub fn reflect( v : Vec3, n : Vec3 ) -> Vec3 {
+    v - 2.0 * Vec3::dot( &v, &n ) * n
+}
+
+// Refracts unit vector uv through a surface with normal n.
+// etai_over_etat is the ratio of refractive indices.
+pub fn refract( uv : Vec3, n : Vec3, etai_over_etat : f32 ) -> Vec3 {
+    let cos_theta = Vec3::dot( &-uv, &n ).min( 1.0 );
+    let r_out_perp = etai_over_etat * (uv + cos_theta * n);
+    let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * n;
+    r_out_perp + r_out_parallel
+}
+
+// Schlick's approximation for reflectance
+pub fn schlick( cosine : f32, ior : f32 ) -> f32 {
+    let r0 = (1.0 - ior) / (1.0 + ior);
+    let r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cosine).powi( 5 )
+}
+
+// Ideal diffuse
+pub struct Lambertian {
+    pub albedo : Vec3,
+}
+
+impl Lambertian {
+    pub fn new( albedo : Vec3 ) -> Lambertian {
+        Lambertian { albedo }
+    }
+}
+
+impl Material for Lambertian {
+    fn scatter( &self, _ray_in : &Ray, rec : &HitRecord, rng : &mut Rng ) -> Option<Scatter> {
+        let mut dir = rec.normal + rng.unit_vector();
+
+        // random vector almost exactly opposite the normal
+        if dir.length_squared() < 1e-8 {
+            dir = rec.normal;
+        }
+
+        Some( Scatter { attenuation : self.albedo, ray : Ray::new( rec.p, dir ), specular : false } )
+    }
+
+    fn albedo( &self, _rec : &HitRecord ) -> Vec3 {
+        self.albedo
+    }
+
+    // scatter() is cosine weighted, so f cos and the pdf differ by the albedo
+    fn eval( &self, _ray_in : &Ray, rec : &HitRecord, dir : Vec3 ) -> Option<(Vec3, f32)> {
+        let cosine = Vec3::dot( &rec.normal, &dir.normalize() );
+        if cosine <= 0.0 {
+            return Some( (Vec3::ZERO, 0.0) );
+        }
+        Some( (self.albedo * (cosine / PI), cosine / PI) )
+    }
+}
+
+// Mirror reflection, blurred by fuzz (0 is a perfect mirror)
+pub struct Metal {
+    pub albedo : Vec3,
+    pub fuzz : f32,
+}
+
+impl Metal {
+    pub fn new( albedo : Vec3, fuzz : f32 ) -> Metal {
+        Metal { albedo, fuzz : fuzz.clamp( 0.0, 1.0 ) }
+    }
+}
+
+impl Material for Metal {
+    fn scatter( &self, ray_in : &Ray, rec : &HitRecord, rng : &mut Rng ) -> Option<Scatter> {
+        let reflected = reflect( ray_in.dir.normalize(), rec.normal );
+        let dir = reflected + self.fuzz * rng.unit_vector();
+
+        // fuzzed below the surface, absorb it
+        if Vec3::dot( &dir, &rec.normal ) <= 0.0 {
+            return None;
+        }
+
+        Some( Scatter { attenuation : self.albedo, ray : Ray::new( rec.p, dir ), specular : true } )
+    }
+
+    fn albedo( &self, _rec : &HitRecord ) -> Vec3 {
+        self.albedo
+    }
+}
+
+// Clear glass-like material. Refracts per Snell's law, and reflects with
+// Schlick's approximation of the Fresnel term.
+pub struct Dielectric {
+    pub ior : f32,
+}
+
+impl Dielectric {
+    pub fn new( ior : f32 ) -> Dielectric {
+        Dielectric { ior }
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter( &self, ray_in : &Ray, rec : &HitRecord, rng : &mut Rng ) -> Option<Scatter> {
+        let ratio = if rec.front_face { 1.0 / self.ior } else { self.ior };
+
+        let unit_dir = ray_in.dir.normalize();
+        let cos_theta = Vec3::dot( &-unit_dir, &rec.normal ).min( 1.0 );
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        let cannot_refract = ratio * sin_theta > 1.0;
+        let dir = if cannot_refract || schlick( cos_theta, ratio ) > rng.next_f32() {
+            reflect( unit_dir, rec.normal )
+        } else {
+            refract( unit_dir, rec.normal, ratio )
+        };
+
+        Some( Scatter { attenuation : Vec3::ONE, ray : Ray::new( rec.p, dir ), specular : true } )
+    }
+}
+
+// Diffuse emitter, glows with the same radiance in every direction from its
+// front side and absorbs whatever hits it
+pub struct DiffuseLight {
+    pub emit : Vec3,
+}
+
+impl DiffuseLight {
+    pub fn new( emit : Vec3 ) -> DiffuseLight {
+        DiffuseLight { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter( &self, _ray_in : &Ray, _rec : &HitRecord, _rng : &mut Rng ) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted( &self, rec : &HitRecord ) -> Vec3 {
+        if rec.front_face { self.emit } else { Vec3::ZERO }
+    }
+
+    fn is_emissive( &self ) -> bool {
+        true
+    }
+
+    fn emission( &self ) -> Vec3 {
+        self.emit
+    }
+}
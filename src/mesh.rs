@@ -0,0 +1,383 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use vec3::Vec3;
+
+use super::ray::Ray;
+use super::rng::Rng;
+use super::aabb::{Aabb, AXES};
+use super::bvh::BvhNode;
+use super::hittable::{HitRecord, Hittable};
+use super::material::MaterialRef;
+use super::light_tree::LightBounds;
+use super::tonemap::luminance;
+
+// Indexed triangle data. normals and uvs are either empty or have one
+// entry per position.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub positions : Vec<Vec3>,
+    pub normals : Vec<Vec3>,
+    pub uvs : Vec<(f32, f32)>,
+    pub indices : Vec<[u32; 3]>,
+}
+
+impl MeshData {
+
+    pub fn num_triangles( &self ) -> usize {
+        self.indices.len()
+    }
+
+    // Scales (per axis) then translates the mesh in place
+    pub fn transform( &mut self, scale : Vec3, translate : Vec3 ) {
+        for p in &mut self.positions {
+            *p = *p * scale + translate;
+        }
+
+        // normals transform by the inverse transpose, which for a scale is 1/scale
+        let inv_scale = Vec3::ONE / scale;
+        for n in &mut self.normals {
+            let tn = *n * inv_scale;
+            if tn.length_squared() > 0.0 {
+                *n = tn.normalize();
+            }
+        }
+
+        // mirrored an odd number of times, flip winding to keep faces facing out
+        if scale.x * scale.y * scale.z < 0.0 {
+            for tri in &mut self.indices {
+                tri.swap( 1, 2 );
+            }
+        }
+    }
+
+    pub fn vertices( &self, tri : usize ) -> (Vec3, Vec3, Vec3) {
+        let [a, b, c] = self.indices[tri];
+        (self.positions[a as usize], self.positions[b as usize], self.positions[c as usize])
+    }
+}
+
+// A single triangle referencing shared mesh data
+pub struct Triangle {
+    mesh : Arc<MeshData>,
+    index : usize,
+    mat : MaterialRef,
+}
+
+impl Triangle {
+    pub fn new( mesh : Arc<MeshData>, index : usize, mat : MaterialRef ) -> Triangle {
+        Triangle { mesh, index, mat }
+    }
+
+    // geometric normal, on the front side, with length twice the area
+    fn area_normal( &self ) -> Vec3 {
+        let (v0, v1, v2) = self.mesh.vertices( self.index );
+        Vec3::cross( &(v1 - v0), &(v2 - v0) )
+    }
+}
+
+// A triangle mesh with its own BVH over the triangles, so the whole mesh
+// can be added to a scene as one object.
+pub struct TriangleMesh {
+    pub data : Arc<MeshData>,
+    pub mat : MaterialRef,
+    bvh : BvhNode,
+
+    // running total of triangle areas, for sampling the mesh as a light
+    area_cdf : Vec<f32>,
+}
+
+impl TriangleMesh {
+
+    pub fn new( data : MeshData, mat : MaterialRef ) -> TriangleMesh {
+        let data = Arc::new( data );
+        let tris = (0..data.num_triangles())
+            .map( |i| Box::new( Triangle::new( data.clone(), i, mat.clone() ) ) as Box<dyn Hittable + Send + Sync> )
+            .collect();
+
+        let mut total = 0.0;
+        let area_cdf = (0..data.num_triangles()).map( |i| {
+            let (v0, v1, v2) = data.vertices( i );
+            total += 0.5 * Vec3::cross( &(v1 - v0), &(v2 - v0) ).length();
+            total
+        }).collect();
+
+        TriangleMesh { data, mat, bvh : BvhNode::new( tris ), area_cdf }
+    }
+
+    pub fn area( &self ) -> f32 {
+        self.area_cdf.last().copied().unwrap_or( 0.0 )
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        self.bvh.hit( ray, ray_tmin, ray_tmax )
+    }
+
+    fn bounding_box( &self ) -> Aabb {
+        self.bvh.bounding_box()
+    }
+
+    fn material( &self ) -> Option<&MaterialRef> {
+        Some( &self.mat )
+    }
+
+    // Uniform over the whole surface. The direction may cross the mesh more
+    // than once, and any of those points could have been the one picked.
+    fn pdf_value( &self, origin : Vec3, dir : Vec3 ) -> f32 {
+        let area = self.area();
+        if area <= 0.0 {
+            return 0.0;
+        }
+        let ray = Ray::new( origin, dir );
+        let len = dir.length();
+        let mut pdf = 0.0;
+        let mut tmin = 0.001;
+        while let Some(rec) = self.bvh.hit( &ray, tmin, f32::INFINITY ) {
+            let cosine = Vec3::dot( &dir, &rec.normal ).abs() / len;
+            if cosine > 0.0 {
+                pdf += (rec.t * len).powi( 2 ) / (cosine * area);
+            }
+            tmin = rec.t + 1e-4;
+        }
+        pdf
+    }
+
+    fn random_direction( &self, origin : Vec3, rng : &mut Rng ) -> Vec3 {
+        if self.area_cdf.is_empty() {
+            return Vec3::new( 1.0, 0.0, 0.0 );
+        }
+        let target = rng.next_f32() * self.area();
+        let tri = self.area_cdf.partition_point( |&a| a <= target ).min( self.area_cdf.len() - 1 );
+
+        // uniform barycentrics
+        let (v0, v1, v2) = self.data.vertices( tri );
+        let su = rng.next_f32().sqrt();
+        let b1 = su * rng.next_f32();
+        let p = v0 * (1.0 - su) + v1 * b1 + v2 * (su - b1);
+        p - origin
+    }
+
+    // lit meshes are sampled a triangle at a time
+    fn light_parts( &self ) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        (0..self.data.num_triangles())
+            .map( |i| Arc::new( Triangle::new( self.data.clone(), i, self.mat.clone() ) ) as Arc<dyn Hittable + Send + Sync> )
+            .collect()
+    }
+}
+
+// Watertight ray/triangle intersection (Woop, Benthin & Wald 2013). Returns
+// t and the barycentric weights of (v0, v1, v2). Rays through a shared edge
+// or vertex hit at least one of the adjacent triangles, so there are no cracks.
+pub fn intersect_triangle( ray : &Ray, v0 : Vec3, v1 : Vec3, v2 : Vec3, ray_tmin : f32, ray_tmax : f32 ) -> Option<(f32, [f32; 3])> {
+    let d = ray.dir;
+
+    // permute so z is the dominant direction axis
+    let ad = Vec3::new( d.x.abs(), d.y.abs(), d.z.abs() );
+    let kz = if ad.x > ad.y && ad.x > ad.z { 0 } else if ad.y > ad.z { 1 } else { 2 };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if d.component( AXES[kz] ) < 0.0 {
+        std::mem::swap( &mut kx, &mut ky );
+    }
+    let (kx, ky, kz) = (AXES[kx], AXES[ky], AXES[kz]);
+
+    // shear so the ray points down +z
+    let sz = 1.0 / d.component( kz );
+    let sx = d.component( kx ) * sz;
+    let sy = d.component( ky ) * sz;
+
+    let a = v0 - ray.origin;
+    let b = v1 - ray.origin;
+    let c = v2 - ray.origin;
+
+    let ax = a.component( kx ) - sx * a.component( kz );
+    let ay = a.component( ky ) - sy * a.component( kz );
+    let bx = b.component( kx ) - sx * b.component( kz );
+    let by = b.component( ky ) - sy * b.component( kz );
+    let cx = c.component( kx ) - sx * c.component( kz );
+    let cy = c.component( ky ) - sy * c.component( kz );
+
+    // scaled barycentrics (2D edge functions)
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+
+    // exactly on an edge, redo in double precision to break the tie consistently
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        u = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+        v = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+        w = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+    }
+
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let az = sz * a.component( kz );
+    let bz = sz * b.component( kz );
+    let cz = sz * c.component( kz );
+    let t_scaled = u * az + v * bz + w * cz;
+
+    let inv_det = 1.0 / det;
+    let t = t_scaled * inv_det;
+    if t <= ray_tmin || ray_tmax <= t {
+        return None;
+    }
+
+    Some( (t, [ u * inv_det, v * inv_det, w * inv_det ]) )
+}
+
+impl Hittable for Triangle {
+    fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        let mesh = &self.mesh;
+        let (v0, v1, v2) = mesh.vertices( self.index );
+        let (t, bary) = intersect_triangle( ray, v0, v1, v2, ray_tmin, ray_tmax )?;
+
+        let geom_n = Vec3::cross( &(v1 - v0), &(v2 - v0) ).normalize();
+        let (_, front_face) = HitRecord::face_normal( ray, geom_n );
+
+        let [i0, i1, i2] = self.mesh.indices[self.index].map( |i| i as usize );
+
+        // interpolated shading normal, kept on the same side as the geometric one
+        let mut normal = geom_n;
+        if !mesh.normals.is_empty() {
+            let n = bary[0] * mesh.normals[i0] + bary[1] * mesh.normals[i1] + bary[2] * mesh.normals[i2];
+            if n.length_squared() > 0.0 {
+                normal = n.normalize();
+                if Vec3::dot( &normal, &geom_n ) < 0.0 {
+                    normal = -normal;
+                }
+            }
+        }
+        if !front_face {
+            normal = -normal;
+        }
+
+        let (u, v) = if mesh.uvs.is_empty() {
+            (bary[1], bary[2])
+        } else {
+            let (uv0, uv1, uv2) = (mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]);
+            ( bary[0] * uv0.0 + bary[1] * uv1.0 + bary[2] * uv2.0,
+              bary[0] * uv0.1 + bary[1] * uv1.1 + bary[2] * uv2.1 )
+        };
+
+        Some(HitRecord {
+            p : ray.at( t ),
+            normal,
+            t,
+            front_face,
+            u,
+            v,
+            mat : self.mat.as_ref(),
+            object_id : 0,
+            material_id : 0,
+        })
+    }
+
+    fn bounding_box( &self ) -> Aabb {
+        let (v0, v1, v2) = self.mesh.vertices( self.index );
+        let mut bbox = Aabb::new( v0, v1 );
+        bbox.grow( v2 );
+        bbox.padded( 1e-4 )
+    }
+
+    fn material( &self ) -> Option<&MaterialRef> {
+        Some( &self.mat )
+    }
+
+    // Uniform over the area, converted to solid angle at origin
+    fn pdf_value( &self, origin : Vec3, dir : Vec3 ) -> f32 {
+        let rec = match self.hit( &Ray::new( origin, dir ), 0.001, f32::INFINITY ) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+        let area_normal = self.area_normal();
+        let dist_sq = rec.t * rec.t * dir.length_squared();
+        let cosine = Vec3::dot( &dir, &area_normal ).abs() / (dir.length() * area_normal.length());
+        dist_sq / (cosine * 0.5 * area_normal.length())
+    }
+
+    fn random_direction( &self, origin : Vec3, rng : &mut Rng ) -> Vec3 {
+        let (v0, v1, v2) = self.mesh.vertices( self.index );
+        let su = rng.next_f32().sqrt();
+        let b1 = su * rng.next_f32();
+        let p = v0 * (1.0 - su) + v1 * b1 + v2 * (su - b1);
+        p - origin
+    }
+
+    // one-sided, so radiance times pi per unit area
+    fn light_bounds( &self ) -> LightBounds {
+        let area_normal = self.area_normal();
+        let power = luminance( self.mat.emission() ) * PI * 0.5 * area_normal.length();
+        LightBounds::facing( self.bounding_box(), power, area_normal )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    fn quad() -> MeshData {
+        // two triangles sharing the diagonal from (-1,-1) to (1,1), at z = -1
+        MeshData {
+            positions : vec![ Vec3::new( -1.0, -1.0, -1.0 ), Vec3::new( 1.0, -1.0, -1.0 ),
+                              Vec3::new( 1.0, 1.0, -1.0 ), Vec3::new( -1.0, 1.0, -1.0 ) ],
+            normals : Vec::new(),
+            uvs : vec![ (0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0) ],
+            indices : vec![ [0, 1, 2], [0, 2, 3] ],
+        }
+    }
+
+    #[test]
+    fn hit_and_uv() {
+        let mesh = TriangleMesh::new( quad(), Arc::new( Lambertian::new( Vec3::ONE ) ) );
+        let ray = Ray::new( Vec3::new( 0.5, -0.5, 0.0 ), Vec3::new( 0.0, 0.0, -1.0 ) );
+        let rec = mesh.hit( &ray, 0.0, f32::INFINITY ).unwrap();
+        assert!( (rec.t - 1.0).abs() < 1e-6 );
+        assert!( rec.front_face );
+        assert_eq!( rec.normal, Vec3::new( 0.0, 0.0, 1.0 ) );
+        assert!( (rec.u - 0.75).abs() < 1e-6 && (rec.v - 0.25).abs() < 1e-6 );
+
+        // from behind
+        let ray = Ray::new( Vec3::new( 0.5, -0.5, -2.0 ), Vec3::new( 0.0, 0.0, 1.0 ) );
+        let rec = mesh.hit( &ray, 0.0, f32::INFINITY ).unwrap();
+        assert!( !rec.front_face );
+        assert_eq!( rec.normal, Vec3::new( 0.0, 0.0, -1.0 ) );
+    }
+
+    #[test]
+    fn watertight_shared_edge() {
+        // rays right along the shared diagonal must never fall through the crack
+        let mesh = TriangleMesh::new( quad(), Arc::new( Lambertian::new( Vec3::ONE ) ) );
+        for i in 0..=100 {
+            let s = -1.0 + 2.0 * (i as f32 / 100.0);
+            let ray = Ray::new( Vec3::new( s, s, 0.0 ), Vec3::new( 0.0, 0.0, -1.0 ) );
+            assert!( mesh.hit( &ray, 0.0, f32::INFINITY ).is_some(), "missed at {}", s );
+        }
+    }
+
+    #[test]
+    fn sample_as_light() {
+        let mesh = TriangleMesh::new( quad(), Arc::new( Lambertian::new( Vec3::ONE ) ) );
+        assert_eq!( mesh.area(), 4.0 );
+
+        // 2 units away, head on: distance squared over area
+        let origin = Vec3::new( 0.2, 0.1, 1.0 );
+        assert!( (mesh.pdf_value( origin, Vec3::new( 0.0, 0.0, -1.0 ) ) - 1.0).abs() < 1e-5 );
+
+        let mut rng = Rng::new( 3, 0 );
+        for _ in 0..100 {
+            let dir = mesh.random_direction( origin, &mut rng );
+            assert!( mesh.pdf_value( origin, dir ) > 0.0 );
+            assert!( ((origin + dir).z + 1.0).abs() < 1e-6 );
+        }
+    }
+}
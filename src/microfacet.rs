@@ -0,0 +1,446 @@
+// Rough surfaces as microfacet models: countless tiny mirror facets, their
+// normals spread by the GGX (Trowbridge-Reitz) distribution and hiding
+// each other as Smith's model has it. Directions are sampled from the
+// normals visible from where the ray came from (Heitz 2018), so hardly any
+// samples go to facets facing away.
+//
+// Only single scattering between facets is modelled, so very rough
+// surfaces lose some energy, most of all at grazing angles.
+
+use std::f32::consts::PI;
+
+use vec3::Vec3;
+
+use super::ray::Ray;
+use super::rng::Rng;
+use super::hittable::HitRecord;
+use super::material::{reflect, refract, Material, Scatter};
+
+// Below this alpha a surface is treated as perfectly smooth
+const MIN_ALPHA : f32 = 1e-3;
+
+// Roughness as given in scenes, 0..1, to the GGX alpha. Squaring it makes
+// equal steps look about equally rougher.
+pub fn roughness_to_alpha( roughness : f32 ) -> f32 {
+    let r = roughness.clamp( 0.0, 1.0 );
+    r * r
+}
+
+// Axes around a shading normal, the normal being +z in local coordinates
+struct Frame {
+    t : Vec3,
+    s : Vec3,
+    n : Vec3,
+}
+
+impl Frame {
+
+    fn new( n : Vec3 ) -> Frame {
+        let (t, s) = n.orthonormal_basis();
+        Frame { t, s, n }
+    }
+
+    fn to_local( &self, v : Vec3 ) -> Vec3 {
+        Vec3::new( Vec3::dot( &v, &self.t ), Vec3::dot( &v, &self.s ), Vec3::dot( &v, &self.n ) )
+    }
+
+    fn to_world( &self, v : Vec3 ) -> Vec3 {
+        v.x * self.t + v.y * self.s + v.z * self.n
+    }
+}
+
+// Density of facet normals m, per unit solid angle projected onto the surface
+fn ggx_d( m : Vec3, alpha : f32 ) -> f32 {
+    if m.z <= 0.0 {
+        return 0.0;
+    }
+    let a_sq = alpha * alpha;
+    let d = m.z * m.z * (a_sq - 1.0) + 1.0;
+    a_sq / (PI * d * d)
+}
+
+// Smith's lambda, from which masking follows; either side of the surface
+fn smith_lambda( w : Vec3, alpha : f32 ) -> f32 {
+    let cos_sq = w.z * w.z;
+    if cos_sq <= 0.0 {
+        return f32::INFINITY;
+    }
+    let tan_sq = (1.0 - cos_sq).max( 0.0 ) / cos_sq;
+    0.5 * ((1.0 + alpha * alpha * tan_sq).sqrt() - 1.0)
+}
+
+// Fraction of facets seen from w that aren't hidden by others
+fn smith_g1( w : Vec3, alpha : f32 ) -> f32 {
+    1.0 / (1.0 + smith_lambda( w, alpha ))
+}
+
+// Fraction seen from both directions, height correlated
+fn smith_g( wo : Vec3, wi : Vec3, alpha : f32 ) -> f32 {
+    1.0 / (1.0 + smith_lambda( wo, alpha ) + smith_lambda( wi, alpha ))
+}
+
+// Density of sample_visible_normal() picking m
+fn visible_normal_pdf( wo : Vec3, m : Vec3, alpha : f32 ) -> f32 {
+    smith_g1( wo, alpha ) * Vec3::dot( &wo, &m ).max( 0.0 ) * ggx_d( m, alpha ) / wo.z
+}
+
+// Random facet normal among those visible from wo, which is above the
+// surface: stretch to alpha 1, sample the projected hemisphere, unstretch
+fn sample_visible_normal( wo : Vec3, alpha : f32, u1 : f32, u2 : f32 ) -> Vec3 {
+    let v = Vec3::new( alpha * wo.x, alpha * wo.y, wo.z ).normalize();
+    let len_sq = v.x * v.x + v.y * v.y;
+    let t1 = if len_sq > 0.0 { Vec3::new( -v.y, v.x, 0.0 ) / len_sq.sqrt() } else { Vec3::new( 1.0, 0.0, 0.0 ) };
+    let t2 = Vec3::cross( &v, &t1 );
+
+    // a disk, squashed where the hemisphere is hidden from v
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + v.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max( 0.0 ).sqrt() * v;
+
+    Vec3::new( alpha * n.x, alpha * n.y, n.z.max( 0.0 ) ).normalize()
+}
+
+// Reflectance of a boundary between dielectrics, for light arriving at
+// cos_i (>= 0) from the side eta is relative to: the index of refraction
+// beyond over the one on this side. 1 past the critical angle.
+pub fn fresnel_dielectric( cos_i : f32, eta : f32 ) -> f32 {
+    let cos_i = cos_i.clamp( 0.0, 1.0 );
+    let sin_t_sq = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin_t_sq >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t_sq).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+// Reflectance of a conductor, whose index of refraction eta + ik is
+// complex, for one wavelength and light arriving at cos_i
+pub fn fresnel_conductor( cos_i : f32, eta : f32, k : f32 ) -> f32 {
+    let cos_sq = cos_i.clamp( 0.0, 1.0 ).powi( 2 );
+    let sin_sq = 1.0 - cos_sq;
+    let t0 = eta * eta - k * k - sin_sq;
+    let a_sq_plus_b_sq = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a_sq_plus_b_sq + t0)).max( 0.0 ).sqrt();
+
+    let t1 = a_sq_plus_b_sq + cos_sq;
+    let t2 = 2.0 * a * cos_sq.sqrt();
+    let r_s = (t1 - t2) / (t1 + t2);
+    let t3 = cos_sq * a_sq_plus_b_sq + sin_sq * sin_sq;
+    let t4 = t2 * sin_sq;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    0.5 * (r_s + r_p)
+}
+
+// Rough metal, coloured by the Fresnel reflectance of its complex index of
+// refraction rather than an albedo
+pub struct RoughConductor {
+    pub eta : Vec3,
+    pub k : Vec3,
+    pub alpha : f32,
+}
+
+impl RoughConductor {
+
+    pub fn new( eta : Vec3, k : Vec3, roughness : f32 ) -> RoughConductor {
+        RoughConductor { eta, k, alpha : roughness_to_alpha( roughness ) }
+    }
+
+    // eta and k at red, green and blue (650, 550 and 450 nm) for common metals
+    pub fn preset( name : &str ) -> Option<(Vec3, Vec3)> {
+        match name {
+            "gold" | "au" => Some( (Vec3::new( 0.143, 0.374, 1.442 ), Vec3::new( 3.983, 2.385, 1.603 )) ),
+            "copper" | "cu" => Some( (Vec3::new( 0.200, 0.924, 1.102 ), Vec3::new( 3.912, 2.452, 2.142 )) ),
+            "aluminium" | "aluminum" | "al" => Some( (Vec3::new( 1.657, 0.880, 0.521 ), Vec3::new( 9.224, 6.270, 4.837 )) ),
+            _ => None,
+        }
+    }
+
+    fn fresnel( &self, cos_i : f32 ) -> Vec3 {
+        Vec3::new( fresnel_conductor( cos_i, self.eta.x, self.k.x ),
+                   fresnel_conductor( cos_i, self.eta.y, self.k.y ),
+                   fresnel_conductor( cos_i, self.eta.z, self.k.z ) )
+    }
+
+    // f cos and pdf for unit local directions, wo above the surface
+    fn eval_local( &self, wo : Vec3, wi : Vec3 ) -> (Vec3, f32) {
+        if wi.z <= 0.0 {
+            return (Vec3::ZERO, 0.0);
+        }
+        let m = (wo + wi).normalize();
+        let cos_om = Vec3::dot( &wo, &m );
+        let d = ggx_d( m, self.alpha );
+        let f_cos = self.fresnel( cos_om ) * (d * smith_g( wo, wi, self.alpha ) / (4.0 * wo.z));
+        (f_cos, visible_normal_pdf( wo, m, self.alpha ) / (4.0 * cos_om))
+    }
+}
+
+impl Material for RoughConductor {
+    fn scatter( &self, ray_in : &Ray, rec : &HitRecord, rng : &mut Rng ) -> Option<Scatter> {
+        let frame = Frame::new( rec.normal );
+        let wo = frame.to_local( -ray_in.dir.normalize() );
+        if wo.z <= 0.0 {
+            return None;
+        }
+        if self.alpha < MIN_ALPHA {
+            let dir = reflect( ray_in.dir.normalize(), rec.normal );
+            return Some( Scatter { attenuation : self.fresnel( wo.z ), ray : Ray::new( rec.p, dir ), specular : true } );
+        }
+
+        // off a facet and back into the surface, absorb it
+        let m = sample_visible_normal( wo, self.alpha, rng.next_f32(), rng.next_f32() );
+        let wi = reflect( -wo, m );
+        let (f_cos, pdf) = self.eval_local( wo, wi );
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some( Scatter { attenuation : f_cos / pdf, ray : Ray::new( rec.p, frame.to_world( wi ) ), specular : false } )
+    }
+
+    fn albedo( &self, _rec : &HitRecord ) -> Vec3 {
+        self.fresnel( 1.0 )
+    }
+
+    fn eval( &self, ray_in : &Ray, rec : &HitRecord, dir : Vec3 ) -> Option<(Vec3, f32)> {
+        if self.alpha < MIN_ALPHA {
+            return None;
+        }
+        let frame = Frame::new( rec.normal );
+        let wo = frame.to_local( -ray_in.dir.normalize() );
+        if wo.z <= 0.0 {
+            return Some( (Vec3::ZERO, 0.0) );
+        }
+        Some( self.eval_local( wo, frame.to_local( dir.normalize() ) ) )
+    }
+}
+
+// Frosted glass: reflects and refracts through rough facets, in proportion
+// to the exact Fresnel reflectance. Like Dielectric, light crossing the
+// surface isn't scaled by the squared ratio of the indices, which cancels
+// going into and out of a closed object.
+pub struct RoughDielectric {
+    pub ior : f32,
+    pub alpha : f32,
+}
+
+impl RoughDielectric {
+
+    pub fn new( ior : f32, roughness : f32 ) -> RoughDielectric {
+        RoughDielectric { ior, alpha : roughness_to_alpha( roughness ) }
+    }
+
+    // index of refraction beyond the surface over the one on the side of rec's normal
+    fn eta( &self, rec : &HitRecord ) -> f32 {
+        if rec.front_face { self.ior } else { 1.0 / self.ior }
+    }
+
+    // f cos and pdf for unit local directions, wo above the surface and wi
+    // either side of it
+    fn eval_local( &self, wo : Vec3, wi : Vec3, eta : f32 ) -> (Vec3, f32) {
+        if wi.z == 0.0 {
+            return (Vec3::ZERO, 0.0);
+        }
+
+        // the facet normal that turns one direction into the other
+        let reflected = wi.z > 0.0;
+        let m = if reflected { wo + wi } else { wo + wi * eta };
+        if m.length_squared() == 0.0 {
+            return (Vec3::ZERO, 0.0);
+        }
+        let m = if m.z < 0.0 { -m.normalize() } else { m.normalize() };
+        let (cos_om, cos_im) = (Vec3::dot( &wo, &m ), Vec3::dot( &wi, &m ));
+        if cos_om <= 0.0 || cos_im * wi.z <= 0.0 {
+            return (Vec3::ZERO, 0.0);
+        }
+
+        let f = fresnel_dielectric( cos_om, eta );
+        let dg = ggx_d( m, self.alpha ) * smith_g( wo, wi, self.alpha );
+        let pdf_m = visible_normal_pdf( wo, m, self.alpha );
+        if reflected {
+            return (Vec3::from_float( f * dg / (4.0 * wo.z) ), f * pdf_m / (4.0 * cos_om));
+        }
+        let denom = (cos_im + cos_om / eta).powi( 2 );
+        let f_cos = (1.0 - f) * dg * (cos_im * cos_om).abs() / (denom * wo.z);
+        (Vec3::from_float( f_cos ), (1.0 - f) * pdf_m * cos_im.abs() / denom)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter( &self, ray_in : &Ray, rec : &HitRecord, rng : &mut Rng ) -> Option<Scatter> {
+        let frame = Frame::new( rec.normal );
+        let wo = frame.to_local( -ray_in.dir.normalize() );
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let eta = self.eta( rec );
+        let smooth = self.alpha < MIN_ALPHA;
+        let m = if smooth { Vec3::new( 0.0, 0.0, 1.0 ) } else { sample_visible_normal( wo, self.alpha, rng.next_f32(), rng.next_f32() ) };
+
+        // past the critical angle reflectance is 1, so it never refracts
+        let reflected = rng.next_f32() < fresnel_dielectric( Vec3::dot( &wo, &m ), eta );
+        let wi = if reflected { reflect( -wo, m ) } else { refract( -wo, m, 1.0 / eta ) };
+        if smooth {
+            return Some( Scatter { attenuation : Vec3::ONE, ray : Ray::new( rec.p, frame.to_world( wi ) ), specular : true } );
+        }
+
+        // off a facet to the wrong side of the surface, absorb it
+        if (wi.z > 0.0) != reflected {
+            return None;
+        }
+        let (f_cos, pdf) = self.eval_local( wo, wi.normalize(), eta );
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some( Scatter { attenuation : f_cos / pdf, ray : Ray::new( rec.p, frame.to_world( wi ) ), specular : false } )
+    }
+
+    fn eval( &self, ray_in : &Ray, rec : &HitRecord, dir : Vec3 ) -> Option<(Vec3, f32)> {
+        if self.alpha < MIN_ALPHA {
+            return None;
+        }
+        let frame = Frame::new( rec.normal );
+        let wo = frame.to_local( -ray_in.dir.normalize() );
+        if wo.z <= 0.0 {
+            return Some( (Vec3::ZERO, 0.0) );
+        }
+        Some( self.eval_local( wo, frame.to_local( dir.normalize() ), self.eta( rec ) ) )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit( mat : &dyn Material, front_face : bool ) -> HitRecord<'_> {
+        HitRecord { p : Vec3::ZERO, normal : Vec3::new( 0.0, 0.0, 1.0 ), t : 1.0, front_face, u : 0.0, v : 0.0,
+                    mat, object_id : 0, material_id : 0 }
+    }
+
+    // Ray arriving at the origin, cos_theta from the normal
+    fn incoming( cos_theta : f32 ) -> Ray {
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        Ray::new( Vec3::new( -sin_theta, 0.0, cos_theta ), Vec3::new( sin_theta, 0.0, -cos_theta ) )
+    }
+
+    // Under uniform white light from everywhere: the light scattered back,
+    // as the renderer would estimate it from scatter(), and from eval() by
+    // sampling the sphere evenly, along with its pdf's integral
+    // and how many scatter() samples survived
+    fn furnace( mat : &dyn Material, front_face : bool, cos_theta : f32, rng : &mut Rng ) -> (f64, f64, f64, f64) {
+        let rec = hit( mat, front_face );
+        let ray = incoming( cos_theta );
+        let n = 100000;
+
+        let (mut sampled, mut survived) = (0.0, 0);
+        for _ in 0..n {
+            if let Some(scatter) = mat.scatter( &ray, &rec, rng ) {
+                sampled += scatter.attenuation.y as f64;
+                survived += 1;
+            }
+        }
+
+        // jittered over a grid, even in z and angle round it, which is even over the sphere
+        let (mut integral, mut pdf_integral) = (0.0, 0.0);
+        let grid = 400;
+        for i in 0..grid {
+            for j in 0..grid {
+                let z = 1.0 - 2.0 * (i as f32 + rng.next_f32()) / grid as f32;
+                let phi = 2.0 * PI * (j as f32 + rng.next_f32()) / grid as f32;
+                let r = (1.0 - z * z).max( 0.0 ).sqrt();
+                let (f_cos, pdf) = mat.eval( &ray, &rec, Vec3::new( r * phi.cos(), r * phi.sin(), z ) ).unwrap();
+                integral += f_cos.y as f64;
+                pdf_integral += pdf as f64;
+            }
+        }
+        let sphere = 4.0 * std::f64::consts::PI / (grid * grid) as f64;
+        (sampled / n as f64, integral * sphere, pdf_integral * sphere, survived as f64 / n as f64)
+    }
+
+    #[test]
+    fn fresnel() {
+        assert!( (fresnel_dielectric( 1.0, 1.5 ) - 0.04).abs() < 1e-6 );
+        assert_eq!( fresnel_dielectric( 0.3, 1.0 / 1.5 ), 1.0 );
+        assert!( fresnel_dielectric( 0.01, 1.5 ) > 0.9 );
+
+        // at normal incidence a conductor reflects ((n-1)^2 + k^2) / ((n+1)^2 + k^2)
+        let (eta, k) = RoughConductor::preset( "gold" ).unwrap();
+        let f0 = ((eta.x - 1.0).powi( 2 ) + k.x * k.x) / ((eta.x + 1.0).powi( 2 ) + k.x * k.x);
+        assert!( (fresnel_conductor( 1.0, eta.x, k.x ) - f0).abs() < 1e-5 );
+        assert!( (fresnel_conductor( 0.0, eta.z, k.z ) - 1.0).abs() < 1e-5 );
+
+        // gold is yellow, aluminium nearly white and bright
+        let gold = RoughConductor::new( eta, k, 0.5 ).fresnel( 1.0 );
+        assert!( gold.x > 0.9 && gold.z < 0.4, "{:?}", gold );
+        let (eta, k) = RoughConductor::preset( "al" ).unwrap();
+        let aluminium = RoughConductor::new( eta, k, 0.5 ).fresnel( 1.0 );
+        assert!( aluminium.z > 0.9 && aluminium.x > 0.85, "{:?}", aluminium );
+        assert!( RoughConductor::preset( "tin" ).is_none() );
+    }
+
+    #[test]
+    fn white_furnace() {
+        let mut rng = Rng::new( 3, 0 );
+
+        // a conductor with an imaginary index reflects everything, so only
+        // facets shadowing each other lose energy, hardly any unless rough
+        for roughness in [ 0.3, 0.6, 1.0 ] {
+            let mirror = RoughConductor::new( Vec3::ZERO, Vec3::ONE, roughness );
+            for cos_theta in [ 1.0, 0.5, 0.2 ] {
+                let (sampled, integral, pdf_integral, survived) = furnace( &mirror, true, cos_theta, &mut rng );
+                let what = format!( "roughness {} at cos {}: {} {} {} {}", roughness, cos_theta, sampled, integral, pdf_integral, survived );
+                assert!( sampled <= 1.0 + 1e-4, "{}", what );
+
+                assert!( (sampled - integral).abs() < 0.02, "{}", what );
+                assert!( (pdf_integral - survived).abs() < 0.02, "{}", what );
+                if roughness <= 0.3 && cos_theta >= 0.5 {
+                    assert!( sampled > 0.97, "{}", what );
+                }
+            }
+        }
+
+        // glass reflects or lets through everything, from outside or in,
+        // though rough facets trap some of the light totally reflected inside
+        for roughness in [ 0.3, 0.6 ] {
+            let glass = RoughDielectric::new( 1.5, roughness );
+            for front_face in [ true, false ] {
+                for cos_theta in [ 1.0, 0.5 ] {
+                    let (sampled, integral, pdf_integral, survived) = furnace( &glass, front_face, cos_theta, &mut rng );
+                    let what = format!( "roughness {} at cos {}, front {}: {} {} {} {}",
+                                        roughness, cos_theta, front_face, sampled, integral, pdf_integral, survived );
+                    assert!( sampled <= 1.0 + 1e-4, "{}", what );
+                    // refraction squeezes the smoother lobe too thin to integrate this way
+                    if roughness > 0.3 {
+                        assert!( (sampled - integral).abs() < 0.02, "{}", what );
+                        assert!( (pdf_integral - survived).abs() < 0.02, "{}", what );
+                    }
+                    if roughness <= 0.3 {
+                        assert!( sampled > 0.95, "{}", what );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_limit() {
+        // no roughness: a perfect mirror, and glass that refracts straight through head on
+        let mut rng = Rng::new( 9, 0 );
+        let (eta, k) = RoughConductor::preset( "copper" ).unwrap();
+        let copper = RoughConductor::new( eta, k, 0.0 );
+        let rec = hit( &copper, true );
+        let scatter = copper.scatter( &incoming( 0.6 ), &rec, &mut rng ).unwrap();
+        assert!( scatter.specular );
+        assert!( (scatter.ray.dir - Vec3::new( 0.8, 0.0, 0.6 )).length() < 1e-5 );
+        assert_eq!( scatter.attenuation, copper.fresnel( 0.6 ) );
+        assert!( copper.eval( &incoming( 0.6 ), &rec, Vec3::new( 0.8, 0.0, 0.6 ) ).is_none() );
+
+        let glass = RoughDielectric::new( 1.5, 0.0 );
+        let rec = hit( &glass, true );
+        let through = (0..100).filter( |_| glass.scatter( &incoming( 1.0 ), &rec, &mut rng ).unwrap().ray.dir.z < 0.0 ).count();
+        assert!( through > 85, "{}", through );
+    }
+}
@@ -0,0 +1,441 @@
+// Wavefront OBJ / MTL importer
+//
+// Supports v, vt, vn, f (any polygon, fan triangulated, negative indices),
+// o, g, usemtl and mtllib. Everything else is ignored. One MeshData is
+// produced per (object, material) run of faces.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use vec3::Vec3;
+
+use super::mesh::MeshData;
+
+#[derive(Debug)]
+pub enum ObjError {
+    // couldn't read the .obj or a referenced .mtl
+    Io { path : PathBuf, err : io::Error },
+
+    // malformed statement
+    Parse { path : PathBuf, line : usize, msg : String },
+
+    // face refers to a vertex/uv/normal that doesn't exist (yet)
+    IndexOutOfRange { path : PathBuf, line : usize, index : i64, count : usize },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match self {
+            ObjError::Io { path, err } =>
+                write!( f, "{}: {}", path.display(), err ),
+            ObjError::Parse { path, line, msg } =>
+                write!( f, "{}:{}: {}", path.display(), line, msg ),
+            ObjError::IndexOutOfRange { path, line, index, count } =>
+                write!( f, "{}:{}: index {} out of range (have {})", path.display(), line, index, count ),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source( &self ) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { err, .. } => Some( err ),
+            _ => None,
+        }
+    }
+}
+
+// Material as described by an .mtl file. Left in MTL terms; it's up to the
+// caller to turn it into something renderable.
+#[derive(Clone, Debug)]
+pub struct ObjMaterial {
+    pub name : String,
+    pub diffuse : Vec3,         // Kd
+    pub specular : Vec3,        // Ks
+    pub emission : Vec3,        // Ke
+    pub shininess : f32,        // Ns
+    pub ior : f32,              // Ni
+    pub dissolve : f32,         // d, or 1-Tr
+    pub illum : u32,
+    pub diffuse_map : Option<PathBuf>,  // map_Kd
+}
+
+impl ObjMaterial {
+    fn new( name : &str ) -> ObjMaterial {
+        ObjMaterial {
+            name : name.to_string(),
+            diffuse : Vec3::from_float( 0.8 ),
+            specular : Vec3::ZERO,
+            emission : Vec3::ZERO,
+            shininess : 0.0,
+            ior : 1.0,
+            dissolve : 1.0,
+            illum : 1,
+            diffuse_map : None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjMesh {
+    pub name : String,
+    pub material : Option<usize>,   // index into ObjFile::materials
+    pub data : MeshData,
+}
+
+#[derive(Debug)]
+pub struct ObjFile {
+    pub meshes : Vec<ObjMesh>,
+    pub materials : Vec<ObjMaterial>,
+}
+
+impl ObjFile {
+    pub fn num_triangles( &self ) -> usize {
+        self.meshes.iter().map( |m| m.data.num_triangles() ).sum()
+    }
+}
+
+pub fn load_obj( path : &Path ) -> Result<ObjFile, ObjError> {
+    let text = fs::read_to_string( path )
+        .map_err( |err| ObjError::Io { path : path.to_path_buf(), err } )?;
+
+    let base_dir = path.parent().unwrap_or( Path::new( "" ) );
+    parse_obj( &text, path, |mtl_name| {
+        let mtl_path = base_dir.join( mtl_name );
+        let mtl_text = fs::read_to_string( &mtl_path )
+            .map_err( |err| ObjError::Io { path : mtl_path.clone(), err } )?;
+        parse_mtl( &mtl_text, &mtl_path )
+    })
+}
+
+// Parses OBJ text. `path` is only used for error messages; mtllib statements
+// are resolved through load_mtl.
+pub fn parse_obj<F>( text : &str, path : &Path, mut load_mtl : F ) -> Result<ObjFile, ObjError>
+    where F : FnMut( &str ) -> Result<Vec<ObjMaterial>, ObjError>
+{
+    let mut positions : Vec<Vec3> = Vec::new();
+    let mut uvs : Vec<(f32, f32)> = Vec::new();
+    let mut normals : Vec<Vec3> = Vec::new();
+
+    let mut materials : Vec<ObjMaterial> = Vec::new();
+    let mut meshes : Vec<ObjMesh> = Vec::new();
+    let mut builder = MeshBuilder::new( "default", None );
+
+    for (line_ndx, raw_line) in text.lines().enumerate() {
+        let line = line_ndx + 1;
+        let perr = |msg : String| ObjError::Parse { path : path.to_path_buf(), line, msg };
+
+        let stmt = raw_line.split( '#' ).next().unwrap_or( "" ).trim();
+        let mut parts = stmt.split_whitespace();
+        let Some(keyword) = parts.next() else { continue };
+        let args : Vec<&str> = parts.collect();
+
+        match keyword {
+            "v" => positions.push( parse_vec3( &args, &perr )? ),
+            "vn" => normals.push( parse_vec3( &args, &perr )? ),
+            "vt" => {
+                if args.is_empty() {
+                    return Err( perr( "vt needs at least one coordinate".to_string() ) );
+                }
+                let u = parse_f32( args[0], &perr )?;
+                let v = match args.get( 1 ) { Some(s) => parse_f32( s, &perr )?, None => 0.0 };
+                uvs.push( (u, v) );
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err( perr( format!( "face needs at least 3 vertices, got {}", args.len() ) ) );
+                }
+                let mut corners = Vec::with_capacity( args.len() );
+                for arg in &args {
+                    corners.push( parse_corner( arg, positions.len(), uvs.len(), normals.len(), path, line )? );
+                }
+                builder.add_polygon( &corners, &positions, &uvs, &normals );
+            },
+            "o" | "g" => {
+                let name = if args.is_empty() { "default".to_string() } else { args.join( " " ) };
+                let material = builder.material;
+                builder.finish( &mut meshes );
+                builder = MeshBuilder::new( &name, material );
+            },
+            "usemtl" => {
+                let name = args.join( " " );
+                let Some(ndx) = materials.iter().position( |m| m.name == name ) else {
+                    return Err( perr( format!( "unknown material '{}'", name ) ) );
+                };
+                if builder.material != Some( ndx ) {
+                    let obj_name = builder.name.clone();
+                    builder.finish( &mut meshes );
+                    builder = MeshBuilder::new( &obj_name, Some( ndx ) );
+                }
+            },
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err( perr( "mtllib needs a file name".to_string() ) );
+                }
+                for mtl in load_mtl( &args.join( " " ) )? {
+                    // later definitions replace earlier ones with the same name
+                    match materials.iter().position( |m| m.name == mtl.name ) {
+                        Some(ndx) => materials[ndx] = mtl,
+                        None => materials.push( mtl ),
+                    }
+                }
+            },
+            _ => {}, // s, l, p, curves, ... not supported, skip
+        }
+    }
+    builder.finish( &mut meshes );
+
+    Ok( ObjFile { meshes, materials } )
+}
+
+pub fn parse_mtl( text : &str, path : &Path ) -> Result<Vec<ObjMaterial>, ObjError> {
+    let mut materials : Vec<ObjMaterial> = Vec::new();
+
+    for (line_ndx, raw_line) in text.lines().enumerate() {
+        let line = line_ndx + 1;
+        let perr = |msg : String| ObjError::Parse { path : path.to_path_buf(), line, msg };
+
+        let stmt = raw_line.split( '#' ).next().unwrap_or( "" ).trim();
+        let mut parts = stmt.split_whitespace();
+        let Some(keyword) = parts.next() else { continue };
+        let args : Vec<&str> = parts.collect();
+
+        if keyword == "newmtl" {
+            if args.is_empty() {
+                return Err( perr( "newmtl needs a name".to_string() ) );
+            }
+            materials.push( ObjMaterial::new( &args.join( " " ) ) );
+            continue;
+        }
+
+        let Some(mtl) = materials.last_mut() else {
+            return Err( perr( format!( "'{}' before any newmtl", keyword ) ) );
+        };
+
+        match keyword {
+            "Kd" => mtl.diffuse = parse_color( &args, &perr )?,
+            "Ks" => mtl.specular = parse_color( &args, &perr )?,
+            "Ke" => mtl.emission = parse_color( &args, &perr )?,
+            "Ns" => mtl.shininess = parse_f32( single( &args, &perr )?, &perr )?,
+            "Ni" => mtl.ior = parse_f32( single( &args, &perr )?, &perr )?,
+            "d" => mtl.dissolve = parse_f32( single( &args, &perr )?, &perr )?,
+            "Tr" => mtl.dissolve = 1.0 - parse_f32( single( &args, &perr )?, &perr )?,
+            "illum" => {
+                let s = single( &args, &perr )?;
+                mtl.illum = s.parse().map_err( |_| perr( format!( "bad illum model '{}'", s ) ) )?;
+            },
+            "map_Kd" => {
+                // the file name is the last argument, anything before it is options
+                let Some(file) = args.last() else {
+                    return Err( perr( "map_Kd needs a file name".to_string() ) );
+                };
+                let dir = path.parent().unwrap_or( Path::new( "" ) );
+                mtl.diffuse_map = Some( dir.join( file ) );
+            },
+            _ => {},
+        }
+    }
+
+    Ok( materials )
+}
+
+// Collects faces for one mesh, de-duplicating v/vt/vn combinations into
+// single indexed vertices.
+struct MeshBuilder {
+    name : String,
+    material : Option<usize>,
+    data : MeshData,
+    vertex_map : HashMap<(usize, Option<usize>, Option<usize>), u32>,
+    any_normals : bool,
+    any_uvs : bool,
+}
+
+impl MeshBuilder {
+    fn new( name : &str, material : Option<usize> ) -> MeshBuilder {
+        MeshBuilder {
+            name : name.to_string(),
+            material,
+            data : MeshData::default(),
+            vertex_map : HashMap::new(),
+            any_normals : false,
+            any_uvs : false,
+        }
+    }
+
+    fn vertex( &mut self, corner : (usize, Option<usize>, Option<usize>),
+               positions : &[Vec3], uvs : &[(f32, f32)], normals : &[Vec3] ) -> u32 {
+        if let Some(ndx) = self.vertex_map.get( &corner ) {
+            return *ndx;
+        }
+        let ndx = self.data.positions.len() as u32;
+        let (p, t, n) = corner;
+        self.data.positions.push( positions[p] );
+        self.data.uvs.push( t.map_or( (0.0, 0.0), |t| uvs[t] ) );
+        self.data.normals.push( n.map_or( Vec3::ZERO, |n| normals[n] ) );
+        self.any_uvs |= t.is_some();
+        self.any_normals |= n.is_some();
+        self.vertex_map.insert( corner, ndx );
+        ndx
+    }
+
+    fn add_polygon( &mut self, corners : &[(usize, Option<usize>, Option<usize>)],
+                    positions : &[Vec3], uvs : &[(f32, f32)], normals : &[Vec3] ) {
+        let first = self.vertex( corners[0], positions, uvs, normals );
+        let mut prev = self.vertex( corners[1], positions, uvs, normals );
+        for corner in &corners[2..] {
+            let cur = self.vertex( *corner, positions, uvs, normals );
+            self.data.indices.push( [first, prev, cur] );
+            prev = cur;
+        }
+    }
+
+    fn finish( mut self, meshes : &mut Vec<ObjMesh> ) {
+        if self.data.indices.is_empty() {
+            return;
+        }
+        if !self.any_normals {
+            self.data.normals.clear();
+        }
+        if !self.any_uvs {
+            self.data.uvs.clear();
+        }
+        meshes.push( ObjMesh { name : self.name, material : self.material, data : self.data } );
+    }
+}
+
+// Resolves a 1-based (or negative, relative) OBJ index to a 0-based one
+fn resolve_index( s : &str, count : usize, path : &Path, line : usize ) -> Result<usize, ObjError> {
+    let index : i64 = s.parse().map_err( |_| ObjError::Parse {
+        path : path.to_path_buf(), line, msg : format!( "bad index '{}'", s ) } )?;
+
+    let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err( ObjError::IndexOutOfRange { path : path.to_path_buf(), line, index, count } );
+    }
+    Ok( resolved as usize )
+}
+
+// Parses one face corner: v, v/vt, v//vn or v/vt/vn
+fn parse_corner( s : &str, num_pos : usize, num_uv : usize, num_nrm : usize,
+                 path : &Path, line : usize ) -> Result<(usize, Option<usize>, Option<usize>), ObjError> {
+    let mut fields = s.split( '/' );
+    let p = resolve_index( fields.next().unwrap_or( "" ), num_pos, path, line )?;
+    let t = match fields.next() {
+        Some(t) if !t.is_empty() => Some( resolve_index( t, num_uv, path, line )? ),
+        _ => None,
+    };
+    let n = match fields.next() {
+        Some(n) if !n.is_empty() => Some( resolve_index( n, num_nrm, path, line )? ),
+        _ => None,
+    };
+    if fields.next().is_some() {
+        return Err( ObjError::Parse { path : path.to_path_buf(), line, msg : format!( "bad face vertex '{}'", s ) } );
+    }
+    Ok( (p, t, n) )
+}
+
+fn parse_f32<E>( s : &str, perr : &impl Fn( String ) -> E ) -> Result<f32, E> {
+    s.parse::<f32>().map_err( |_| perr( format!( "expected a number, got '{}'", s ) ) )
+}
+
+fn parse_vec3<E>( args : &[&str], perr : &impl Fn( String ) -> E ) -> Result<Vec3, E> {
+    if args.len() < 3 {
+        return Err( perr( format!( "expected 3 numbers, got {}", args.len() ) ) );
+    }
+    Ok( Vec3::new( parse_f32( args[0], perr )?, parse_f32( args[1], perr )?, parse_f32( args[2], perr )? ) )
+}
+
+// MTL colors may be a single value meaning grey
+fn parse_color<E>( args : &[&str], perr : &impl Fn( String ) -> E ) -> Result<Vec3, E> {
+    match args.len() {
+        1 => Ok( Vec3::from_float( parse_f32( args[0], perr )? ) ),
+        _ => parse_vec3( args, perr ),
+    }
+}
+
+fn single<'a, E>( args : &[&'a str], perr : &impl Fn( String ) -> E ) -> Result<&'a str, E> {
+    match args.first() {
+        Some(s) => Ok( s ),
+        None => Err( perr( "missing value".to_string() ) ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE_MTL : &str = "
+newmtl red
+Kd 0.8 0.1 0.1
+Ns 10
+newmtl glass
+Kd 1
+Ni 1.5
+d 0.2
+";
+
+    fn no_mtl( name : &str ) -> Result<Vec<ObjMaterial>, ObjError> {
+        if name == "cube.mtl" {
+            parse_mtl( CUBE_MTL, Path::new( "cube.mtl" ) )
+        } else {
+            Err( ObjError::Io { path : PathBuf::from( name ), err : io::Error::from( io::ErrorKind::NotFound ) } )
+        }
+    }
+
+    #[test]
+    fn quads_and_materials() {
+        let text = "
+mtllib cube.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+o thing
+usemtl red
+f 1/1/1 2/2/1 3/3/1 4/4/1
+usemtl glass
+f -4//1 -3//1 -2//1
+";
+        let obj = parse_obj( text, Path::new( "cube.obj" ), no_mtl ).unwrap();
+        assert_eq!( obj.materials.len(), 2 );
+        assert_eq!( obj.materials[0].diffuse, Vec3::new( 0.8, 0.1, 0.1 ) );
+        assert_eq!( obj.materials[1].diffuse, Vec3::ONE );
+        assert_eq!( obj.materials[1].ior, 1.5 );
+
+        assert_eq!( obj.meshes.len(), 2 );
+        assert_eq!( obj.meshes[0].name, "thing" );
+        assert_eq!( obj.meshes[0].material, Some( 0 ) );
+        assert_eq!( obj.meshes[0].data.indices, vec![ [0, 1, 2], [0, 2, 3] ] );
+        assert_eq!( obj.meshes[0].data.uvs.len(), 4 );
+        assert_eq!( obj.meshes[1].material, Some( 1 ) );
+        assert!( obj.meshes[1].data.uvs.is_empty() );
+        assert_eq!( obj.meshes[1].data.normals.len(), 3 );
+        assert_eq!( obj.num_triangles(), 3 );
+    }
+
+    #[test]
+    fn errors() {
+        let err = parse_obj( "v 0 0 0\nv 1 0\n", Path::new( "a.obj" ), no_mtl ).unwrap_err();
+        assert!( matches!( err, ObjError::Parse { line : 2, .. } ) );
+
+        let err = parse_obj( "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 4\n", Path::new( "a.obj" ), no_mtl ).unwrap_err();
+        assert!( matches!( err, ObjError::IndexOutOfRange { line : 4, index : 4, count : 3, .. } ) );
+
+        let err = parse_obj( "usemtl nope\n", Path::new( "a.obj" ), no_mtl ).unwrap_err();
+        assert_eq!( err.to_string(), "a.obj:1: unknown material 'nope'" );
+
+        let err = parse_obj( "mtllib missing.mtl\n", Path::new( "a.obj" ), no_mtl ).unwrap_err();
+        assert!( matches!( err, ObjError::Io { .. } ) );
+
+        let err = parse_mtl( "Kd 1 1 1\n", Path::new( "a.mtl" ) ).unwrap_err();
+        assert!( matches!( err, ObjError::Parse { line : 1, .. } ) );
+    }
+}
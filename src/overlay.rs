@@ -0,0 +1,131 @@
+// Marks on top of the displayed image showing what the render threads are
+// doing: corner brackets around tiles being rendered, a tint over tiles
+// being denoised.
+
+use std::collections::HashMap;
+
+use slint::SharedPixelBuffer;
+use slint::Rgb8Pixel;
+
+use super::render::{Tile, TileStatus};
+
+const BRACKET_COLOR : [u8; 3] = [ 255, 170, 0 ];
+const DENOISE_TINT : [u8; 3] = [ 60, 120, 255 ];
+
+#[derive(Default)]
+pub struct TileOverlay {
+    // in-flight tiles by position
+    active : HashMap<(u32, u32), (u32, u32, TileStatus)>,
+}
+
+impl TileOverlay {
+
+    pub fn new() -> TileOverlay {
+        TileOverlay::default()
+    }
+
+    // Tracks a tile status report; finished or cleared tiles drop out
+    pub fn update( &mut self, tile : &Tile ) {
+        match tile.status {
+            TileStatus::Rendering | TileStatus::Denoising => {
+                self.active.insert( (tile.x, tile.y), (tile.w, tile.h, tile.status) );
+            },
+            TileStatus::Clear | TileStatus::Finished => {
+                self.active.remove( &(tile.x, tile.y) );
+            },
+        }
+    }
+
+    pub fn clear( &mut self ) {
+        self.active.clear();
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.active.is_empty()
+    }
+
+    pub fn draw( &self, buffer : &mut SharedPixelBuffer<Rgb8Pixel> ) {
+        let (width, height) = (buffer.width(), buffer.height());
+        let bytes = buffer.make_mut_bytes();
+        let mut put = |i : u32, j : u32, f : &dyn Fn( [u8; 3] ) -> [u8; 3]| {
+            if i < width && j < height {
+                let ndx = ((j * width + i) * 3) as usize;
+                let old = [ bytes[ndx], bytes[ndx + 1], bytes[ndx + 2] ];
+                bytes[ndx..ndx+3].copy_from_slice( &f( old ) );
+            }
+        };
+
+        for (&(x, y), &(w, h, status)) in &self.active {
+            match status {
+                TileStatus::Rendering => {
+                    // an L in each corner, a quarter of the edge long but
+                    // no longer than the tile, which may be a pixel wide
+                    if w == 0 || h == 0 {
+                        continue;
+                    }
+                    let len = (w.min( h ) / 4).max( 2 ).min( w.min( h ) );
+                    let (x1, y1) = (x + w - 1, y + h - 1);
+                    for k in 0..len {
+                        for (i, j) in [ (x + k, y), (x, y + k), (x1 - k, y), (x1, y + k),
+                                        (x + k, y1), (x, y1 - k), (x1 - k, y1), (x1, y1 - k) ] {
+                            put( i, j, &|_| BRACKET_COLOR );
+                        }
+                    }
+                },
+                TileStatus::Denoising => {
+                    for j in y..(y + h) {
+                        for i in x..(x + w) {
+                            put( i, j, &|c| [ 0, 1, 2 ].map( |n| ((c[n] as u32 + DENOISE_TINT[n] as u32) / 2) as u8 ) );
+                        }
+                    }
+                },
+                TileStatus::Clear | TileStatus::Finished => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel( buffer : &SharedPixelBuffer<Rgb8Pixel>, i : u32, j : u32 ) -> [u8; 3] {
+        let p = buffer.as_slice()[ (j * buffer.width() + i) as usize ];
+        [ p.r, p.g, p.b ]
+    }
+
+    #[test]
+    fn brackets_and_tint() {
+        let mut overlay = TileOverlay::new();
+        let mut tile = Tile::new( 0, 0, 8, 8 );
+        tile.status = TileStatus::Rendering;
+        overlay.update( &tile );
+        let mut denoising = Tile::new( 8, 0, 8, 8 );
+        denoising.status = TileStatus::Denoising;
+        overlay.update( &denoising );
+
+        let mut buffer = SharedPixelBuffer::<Rgb8Pixel>::new( 16, 8 );
+        overlay.draw( &mut buffer );
+        assert_eq!( pixel( &buffer, 0, 0 ), BRACKET_COLOR );
+        assert_eq!( pixel( &buffer, 7, 7 ), BRACKET_COLOR );
+        assert_eq!( pixel( &buffer, 4, 4 ), [ 0, 0, 0 ] );
+        assert_eq!( pixel( &buffer, 12, 4 ), [ 30, 60, 127 ] );
+
+        // finishing removes the marks
+        tile.status = TileStatus::Finished;
+        overlay.update( &tile );
+        denoising.status = TileStatus::Finished;
+        overlay.update( &denoising );
+        assert!( overlay.is_empty() );
+
+        // a one pixel tile in the corner stays inside itself
+        let mut tiny = Tile::new( 0, 0, 1, 1 );
+        tiny.status = TileStatus::Rendering;
+        overlay.update( &tiny );
+        let mut buffer = SharedPixelBuffer::<Rgb8Pixel>::new( 4, 4 );
+        overlay.draw( &mut buffer );
+        assert_eq!( pixel( &buffer, 0, 0 ), BRACKET_COLOR );
+        assert_eq!( pixel( &buffer, 1, 0 ), [ 0, 0, 0 ] );
+        assert_eq!( pixel( &buffer, 0, 1 ), [ 0, 0, 0 ] );
+    }
+}
@@ -0,0 +1,159 @@
+// Lights with no size: points, spots and distant (directional) lights. Rays
+// never hit them, so they only light the scene by being sampled.
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use vec3::Vec3;
+
+use super::ies::IesProfile;
+use super::aabb::Aabb;
+use super::light_tree::LightBounds;
+use super::tonemap::luminance;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PunctualKind {
+    Point,
+
+    // full intensity inside cos_inner, fading out to nothing at cos_outer
+    Spot { cos_inner : f32, cos_outer : f32 },
+
+    // from infinitely far away, intensity is the irradiance it gives
+    Directional,
+}
+
+#[derive(Clone, Debug)]
+pub struct PunctualLight {
+    pub kind : PunctualKind,
+    pub position : Vec3,
+
+    // unit vector the light points along: the spot's axis, the nadir of the
+    // IES profile, or the way directional light travels
+    pub direction : Vec3,
+
+    // radiant intensity (per steradian) for points and spots, scaled by the
+    // profile's candela values if it has one
+    pub intensity : Vec3,
+    pub profile : Option<Arc<IesProfile>>,
+}
+
+impl PunctualLight {
+
+    pub fn point( position : Vec3, intensity : Vec3 ) -> PunctualLight {
+        PunctualLight { kind : PunctualKind::Point, position, direction : Vec3::new( 0.0, -1.0, 0.0 ), intensity, profile : None }
+    }
+
+    // angle is the half angle of the cone in degrees, blend how many degrees
+    // inside its edge the light starts to fade
+    pub fn spot( position : Vec3, direction : Vec3, intensity : Vec3, angle : f32, blend : f32 ) -> PunctualLight {
+        let outer = angle.clamp( 0.0, 180.0 );
+        let inner = (outer - blend.max( 0.0 )).max( 0.0 );
+        let kind = PunctualKind::Spot { cos_inner : inner.to_radians().cos(), cos_outer : outer.to_radians().cos() };
+        PunctualLight { kind, position, direction : direction.normalize(), intensity, profile : None }
+    }
+
+    pub fn directional( direction : Vec3, irradiance : Vec3 ) -> PunctualLight {
+        PunctualLight { kind : PunctualKind::Directional, position : Vec3::ZERO, direction : direction.normalize(), intensity : irradiance, profile : None }
+    }
+
+    // Shapes the light with a photometric profile, its nadir along aim.
+    // Intensity then scales the profile's candela values.
+    pub fn with_profile( self, profile : Arc<IesProfile>, aim : Vec3 ) -> PunctualLight {
+        PunctualLight { profile : Some( profile ), direction : aim.normalize(), ..self }
+    }
+
+    // Light reaching point p: unit direction towards the light, how far
+    // away it is, and the irradiance it gives facing it head on
+    pub fn illuminate( &self, p : Vec3 ) -> (Vec3, f32, Vec3) {
+        if self.kind == PunctualKind::Directional {
+            return (-self.direction, f32::INFINITY, self.intensity);
+        }
+        let to_light = self.position - p;
+        let dist = to_light.length();
+        let wi = to_light / dist;
+        (wi, dist, self.intensity * self.falloff( -wi ) / (dist * dist))
+    }
+
+    // For the light tree; None for directional lights, which are everywhere
+    pub fn light_bounds( &self ) -> Option<LightBounds> {
+        let bbox = Aabb::new( self.position, self.position );
+        let mut intensity = luminance( self.intensity );
+        if let Some(profile) = &self.profile {
+            intensity *= profile.max_candela();
+        }
+        match self.kind {
+            PunctualKind::Directional => None,
+
+            // a cone no wider than a hemisphere, anything more counts as every way
+            PunctualKind::Spot { cos_outer, .. } if cos_outer >= 0.0 => {
+                let power = intensity * 2.0 * PI * (1.0 - cos_outer);
+                Some( LightBounds { theta_e : cos_outer.acos(), ..LightBounds::facing( bbox, power, self.direction ) } )
+            },
+            _ => Some( LightBounds::omni( bbox, intensity * 4.0 * PI ) ),
+        }
+    }
+
+    // how much of the intensity goes out along unit direction w
+    fn falloff( &self, w : Vec3 ) -> f32 {
+        let cos_axis = Vec3::dot( &w, &self.direction );
+        let mut scale = match self.kind {
+            PunctualKind::Spot { cos_inner, cos_outer } => smoothstep( cos_outer, cos_inner, cos_axis ),
+            _ => 1.0,
+        };
+
+        // C0 is along the first axis of the basis around the nadir
+        if let Some(profile) = &self.profile {
+            let (t, s) = self.direction.orthonormal_basis();
+            let theta = cos_axis.clamp( -1.0, 1.0 ).acos().to_degrees();
+            let phi = Vec3::dot( &w, &s ).atan2( Vec3::dot( &w, &t ) ).to_degrees();
+            scale *= profile.candela( theta, phi );
+        }
+        scale
+    }
+}
+
+fn smoothstep( edge0 : f32, edge1 : f32, x : f32 ) -> f32 {
+    if edge0 >= edge1 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp( 0.0, 1.0 );
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falloff() {
+        // inverse square
+        let point = PunctualLight::point( Vec3::new( 0.0, 2.0, 0.0 ), Vec3::from_float( 8.0 ) );
+        let (wi, dist, light) = point.illuminate( Vec3::ZERO );
+        assert_eq!( (wi, dist, light), (Vec3::new( 0.0, 1.0, 0.0 ), 2.0, Vec3::from_float( 2.0 )) );
+
+        // full on the axis, fading through the blend, none outside the cone
+        let spot = PunctualLight::spot( Vec3::new( 0.0, 1.0, 0.0 ), Vec3::new( 0.0, -1.0, 0.0 ), Vec3::ONE, 45.0, 45.0 );
+        assert_eq!( spot.illuminate( Vec3::ZERO ).2, Vec3::ONE );
+        let (_, dist, blended) = spot.illuminate( Vec3::new( 30f32.to_radians().tan(), 0.0, 0.0 ) );
+        let t = (30f32.to_radians().cos() - 45f32.to_radians().cos()) / (1.0 - 45f32.to_radians().cos());
+        assert!( (blended.x * dist * dist - t * t * (3.0 - 2.0 * t)).abs() < 1e-5, "{:?}", blended );
+        assert_eq!( spot.illuminate( Vec3::new( 1.0, 1.0, 0.0 ) ).2, Vec3::ZERO );
+
+        let sun = PunctualLight::directional( Vec3::new( 0.0, -2.0, 0.0 ), Vec3::from_float( 3.0 ) );
+        assert_eq!( sun.illuminate( Vec3::new( 5.0, 0.0, 1.0 ) ), (Vec3::new( 0.0, 1.0, 0.0 ), f32::INFINITY, Vec3::from_float( 3.0 )) );
+    }
+
+    #[test]
+    fn profile() {
+        let text = "TILT=NONE\n1 -1 1 3 1 1 2 0 0 0 1 1 10\n0 45 90\n0\n1000 500 0\n";
+        let profile = Arc::new( crate::ies::parse( text ).unwrap() );
+
+        // hung from the ceiling pointing down, 1/1000 scales it to 1 straight below
+        let light = PunctualLight::point( Vec3::new( 0.0, 1.0, 0.0 ), Vec3::from_float( 0.001 ) )
+            .with_profile( profile, Vec3::new( 0.0, -1.0, 0.0 ) );
+        assert!( (light.illuminate( Vec3::ZERO ).2.x - 1.0).abs() < 1e-6 );
+        let (_, _, at_45) = light.illuminate( Vec3::new( 1.0, 0.0, 0.0 ) );
+        assert!( (at_45.x - 0.5 / 2.0).abs() < 1e-6 );
+        assert_eq!( light.illuminate( Vec3::new( 0.0, 2.0, 0.0 ) ).2, Vec3::ZERO );
+    }
+}
@@ -0,0 +1,159 @@
+use std::f32::consts::PI;
+
+use vec3::Vec3;
+
+use super::ray::Ray;
+use super::rng::Rng;
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::material::MaterialRef;
+use super::light_tree::LightBounds;
+use super::tonemap::luminance;
+
+// Parallelogram with a corner at q and edges u and v. The front side is the
+// one u x v points to, which is the side a light made of it shines from.
+pub struct Quad {
+    pub q : Vec3,
+    pub u : Vec3,
+    pub v : Vec3,
+    pub mat : MaterialRef,
+
+    // plane n.p = d, and w to project hit points onto u and v
+    normal : Vec3,
+    d : f32,
+    w : Vec3,
+}
+
+impl Quad {
+    pub fn new( q : Vec3, u : Vec3, v : Vec3, mat : MaterialRef ) -> Quad {
+        let n = Vec3::cross( &u, &v );
+        let normal = n.normalize();
+        let d = Vec3::dot( &normal, &q );
+        let w = n / Vec3::dot( &n, &n );
+        Quad { q, u, v, mat, normal, d, w }
+    }
+
+    pub fn area( &self ) -> f32 {
+        Vec3::cross( &self.u, &self.v ).length()
+    }
+}
+
+impl Hittable for Quad {
+    fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        let denom = Vec3::dot( &self.normal, &ray.dir );
+
+        // parallel to the plane
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.d - Vec3::dot( &self.normal, &ray.origin )) / denom;
+        if t <= ray_tmin || ray_tmax <= t {
+            return None;
+        }
+
+        // position on the plane in terms of u and v, inside if both in 0..1
+        let p = ray.at( t );
+        let planar = p - self.q;
+        let alpha = Vec3::dot( &self.w, &Vec3::cross( &planar, &self.v ) );
+        let beta = Vec3::dot( &self.w, &Vec3::cross( &self.u, &planar ) );
+        if !(0.0..=1.0).contains( &alpha ) || !(0.0..=1.0).contains( &beta ) {
+            return None;
+        }
+
+        let (normal, front_face) = HitRecord::face_normal( ray, self.normal );
+        Some(HitRecord {
+            t,
+            p,
+            front_face,
+            normal,
+            u : alpha,
+            v : beta,
+            mat : self.mat.as_ref(),
+            object_id : 0,
+            material_id : 0,
+        })
+    }
+
+    fn bounding_box( &self ) -> Aabb {
+        let mut bbox = Aabb::new( self.q, self.q + self.u + self.v );
+        bbox.grow( self.q + self.u );
+        bbox.grow( self.q + self.v );
+
+        // flat along at least one axis
+        bbox.padded( 1e-4 )
+    }
+
+    fn material( &self ) -> Option<&MaterialRef> {
+        Some( &self.mat )
+    }
+
+    // Uniform over the area, converted to solid angle at origin
+    fn pdf_value( &self, origin : Vec3, dir : Vec3 ) -> f32 {
+        match self.hit( &Ray::new( origin, dir ), 0.001, f32::INFINITY ) {
+            Some(rec) => {
+                let dist_sq = rec.t * rec.t * dir.length_squared();
+                let cosine = Vec3::dot( &dir, &self.normal ).abs() / dir.length();
+                dist_sq / (cosine * self.area())
+            },
+            None => 0.0,
+        }
+    }
+
+    fn random_direction( &self, origin : Vec3, rng : &mut Rng ) -> Vec3 {
+        let p = self.q + rng.next_f32() * self.u + rng.next_f32() * self.v;
+        p - origin
+    }
+
+    // one-sided, so radiance times pi per unit area
+    fn light_bounds( &self ) -> LightBounds {
+        let power = luminance( self.mat.emission() ) * PI * self.area();
+        LightBounds::facing( self.bounding_box(), power, self.normal )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use std::sync::Arc;
+
+    #[test]
+    fn hits_inside_only() {
+        // 2 x 1 in the z = -1 plane, facing +z
+        let quad = Quad::new( Vec3::new( -1.0, 0.0, -1.0 ), Vec3::new( 2.0, 0.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ),
+                              Arc::new( Lambertian::new( Vec3::ONE ) ) );
+        assert_eq!( quad.area(), 2.0 );
+
+        let rec = quad.hit( &Ray::new( Vec3::new( 0.5, 0.25, 0.0 ), Vec3::new( 0.0, 0.0, -1.0 ) ), 0.001, f32::INFINITY ).unwrap();
+        assert_eq!( rec.t, 1.0 );
+        assert!( rec.front_face );
+        assert_eq!( (rec.u, rec.v), (0.75, 0.25) );
+
+        // from behind
+        let rec = quad.hit( &Ray::new( Vec3::new( 0.0, 0.5, -2.0 ), Vec3::new( 0.0, 0.0, 1.0 ) ), 0.001, f32::INFINITY ).unwrap();
+        assert!( !rec.front_face );
+        assert_eq!( rec.normal, Vec3::new( 0.0, 0.0, -1.0 ) );
+
+        assert!( quad.hit( &Ray::new( Vec3::new( 0.0, 1.5, 0.0 ), Vec3::new( 0.0, 0.0, -1.0 ) ), 0.001, f32::INFINITY ).is_none() );
+        assert!( quad.hit( &Ray::new( Vec3::new( 0.0, 0.5, 0.0 ), Vec3::new( 1.0, 0.0, 0.0 ) ), 0.001, f32::INFINITY ).is_none() );
+        assert!( quad.bounding_box().extent().z > 0.0 );
+    }
+
+    #[test]
+    fn sampled_directions_hit() {
+        let quad = Quad::new( Vec3::new( -1.0, 0.0, -1.0 ), Vec3::new( 2.0, 0.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ),
+                              Arc::new( Lambertian::new( Vec3::ONE ) ) );
+        let origin = Vec3::new( 0.3, 0.2, 3.0 );
+        let mut rng = crate::rng::Rng::new( 1, 0 );
+        for _ in 0..100 {
+            let dir = quad.random_direction( origin, &mut rng );
+            assert!( quad.pdf_value( origin, dir ) > 0.0 );
+        }
+
+        // far away it's about distance squared over area, head on
+        let far = Vec3::new( 0.0, 0.5, 1000.0 );
+        let pdf = quad.pdf_value( far, Vec3::new( 0.0, 0.0, -1.0 ) );
+        assert!( (pdf / (1001.0 * 1001.0 / 2.0) - 1.0).abs() < 1e-3 );
+        assert_eq!( quad.pdf_value( origin, Vec3::new( 0.0, 0.0, 1.0 ) ), 0.0 );
+    }
+}
@@ -1,6 +1,6 @@
-
 use vec3::Vec3;
 
+#[derive(Copy, Clone, Debug)]
 pub struct Ray {
     pub origin : Vec3,
     pub dir : Vec3,
@@ -8,6 +8,10 @@ pub struct Ray {
 
 impl Ray {
 
+    pub fn new( origin : Vec3, dir : Vec3 ) -> Ray {
+        Ray { origin, dir }
+    }
+
     pub fn at( &self, t : f32 ) -> Vec3 {
         self.origin + (self.dir * t)
     }
@@ -0,0 +1,1113 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use vec3::Vec3;
+
+use super::ray::Ray;
+use super::hittable::HitRecord;
+use super::light::{power_heuristic, LightSample};
+use super::scene::{RenderSettings, Scene};
+use super::rng::Rng;
+use super::film::{Film, PixelSum};
+use super::denoise::{denoise_region, DenoiseMode};
+use super::tonemap::luminance;
+use super::scheduler::{TileQueue, Utilization, WorkerPool};
+use super::tile_order::TileOrder;
+
+// Where a tile is in its life. Workers send a copy of the tile back each
+// time it changes, only the Finished one carries results.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileStatus {
+    Clear, // Reset or clear the tile
+    Rendering,
+    Denoising,
+    Finished
+} 
+
+#[derive(Clone)]
+pub struct Tile {
+    pub x : u32,
+    pub y : u32,
+    pub w : u32,
+    pub h : u32,
+    pub status : TileStatus,
+
+    // render job this tile belongs to, see RenderJob
+    pub generation : u32,
+
+    // which progressive pass this is, and how many samples per pixel it takes
+    pub pass : u32,
+    pub spp : u32,
+
+    // rendered: spp samples per pixel, summed
+    pub samples : Option<Vec<PixelSum>>,
+
+    // A tile with a source film is a denoising job rather than a render,
+    // and comes back with denoised radiance instead of samples
+    pub source : Option<Arc<Film>>,
+    pub denoised : Option<Vec<Vec3>>,
+}
+
+impl Tile {
+    pub fn new( x : u32, y : u32, w : u32, h : u32 ) -> Tile {
+        Tile { x, y, w, h, status : TileStatus::Clear, generation : 0, pass : 0, spp : 1,
+               samples : None, source : None, denoised : None }
+    }
+
+    // Empty copy of this tile, to be rendered again for another pass
+    pub fn for_pass( &self, pass : u32, spp : u32 ) -> Tile {
+        Tile { generation : self.generation, pass, spp, ..Tile::new( self.x, self.y, self.w, self.h ) }
+    }
+
+    // Copy of this tile that denoises its region of film
+    pub fn for_denoise( &self, film : Arc<Film> ) -> Tile {
+        Tile { generation : self.generation, source : Some( film ), ..Tile::new( self.x, self.y, self.w, self.h ) }
+    }
+}
+
+// Light from everywhere nothing was hit: the environment if there is one,
+// else a gradient. None when the sky is off.
+fn sky( scene : &Scene, ray : &Ray ) -> Vec3 {
+    if !scene.settings.sky {
+        return Vec3::ZERO;
+    }
+    if let Some(env) = &scene.lights.environment {
+        return env.radiance( ray.dir );
+    }
+    let unit_dir = ray.dir.normalize();    
+    let a = 0.5 * unit_dir.y + 1.0;
+    
+    (1.0-a)*Vec3::ONE + a*Vec3::new( 0.5, 0.7, 1.0)
+}
+
+// One camera path: its radiance, split by how the light got here for the
+// AOVs, plus what it hit first for the denoiser. At every diffuse bounce a
+// light is sampled directly, and light found either way is weighted with
+// MIS so nothing is counted twice.
+fn sample_path( scene : &Scene, camera_ray : &Ray, rng : &mut Rng ) -> PixelSum {
+    let max_depth = scene.settings.max_depth;
+    let sample_lights = scene.settings.light_sampling && !scene.lights.is_empty();
+
+    // punctual lights can't be found any other way, they're sampled regardless
+    let sample_punctual = !sample_lights && !scene.lights.punctual.is_empty();
+
+    let mut sample = PixelSum::default();
+    let mut ray = *camera_ray;
+    let mut throughput = Vec3::ONE;
+    let mut first_specular = false;
+
+    // where the last bounce was and the pdf it was picked with, None when
+    // it was specular and could not have been found by sampling lights
+    let mut last_bounce : Option<(Vec3, f32)> = None;
+
+    for bounce in 0..max_depth {
+
+        // tmin slightly above zero so the ray doesn't re-hit the surface it left
+        let rec = match scene.hit( &ray, 0.001, f32::INFINITY ) {
+            Some(rec) => rec,
+            None => {
+                if bounce == 0 {
+                    sample.albedo = Vec3::ONE;
+                }
+                let weight = mis_weight( scene, sample_lights, last_bounce, &ray );
+                add_light( &mut sample, bounce, first_specular, throughput * sky( scene, &ray ) * weight );
+                break;
+            },
+        };
+
+        if bounce == 0 {
+            sample.albedo = rec.mat.albedo( &rec );
+            sample.normal = rec.normal;
+            sample.depth = (rec.p - ray.origin).length();
+            sample.object_id = rec.object_id;
+            sample.material_id = rec.material_id;
+        }
+
+        let emitted = rec.mat.emitted( &rec );
+        if emitted != Vec3::ZERO {
+            let weight = mis_weight( scene, sample_lights, last_bounce, &ray );
+            add_light( &mut sample, bounce, first_specular, throughput * emitted * weight );
+        }
+
+        let scatter = match rec.mat.scatter( &ray, &rec, rng ) {
+            Some(scatter) => scatter,
+            None => break,
+        };
+        if bounce == 0 {
+            first_specular = scatter.specular;
+        }
+
+        // light reaching this point directly counts as if found by the next bounce
+        let eval = if scatter.specular { None } else { rec.mat.eval( &ray, &rec, scatter.ray.dir ) };
+        if (sample_lights || sample_punctual) && eval.is_some() && bounce + 1 < max_depth {
+            let direct = if sample_lights {
+                sample_light( scene, &ray, &rec, rng )
+            } else {
+                scene.lights.sample_punctual( rec.p, rng ).map_or( Vec3::ZERO, |light| delta_light( scene, &ray, &rec, light ) )
+            };
+            add_light( &mut sample, bounce + 1, first_specular, throughput * direct );
+        }
+
+        last_bounce = eval.map( |(_, pdf)| (rec.p, pdf) );
+        throughput *= scatter.attenuation;
+        ray = scatter.ray;
+    }
+
+    sample.radiance = sample.emission + sample.diffuse_direct + sample.diffuse_indirect
+        + sample.specular_direct + sample.specular_indirect;
+    sample.lum_sq = luminance( sample.radiance ).powi( 2 );
+    sample
+}
+
+// Weight for light a scattered ray found by chance, against sampling the
+// lights finding it too. Rays from specular bounces are never weighted.
+fn mis_weight( scene : &Scene, sample_lights : bool, last_bounce : Option<(Vec3, f32)>, ray : &Ray ) -> f32 {
+    match last_bounce {
+        Some( (origin, bsdf_pdf) ) if sample_lights => power_heuristic( bsdf_pdf, scene.lights.pdf( origin, ray.dir, scene.settings.light_tree ) ),
+        _ => 1.0,
+    }
+}
+
+// Light arriving at a hit from a direction picked on one of the lights,
+// through the BSDF and weighted against finding it by scattering. The
+// shadow ray takes whatever it hits first, so a blocked light gives nothing,
+// and one that escapes sees the environment. Punctual lights can't be found
+// by scattering, they only need a clear line to them.
+fn sample_light( scene : &Scene, ray_in : &Ray, rec : &HitRecord, rng : &mut Rng ) -> Vec3 {
+    let dir = match scene.lights.sample( rec.p, scene.settings.light_tree, rng ) {
+        None => return Vec3::ZERO,
+        Some( LightSample::Direction(dir) ) => dir,
+        Some(delta) => return delta_light( scene, ray_in, rec, delta ),
+    };
+    let light_pdf = scene.lights.pdf( rec.p, dir, scene.settings.light_tree );
+    if light_pdf <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let (f_cos, bsdf_pdf) = match rec.mat.eval( ray_in, rec, dir ) {
+        Some(eval) => eval,
+        None => return Vec3::ZERO,
+    };
+    if f_cos == Vec3::ZERO {
+        return Vec3::ZERO;
+    }
+
+    let shadow_ray = Ray::new( rec.p, dir );
+    let light = match scene.hit( &shadow_ray, 0.001, f32::INFINITY ) {
+        Some(light) => light.mat.emitted( &light ),
+        None if scene.lights.environment.is_some() => sky( scene, &shadow_ray ),
+        None => Vec3::ZERO,
+    };
+    light * f_cos * (power_heuristic( light_pdf, bsdf_pdf ) / light_pdf)
+}
+
+// Light from a punctual light sample, unweighted as nothing else finds it
+fn delta_light( scene : &Scene, ray_in : &Ray, rec : &HitRecord, sample : LightSample ) -> Vec3 {
+    let LightSample::Delta { dir, dist, light, probability } = sample else {
+        return Vec3::ZERO;
+    };
+    let f_cos = match rec.mat.eval( ray_in, rec, dir ) {
+        Some( (f_cos, _) ) if f_cos != Vec3::ZERO && light != Vec3::ZERO => f_cos,
+        _ => return Vec3::ZERO,
+    };
+    if scene.hit( &Ray::new( rec.p, dir ), 0.001, dist ).is_some() {
+        return Vec3::ZERO;
+    }
+    light * f_cos / probability
+}
+
+// Sorts light found along a path into the AOV it belongs to, by how many
+// bounces it took to get to the camera and what the first one was
+fn add_light( sample : &mut PixelSum, bounces : u32, first_specular : bool, light : Vec3 ) {
+    match (bounces, first_specular) {
+        (0, _) => sample.emission += light,
+        (1, false) => sample.diffuse_direct += light,
+        (1, true) => sample.specular_direct += light,
+        (_, false) => sample.diffuse_indirect += light,
+        (_, true) => sample.specular_indirect += light,
+    }
+}
+
+// Sum of spp jittered paths through pixel (i, j). Each pass draws different
+// random numbers, so passes add up to one big set of independent samples.
+pub fn render_pixel( scene : &Scene, i : u32, j : u32, pass : u32, spp : u32 ) -> PixelSum {
+    let settings = &scene.settings;
+    let mut rng = Rng::for_pixel( settings.seed, i, j, pass );
+
+    let mut sum = PixelSum::default();
+    for _ in 0..spp {
+        let ray = scene.camera.ray_at_pixel_jittered( i as i32, j as i32, &mut rng );
+        sum += sample_path( scene, &ray, &mut rng );
+    }
+
+    sum
+}
+
+// Renders the whole image into the film on the calling thread, all passes
+pub fn do_render( scene : &Scene, film : &mut Film ) {
+
+    let full = Tile::new( 0, 0, film.width, film.height );
+    let mut schedule = PassSchedule::new( &scene.settings );
+    while let Some( (pass, spp) ) = schedule.next_pass() {
+        let mut tile = full.for_pass( pass, spp );
+        render_tile( scene, &mut tile );
+
+        if let Some(samples) = &tile.samples {
+            film.add_tile( tile.x, tile.y, tile.w, tile.h, samples, tile.spp );
+        }
+    }
+
+    if scene.settings.denoise != DenoiseMode::Off {
+        let denoised = denoise_region( film, 0, 0, film.width, film.height );
+        film.set_denoised( 0, 0, film.width, film.height, &denoised );
+    }
+}
+
+pub fn render_tile( scene : &Scene, tile : &mut Tile ) {
+    let mut samples = Vec::with_capacity( (tile.w * tile.h) as usize );
+
+    for j in 0..tile.h {                
+        for i in 0..tile.w {
+            samples.push( render_pixel( scene, tile.x + i, tile.y + j, tile.pass, tile.spp ) );
+        }
+    }
+
+    tile.samples = Some( samples );
+    tile.status = TileStatus::Finished;
+
+    // todo return Result
+}
+
+pub fn ceil_div( a : u32, b : u32 ) -> u32 {
+    a.div_ceil( b )
+}
+
+// Splits the image into tiles of at most tile_sz x tile_sz, in the given order
+pub fn make_tiles( width : u32, height : u32, tile_sz : u32, order : TileOrder ) -> Vec<Tile> {
+    let tile_sz = tile_sz.max( 1 );
+    let num_tiles_x = ceil_div( width, tile_sz );
+    let num_tiles_y = ceil_div( height, tile_sz );
+
+    let mut tiles = Vec::new();
+    for tj in 0..num_tiles_y {
+        for ti in 0..num_tiles_x {
+            tiles.push( Tile::new(
+                ti * tile_sz,
+                tj * tile_sz,
+                std::cmp::min( tile_sz, width - ti*tile_sz ),
+                std::cmp::min( tile_sz, height - tj*tile_sz ) ) );
+        }
+    }
+    order.apply( tiles, num_tiles_x, num_tiles_y )
+}
+
+// Most samples per pixel taken in a single pass. Keeps later passes short
+// so the image keeps updating.
+const MAX_PASS_SPP : u32 = 16;
+
+// Decides how many samples each progressive pass takes. The first pass is a
+// single sample for a quick preview, then the count doubles each pass up to
+// MAX_PASS_SPP, until samples_per_pixel is reached or time runs out.
+pub struct PassSchedule {
+    target_spp : u32,
+    time_limit : Option<Duration>,
+    start : Instant,
+    next : u32,
+    spp_done : u32,
+
+    // time spent paused doesn't count against the time limit
+    paused_at : Option<Instant>,
+    paused_total : Duration,
+}
+
+impl PassSchedule {
+
+    pub fn new( settings : &RenderSettings ) -> PassSchedule {
+        PassSchedule {
+            target_spp : settings.samples_per_pixel.max( 1 ),
+            // too big to be a Duration (or infinite) is as good as none
+            time_limit : Duration::try_from_secs_f32( settings.time_limit ).ok().filter( |limit| !limit.is_zero() ),
+            start : Instant::now(),
+            next : 0,
+            spp_done : 0,
+            paused_at : None,
+            paused_total : Duration::ZERO,
+        }
+    }
+
+    // (pass, spp) for the next pass, or None if we're done. Time is only
+    // checked between passes, so a pass that starts in time always finishes.
+    pub fn next_pass( &mut self ) -> Option<(u32, u32)> {
+        if self.spp_done >= self.target_spp {
+            return None;
+        }
+        if self.time_limit.is_some_and( |limit| self.elapsed() >= limit ) {
+            return None;
+        }
+
+        let pass = self.next;
+        let spp = (1u32 << pass.min( 31 )).min( MAX_PASS_SPP ).min( self.target_spp - self.spp_done );
+        self.next += 1;
+        self.spp_done += spp;
+        Some( (pass, spp) )
+    }
+
+    // samples per pixel handed out so far
+    pub fn spp_done( &self ) -> u32 {
+        self.spp_done
+    }
+
+    pub fn target_spp( &self ) -> u32 {
+        self.target_spp
+    }
+
+    // render time so far, not counting pauses
+    pub fn elapsed( &self ) -> Duration {
+        let now = self.paused_at.unwrap_or_else( Instant::now );
+        now.duration_since( self.start ).saturating_sub( self.paused_total )
+    }
+
+    pub fn pause( &mut self ) {
+        self.paused_at.get_or_insert_with( Instant::now );
+    }
+
+    pub fn resume( &mut self ) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.paused_total += paused_at.elapsed();
+        }
+    }
+}
+
+// The passes handed out so far, shared with the workers that take them
+struct Passes {
+    layout : Vec<Tile>,
+    schedule : PassSchedule,
+    queued : u32,
+}
+
+impl Passes {
+
+    // tiles of the next pass, None if there are no more
+    fn next( &mut self ) -> Option<Vec<Tile>> {
+        let (pass, spp) = self.schedule.next_pass()?;
+        self.queued = pass + 1;
+        Some( self.layout.iter().map( |tile| tile.for_pass( pass, spp ) ).collect() )
+    }
+}
+
+// Feeds passes of tiles to the render threads. Each pass covers the whole
+// image. Workers take the next pass themselves as soon as the current one
+// is all handed out, so they never sit waiting for its last tiles to come
+// back; those are counted per pass to tell when a pass is complete.
+pub struct Progressive {
+    passes : Arc<Mutex<Passes>>,
+    queue : Arc<TileQueue>,
+    num_tiles : usize,
+    finished : bool,
+
+    // tiles back from each pass
+    done : Vec<usize>,
+}
+
+impl Progressive {
+
+    pub fn new( layout : Vec<Tile>, settings : &RenderSettings, queue : Arc<TileQueue> ) -> Progressive {
+        let num_tiles = layout.len();
+        let passes = Arc::new( Mutex::new( Passes { layout, schedule : PassSchedule::new( settings ), queued : 0 } ) );
+        let mut progressive = Progressive { passes : passes.clone(), queue, num_tiles, finished : false, done : Vec::new() };
+
+        let first = progressive.passes().next();
+        progressive.queue_pass( first, Vec::new() );
+        if !progressive.finished {
+            progressive.queue.set_feeder( Box::new( move || passes.lock().unwrap().next() ) );
+        }
+        progressive
+    }
+
+    fn passes( &self ) -> MutexGuard<'_, Passes> {
+        self.passes.lock().unwrap()
+    }
+
+    // Tiles of the pass after pass, which has just completed, for
+    // queue_pass(). Empty if the workers already took that pass, None if
+    // there's no pass after it.
+    pub fn pass_after( &mut self, pass : u32 ) -> Option<Vec<Tile>> {
+        let mut passes = self.passes();
+        if passes.queued > pass + 1 {
+            return Some( Vec::new() );
+        }
+        passes.next()
+    }
+
+    // Queues tiles from pass_after() behind some extra tiles. With no pass
+    // left the queue is closed, and the workers exit once they've done the
+    // extra tiles.
+    pub fn queue_pass( &mut self, next : Option<Vec<Tile>>, extra : Vec<Tile> ) {
+        let last = next.is_none();
+        let mut tiles = extra;
+        tiles.extend( next.unwrap_or_default() );
+        if !tiles.is_empty() {
+            self.queue.push_batch( tiles );
+        }
+        if last {
+            self.stop();
+        }
+    }
+
+    // Call for every finished render tile, in order of the passes for each
+    // tile. Returns true when that was the last one of its pass.
+    pub fn tile_done( &mut self, pass : u32 ) -> bool {
+        if self.finished {
+            return false;
+        }
+        let pass = pass as usize;
+        if self.done.len() <= pass {
+            self.done.resize( pass + 1, 0 );
+        }
+        self.done[pass] += 1;
+        self.done[pass] == self.num_tiles
+    }
+
+    pub fn is_finished( &self ) -> bool {
+        self.finished
+    }
+
+    // Queue no more passes. Tiles already handed out still come back.
+    pub fn stop( &mut self ) {
+        self.finished = true;
+        self.queue.close();
+    }
+
+    pub fn pause( &mut self ) {
+        self.passes().schedule.pause();
+    }
+
+    pub fn resume( &mut self ) {
+        self.passes().schedule.resume();
+    }
+
+    // samples per pixel handed out so far, of how many
+    pub fn spp( &self ) -> (u32, u32) {
+        let passes = self.passes();
+        (passes.schedule.spp_done(), passes.schedule.target_spp())
+    }
+
+    pub fn elapsed( &self ) -> Duration {
+        self.passes().schedule.elapsed()
+    }
+
+    // e.g. "pass 3, 8/64 spp, 1.2s"
+    pub fn status( &self ) -> String {
+        let passes = self.passes();
+        format!( "{}pass {}, {}/{} spp, {:.1}s",
+                 if self.is_finished() { "done, " } else { "" },
+                 passes.queued.max( 1 ), passes.schedule.spp_done(), passes.schedule.target_spp(),
+                 passes.schedule.elapsed().as_secs_f32() )
+    }
+
+    pub fn num_tiles( &self ) -> usize {
+        self.num_tiles
+    }
+}
+
+impl Drop for Progressive {
+    fn drop( &mut self ) {
+        // don't leave workers waiting for a pass that will never come
+        self.queue.close();
+    }
+}
+
+// Shared between a RenderJob and its workers
+pub struct JobControl {
+    cancelled : AtomicBool,
+    paused : Mutex<bool>,
+    resumed : Condvar,
+}
+
+impl JobControl {
+
+    pub fn new() -> JobControl {
+        JobControl { cancelled : AtomicBool::new( false ), paused : Mutex::new( false ), resumed : Condvar::new() }
+    }
+
+    pub fn cancel( &self ) {
+        self.cancelled.store( true, Ordering::Relaxed );
+        // wake anyone paused so they can see it
+        self.set_paused( false );
+    }
+
+    pub fn is_cancelled( &self ) -> bool {
+        self.cancelled.load( Ordering::Relaxed )
+    }
+
+    pub fn set_paused( &self, paused : bool ) {
+        *self.paused.lock().unwrap() = paused;
+        self.resumed.notify_all();
+    }
+
+    pub fn is_paused( &self ) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    // Blocks while paused. Returns false if the job was cancelled.
+    fn wait_if_paused( &self ) -> bool {
+        let mut paused = self.paused.lock().unwrap();
+        while *paused {
+            paused = self.resumed.wait( paused ).unwrap();
+        }
+        !self.is_cancelled()
+    }
+}
+
+impl Default for JobControl {
+    fn default() -> JobControl {
+        JobControl::new()
+    }
+}
+
+// Starts num_threads workers taking tiles from queue and sending them,
+// rendered, to tx_done_tiles. Workers exit once the queue is closed and
+// drained, or as soon as control is cancelled.
+pub fn spawn_render_threads( scene : Arc<Scene>, queue : Arc<TileQueue>, tx_done_tiles : mpsc::Sender<Tile>,
+                             num_threads : usize, control : Arc<JobControl> ) -> WorkerPool {
+
+    WorkerPool::spawn( num_threads, queue, move |_, mut tile| {
+
+        // pausing holds on to the tile until resumed
+        if !control.wait_if_paused() {
+            return false;
+        }
+
+        // let the UI know what we're working on, then do it. Receiver gone
+        // means nobody wants the result, stop.
+        let source = tile.source.take();
+        tile.status = if source.is_some() { TileStatus::Denoising } else { TileStatus::Rendering };
+        if tx_done_tiles.send( tile.clone() ).is_err() {
+            return false;
+        }
+
+        match source {
+            Some(film) => {
+                tile.denoised = Some( denoise_region( &film, tile.x, tile.y, tile.w, tile.h ) );
+                tile.status = TileStatus::Finished;
+            },
+            None => render_tile( &scene, &mut tile ),
+        }
+        tx_done_tiles.send( tile ).is_ok()
+    })
+}
+
+// Denoising needs an apron around each tile, bigger tiles waste less on it
+const DENOISE_TILE_SZ : u32 = 64;
+
+// One render of one scene: the progressive passes, the threads working on
+// them and the film they accumulate into. Finished tiles go to the
+// tx_done_tiles given to start(), stamped with the generation, so whoever is
+// collecting them can tell tiles from an old job apart after a restart, and
+// should be handed back through tile_done().
+pub struct RenderJob {
+    generation : u32,
+    control : Arc<JobControl>,
+    progressive : Progressive,
+    workers : WorkerPool,
+    cancelled : bool,
+
+    film : Film,
+    denoise : DenoiseMode,
+    tile_order : TileOrder,
+
+    // denoise tiles handed out and not back yet
+    denoising : usize,
+
+    // Passes overlap, so a tile's next pass can come back before the one
+    // before it. For each tile, by position, the pass the film takes next;
+    // later ones wait in early, so each pixel sums its passes in order and
+    // the image doesn't depend on the number of threads.
+    next_pass : HashMap<(u32, u32), u32>,
+    early : Vec<Tile>,
+}
+
+impl RenderJob {
+
+    pub fn start( scene : Arc<Scene>, num_threads : usize, generation : u32,
+                  tx_done_tiles : mpsc::Sender<Tile> ) -> RenderJob {
+        let settings = &scene.settings;
+        let mut layout = make_tiles( scene.camera.width, scene.camera.height, settings.tile_size, settings.tile_order );
+        for tile in &mut layout {
+            tile.generation = generation;
+        }
+
+        let queue = Arc::new( TileQueue::new() );
+        let progressive = Progressive::new( layout, &scene.settings, queue.clone() );
+
+        let film = Film::new( scene.camera.width, scene.camera.height );
+        let (denoise, tile_order) = (scene.settings.denoise, scene.settings.tile_order);
+
+        let control = Arc::new( JobControl::new() );
+        let workers = spawn_render_threads( scene, queue, tx_done_tiles, num_threads, control.clone() );
+
+        RenderJob { generation, control, progressive, workers, cancelled : false, film, denoise, tile_order, denoising : 0,
+                    next_pass : HashMap::new(), early : Vec::new() }
+    }
+
+    // Cancels this job and starts another on a new scene, one generation on
+    pub fn restart( &mut self, scene : Arc<Scene>, num_threads : usize, tx_done_tiles : mpsc::Sender<Tile> ) {
+        self.cancel();
+        *self = RenderJob::start( scene, num_threads, self.generation.wrapping_add( 1 ), tx_done_tiles );
+    }
+
+    pub fn generation( &self ) -> u32 {
+        self.generation
+    }
+
+    // Stops the workers after the tile they're on. Doesn't wait for them.
+    pub fn cancel( &mut self ) {
+        self.cancelled = true;
+        self.control.cancel();
+        self.progressive.stop();
+    }
+
+    pub fn set_paused( &mut self, paused : bool ) {
+        if self.cancelled {
+            return;
+        }
+        if paused {
+            self.progressive.pause();
+        } else {
+            self.progressive.resume();
+        }
+        self.control.set_paused( paused );
+    }
+
+    pub fn is_paused( &self ) -> bool {
+        self.control.is_paused()
+    }
+
+    // Call for every tile received, finished ones go into the film. Returns
+    // false, and ignores the tile, if it came from some other job.
+    pub fn tile_done( &mut self, tile : &Tile ) -> bool {
+        if tile.generation != self.generation || self.cancelled {
+            return false;
+        }
+        if tile.status != TileStatus::Finished {
+            return true;
+        }
+
+        if tile.samples.is_some() {
+            let at = (tile.x, tile.y);
+            if tile.pass != *self.next_pass.entry( at ).or_insert( 0 ) {
+                self.early.push( tile.clone() );
+            } else {
+                self.add_pass( tile );
+                while let Some(ndx) = self.early.iter().position( |t| (t.x, t.y) == at && t.pass == self.next_pass[&at] ) {
+                    let waiting = self.early.swap_remove( ndx );
+                    self.add_pass( &waiting );
+                }
+            }
+        }
+        if let Some(denoised) = &tile.denoised {
+            self.film.set_denoised( tile.x, tile.y, tile.w, tile.h, denoised );
+            self.denoising = self.denoising.saturating_sub( 1 );
+        }
+        true
+    }
+
+    // A tile's samples, the next pass the film takes for it
+    fn add_pass( &mut self, tile : &Tile ) {
+        if let Some(samples) = &tile.samples {
+            self.film.add_tile( tile.x, tile.y, tile.w, tile.h, samples, tile.spp );
+        }
+        *self.next_pass.entry( (tile.x, tile.y) ).or_insert( 0 ) += 1;
+        if self.progressive.tile_done( tile.pass ) {
+            self.pass_done( tile.pass );
+        }
+    }
+
+    // A whole pass is in the film, and maybe some of the next. Denoise it if
+    // asked to, then go on.
+    fn pass_done( &mut self, pass : u32 ) {
+        let next = self.progressive.pass_after( pass );
+        let denoise = match self.denoise {
+            DenoiseMode::Off => false,
+            DenoiseMode::Final => next.is_none(),
+            DenoiseMode::EveryPass => true,
+        };
+
+        let mut extra = Vec::new();
+        if denoise {
+            // the workers read a snapshot, the film keeps changing under them
+            let snapshot = Arc::new( self.film.clone() );
+            extra = make_tiles( self.film.width, self.film.height, DENOISE_TILE_SZ, self.tile_order ).iter()
+                .map( |tile| Tile { generation : self.generation, ..tile.for_denoise( snapshot.clone() ) } )
+                .collect();
+            self.denoising += extra.len();
+        }
+        self.progressive.queue_pass( next, extra );
+    }
+
+    pub fn film( &self ) -> &Film {
+        &self.film
+    }
+
+    // all passes are in and denoised (or the job was cancelled)
+    pub fn is_finished( &self ) -> bool {
+        self.cancelled || (self.progressive.is_finished() && self.denoising == 0)
+    }
+
+    pub fn status( &self ) -> String {
+        let status = if self.denoising > 0 {
+            format!( "{}, denoising", self.progressive.status() )
+        } else {
+            self.progressive.status()
+        };
+
+        if self.cancelled {
+            "cancelled".to_string()
+        } else if self.is_paused() {
+            format!( "paused, {}", status )
+        } else {
+            status
+        }
+    }
+
+    pub fn utilization( &self ) -> Vec<Utilization> {
+        self.workers.utilization()
+    }
+
+    pub fn utilization_summary( &self ) -> String {
+        self.workers.utilization_summary()
+    }
+
+    // Name, value pairs describing the render so far, for file headers
+    pub fn stats( &self ) -> Vec<(String, String)> {
+        let (spp_done, target_spp) = self.progressive.spp();
+        vec![
+            ( "software".to_string(), "rtview".to_string() ),
+            ( "samplesPerPixel".to_string(), spp_done.to_string() ),
+            ( "targetSamplesPerPixel".to_string(), target_spp.to_string() ),
+            ( "renderTime".to_string(), format!( "{:.3}s", self.progressive.elapsed().as_secs_f32() ) ),
+            ( "threads".to_string(), self.workers.num_threads().to_string() ),
+            ( "threadUtilization".to_string(), self.utilization_summary() ),
+            ( "denoise".to_string(), self.denoise.name().to_string() ),
+        ]
+    }
+
+    // Waits for the workers to exit, and hands over the film
+    pub fn join( self ) -> Result<Film, String> {
+        let RenderJob { workers, progressive, film, .. } = self;
+        drop( progressive );
+        workers.join()?;
+        Ok( film )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aov_passes_add_up_to_beauty() {
+        let scene = Scene::demo( 32, 20 );
+        for j in 0..20 {
+            for i in 0..32 {
+                let sum = render_pixel( &scene, i, j, 0, 4 );
+                let parts = sum.emission + sum.diffuse_direct + sum.diffuse_indirect + sum.specular_direct + sum.specular_indirect;
+                assert!( (parts - sum.radiance).length() <= 1e-4 * (1.0 + sum.radiance.length()), "({}, {})", i, j );
+            }
+        }
+
+        // the demo numbers the ground, then the centre, left and right
+        // spheres, each with its own material
+        let ids = |i, j| {
+            let sum = render_pixel( &scene, i, j, 0, 4 );
+            (sum.object_id, sum.material_id)
+        };
+        assert_eq!( ids( 30, 17 ), (1, 1) );
+        assert_eq!( ids( 16, 10 ), (2, 2) );
+        assert_eq!( ids( 5, 13 ), (3, 3) );
+        assert_eq!( ids( 23, 3 ), (4, 4) );
+    }
+
+    #[test]
+    fn shared_material_ids() {
+        use crate::material::{Lambertian, MaterialRef};
+        use crate::sphere::Sphere;
+
+        // two balls of the same grey either side of a red one
+        let mut scene = Scene::new( 8, 8 );
+        let grey : MaterialRef = Arc::new( Lambertian::new( Vec3::from_float( 0.5 ) ) );
+        let red : MaterialRef = Arc::new( Lambertian::new( Vec3::new( 0.7, 0.1, 0.1 ) ) );
+        scene.add( Box::new( Sphere::new( Vec3::new( -2.0, 0.0, -5.0 ), 0.5, grey.clone() ) ) );
+        scene.add( Box::new( Sphere::new( Vec3::new( 0.0, 0.0, -5.0 ), 0.5, red ) ) );
+        scene.add( Box::new( Sphere::new( Vec3::new( 2.0, 0.0, -5.0 ), 0.5, grey ) ) );
+
+        let mut rng = Rng::new( 3, 0 );
+        let mut ids = |x : f32| {
+            let sample = sample_path( &scene, &Ray::new( Vec3::ZERO, Vec3::new( x, 0.0, -5.0 ) ), &mut rng );
+            (sample.object_id, sample.material_id)
+        };
+        assert_eq!( ids( -2.0 ), (1, 1) );
+        assert_eq!( ids( 0.0 ), (2, 2) );
+        assert_eq!( ids( 2.0 ), (3, 1) );
+        assert_eq!( ids( 5.0 ), (0, 0) );
+    }
+
+    #[test]
+    fn lit_by_emitters_only() {
+        use crate::material::{DiffuseLight, Lambertian};
+        use crate::sphere::Sphere;
+
+        // inside a glowing sphere, looking at a grey ball. Without light
+        // sampling every bounce off the ball finds exactly the glow.
+        let mut scene = Scene::new( 8, 8 );
+        scene.settings.sky = false;
+        scene.settings.light_sampling = false;
+        scene.add( Box::new( Sphere::new( Vec3::ZERO, -10.0, Arc::new( DiffuseLight::new( Vec3::from_float( 2.0 ) ) ) ) ) );
+        scene.add( Box::new( Sphere::new( Vec3::new( 0.0, 0.0, -1.0 ), 0.2, Arc::new( Lambertian::new( Vec3::from_float( 0.5 ) ) ) ) ) );
+
+        let mut rng = Rng::for_pixel( 0, 0, 0, 0 );
+        let sample = sample_path( &scene, &Ray::new( Vec3::ZERO, Vec3::new( 0.0, 0.0, -1.0 ) ), &mut rng );
+        assert_eq!( sample.radiance, Vec3::from_float( 1.0 ) );
+        assert_eq!( sample.diffuse_direct, Vec3::from_float( 1.0 ) );
+        let sample = sample_path( &scene, &Ray::new( Vec3::ZERO, Vec3::new( 0.0, 1.0, 0.0 ) ), &mut rng );
+        assert_eq!( sample.emission, Vec3::from_float( 2.0 ) );
+
+        // nothing to hit, the sky only shows when it's on
+        scene.world.clear();
+        let up = Ray::new( Vec3::ZERO, Vec3::new( 0.0, 1.0, 0.0 ) );
+        assert_eq!( sample_path( &scene, &up, &mut rng ).radiance, Vec3::ZERO );
+        scene.settings.sky = true;
+        assert_eq!( sample_path( &scene, &up, &mut rng ).radiance, sky( &scene, &up ) );
+        assert!( sky( &scene, &up ).length() > 0.0 );
+    }
+
+    #[test]
+    fn light_sampling_matches_brute_force() {
+        use crate::material::{DiffuseLight, Lambertian};
+        use crate::quad::Quad;
+
+        // a small lamp 2 units above a grey floor
+        let mut scene = Scene::new( 8, 8 );
+        scene.settings.sky = false;
+        scene.add( Box::new( Quad::new( Vec3::new( -50.0, 0.0, -50.0 ), Vec3::new( 0.0, 0.0, 100.0 ), Vec3::new( 100.0, 0.0, 0.0 ),
+                                        Arc::new( Lambertian::new( Vec3::from_float( 0.5 ) ) ) ) ) );
+        scene.add( Box::new( Quad::new( Vec3::new( -0.5, 2.0, -0.5 ), Vec3::new( 1.0, 0.0, 0.0 ), Vec3::new( 0.0, 0.0, 1.0 ),
+                                        Arc::new( DiffuseLight::new( Vec3::from_float( 10.0 ) ) ) ) ) );
+        assert_eq!( scene.lights.len(), 1 );
+
+        // mean and variance of the light the floor reflects up at the camera
+        let estimate = |scene : &Scene| {
+            let n = 20000;
+            let mut rng = Rng::new( 9, 0 );
+            let (mut sum, mut sum_sq) = (0.0, 0.0);
+            for _ in 0..n {
+                let sample = sample_path( scene, &Ray::new( Vec3::new( 0.3, 1.0, 0.0 ), Vec3::new( 0.0, -1.0, 0.0 ) ), &mut rng );
+                assert!( (sample.diffuse_direct - sample.radiance).length() < 1e-6 );
+                sum += sample.radiance.x as f64;
+                sum_sq += (sample.radiance.x as f64).powi( 2 );
+            }
+            let mean = sum / n as f64;
+            (mean, sum_sq / n as f64 - mean * mean)
+        };
+
+        let (nee_mean, nee_var) = estimate( &scene );
+        scene.settings.light_sampling = false;
+        let (brute_mean, brute_var) = estimate( &scene );
+        assert!( (nee_mean / brute_mean - 1.0).abs() < 0.05, "{} vs {}", nee_mean, brute_mean );
+        assert!( nee_var * 10.0 < brute_var, "{} vs {}", nee_var, brute_var );
+    }
+
+    #[test]
+    fn lit_by_environment() {
+        use crate::environment::EnvMap;
+        use crate::hdr_image::HdrImage;
+        use crate::material::Lambertian;
+        use crate::sphere::Sphere;
+
+        // a grey ball under an even white sky (with a hot spot the ball can't
+        // see) reflects its albedo, with or without sampling the sky
+        let mut image = HdrImage::new( 32, 16 );
+        image.pixels.iter_mut().for_each( |p| *p = Vec3::ONE );
+        image.pixels[15 * 32] = Vec3::from_float( 1000.0 );
+        let mut scene = Scene::new( 8, 8 );
+        scene.set_environment( Arc::new( EnvMap::new( image, 0.0, 1.0 ).unwrap() ) );
+        scene.add( Box::new( Sphere::new( Vec3::ZERO, 1.0, Arc::new( Lambertian::new( Vec3::from_float( 0.5 ) ) ) ) ) );
+
+        let ray = Ray::new( Vec3::new( 0.0, 3.0, 0.0 ), Vec3::new( 0.0, -1.0, 0.0 ) );
+        for light_sampling in [ true, false ] {
+            scene.settings.light_sampling = light_sampling;
+            let mut rng = Rng::new( 6, 0 );
+            let n = 4000;
+            let sum = (0..n).fold( Vec3::ZERO, |sum, _| sum + sample_path( &scene, &ray, &mut rng ).radiance );
+            let mean = sum / n as f32;
+            assert!( (mean - Vec3::from_float( 0.5 )).length() < 0.02, "{:?} with light sampling {}", mean, light_sampling );
+        }
+        assert_eq!( sky( &scene, &Ray::new( Vec3::ZERO, Vec3::new( 0.3, 0.2, 0.1 ) ) ), Vec3::ONE );
+    }
+
+    #[test]
+    fn lit_by_point_light() {
+        use crate::material::Lambertian;
+        use crate::punctual::PunctualLight;
+        use crate::quad::Quad;
+        use crate::sphere::Sphere;
+
+        // grey floor, a point light 2 above: albedo / pi * I / d^2 = 1
+        let mut scene = Scene::new( 8, 8 );
+        scene.settings.sky = false;
+        scene.add( Box::new( Quad::new( Vec3::new( -5.0, 0.0, 5.0 ), Vec3::new( 10.0, 0.0, 0.0 ), Vec3::new( 0.0, 0.0, -10.0 ),
+                                        Arc::new( Lambertian::new( Vec3::from_float( 0.5 ) ) ) ) ) );
+        scene.add_light( PunctualLight::point( Vec3::new( 0.0, 2.0, 0.0 ), Vec3::from_float( 8.0 * std::f32::consts::PI ) ) );
+
+        // the same brute force, when nothing else is sampled
+        let ray = Ray::new( Vec3::new( 0.0, 1.0, 1.0 ), Vec3::new( 0.0, -1.0, -1.0 ) );
+        let mut rng = Rng::new( 8, 0 );
+        for light_sampling in [ true, false ] {
+            scene.settings.light_sampling = light_sampling;
+            let sample = sample_path( &scene, &ray, &mut rng );
+            assert!( (sample.radiance - Vec3::ONE).length() < 1e-5, "{:?}", sample.radiance );
+            assert_eq!( sample.radiance, sample.diffuse_direct );
+        }
+
+        // can't be seen, and doesn't get through things
+        assert_eq!( sample_path( &scene, &Ray::new( Vec3::new( 0.0, 1.0, 0.0 ), Vec3::new( 0.0, 1.0, 0.0 ) ), &mut rng ).radiance, Vec3::ZERO );
+        scene.add( Box::new( Sphere::new( Vec3::new( 0.0, 1.0, 0.0 ), 0.2, Arc::new( Lambertian::new( Vec3::ONE ) ) ) ) );
+        assert_eq!( sample_path( &scene, &ray, &mut rng ).radiance, Vec3::ZERO );
+    }
+
+    #[test]
+    fn pass_schedule_adds_up_to_target() {
+        let settings = RenderSettings { samples_per_pixel : 100, ..Default::default() };
+        let mut schedule = PassSchedule::new( &settings );
+        let passes : Vec<(u32, u32)> = std::iter::from_fn( || schedule.next_pass() ).collect();
+
+        assert_eq!( passes[0], (0, 1) );
+        assert!( passes.iter().all( |&(_, spp)| spp <= MAX_PASS_SPP ) );
+        assert_eq!( passes.iter().map( |&(_, spp)| spp ).sum::<u32>(), 100 );
+        assert_eq!( schedule.spp_done(), 100 );
+    }
+
+    #[test]
+    fn huge_time_limit_is_no_limit() {
+        for time_limit in [ f32::INFINITY, 1e20, 1e30, f32::NAN, -1.0, 0.0 ] {
+            let settings = RenderSettings { samples_per_pixel : 4, time_limit, ..Default::default() };
+            let mut schedule = PassSchedule::new( &settings );
+            assert_eq!( std::iter::from_fn( || schedule.next_pass() ).count(), 3, "{}", time_limit );
+        }
+    }
+
+    #[test]
+    fn progressive_queues_next_pass_when_done() {
+        let settings = RenderSettings { samples_per_pixel : 3, ..Default::default() };
+        let queue = Arc::new( TileQueue::new() );
+        let mut progressive = Progressive::new( make_tiles( 64, 32, 32, TileOrder::Scanline ), &settings, queue.clone() );
+        let drain = || std::iter::from_fn( || queue.try_take() ).collect::<Vec<Tile>>();
+
+        // pass 0 is queued straight away; with no worker asking for pass 1
+        // yet, it's taken once both tiles are back
+        let first = drain();
+        assert_eq!( first.len(), 2 );
+        assert!( first.iter().all( |t| t.pass == 0 && t.spp == 1 ) );
+        assert!( !progressive.tile_done( 0 ) );
+        assert!( queue.try_take().is_none() );
+        assert!( progressive.tile_done( 0 ) );
+        let next = progressive.pass_after( 0 );
+        progressive.queue_pass( next, Vec::new() );
+
+        let second = drain();
+        assert_eq!( second.len(), 2 );
+        assert!( second.iter().all( |t| t.pass == 1 && t.spp == 2 ) );
+        assert!( !progressive.tile_done( 1 ) );
+        assert!( progressive.tile_done( 1 ) );
+        assert!( progressive.pass_after( 1 ).is_none() );
+        progressive.queue_pass( None, Vec::new() );
+
+        assert!( progressive.is_finished() );
+        assert!( queue.try_take().is_none() );
+    }
+
+    #[test]
+    fn workers_never_wait_between_passes() {
+        // nothing hands tiles back, yet the workers get through every pass:
+        // each one is queued as soon as the one before is handed out
+        let settings = RenderSettings { samples_per_pixel : 40, ..Default::default() };
+        let queue = Arc::new( TileQueue::new() );
+        let progressive = Progressive::new( make_tiles( 64, 64, 16, TileOrder::Scanline ), &settings, queue.clone() );
+        let (tx, rx) = mpsc::channel();
+        let pool = WorkerPool::spawn( 3, queue, move |_, tile : Tile| tx.send( (tile.pass, tile.spp) ).is_ok() );
+
+        let mut tiles = [ 0; 16 ];
+        let passes = 5;
+        for (pass, spp) in rx.iter().take( 16 * passes ) {
+            tiles[pass as usize] += 1;
+            assert!( spp <= MAX_PASS_SPP, "{}", spp );
+        }
+        assert_eq!( &tiles[..passes], &[ 16; 5 ] );
+        assert_eq!( progressive.spp(), (40, 40) );
+        drop( progressive );
+        pool.join().unwrap();
+    }
+
+    #[test]
+    fn image_independent_of_threads() {
+        // however the threads' tiles interleave, each pixel sums its passes in order
+        let mut scene = Scene::new( 48, 32 );
+        scene.settings.samples_per_pixel = 20;
+        scene.settings.tile_size = 8;
+        let scene = Arc::new( scene );
+
+        let render = |threads| {
+            let (tx_done, rx_done) = mpsc::channel();
+            let mut job = RenderJob::start( scene.clone(), threads, 0, tx_done );
+            for tile in rx_done.iter() {
+                job.tile_done( &tile );
+            }
+            assert!( job.is_finished() );
+            job.join().unwrap()
+        };
+        let one = render( 1 );
+        let four = render( 4 );
+        for y in 0..32 {
+            for x in 0..48 {
+                assert_eq!( one.pixel( x, y ), four.pixel( x, y ), "at {}, {}", x, y );
+            }
+        }
+    }
+
+    #[test]
+    fn restarted_job_ignores_stale_tiles() {
+        let mut scene = Scene::new( 16, 16 );
+        scene.settings.samples_per_pixel = 1;
+        scene.settings.tile_size = 8;
+        let scene = Arc::new( scene );
+
+        let (tx_done, rx_done) = mpsc::channel();
+        let mut job = RenderJob::start( scene.clone(), 2, 0, tx_done.clone() );
+        job.set_paused( true );
+        assert!( job.is_paused() );
+
+        job.restart( scene, 2, tx_done );
+        assert_eq!( job.generation(), 1 );
+
+        let stale = Tile::new( 0, 0, 8, 8 );
+        assert!( !job.tile_done( &stale ) );
+
+        // only the new job's 4 tiles count towards finishing it, each
+        // reported as rendering before it comes back finished
+        let mut rendering = 0;
+        while !job.is_finished() {
+            let tile = rx_done.recv().unwrap();
+            if tile.generation == 1 {
+                assert!( job.tile_done( &tile ) );
+                match tile.status {
+                    TileStatus::Rendering => rendering += 1,
+                    TileStatus::Finished => assert!( tile.samples.is_some() ),
+                    other => panic!( "unexpected {:?}", other ),
+                }
+            }
+        }
+        assert_eq!( rendering, 4 );
+        job.join().unwrap();
+    }
+}
@@ -1,72 +1,249 @@
 use vec3::Vec3;
 
 use super::ray::Ray;
+use super::hittable::{HitRecord, Hittable, HittableList, Tagged};
+use super::sphere::Sphere;
+use super::light::LightList;
+use super::environment::Environment;
+use super::punctual::PunctualLight;
+use super::bvh::BvhNode;
+use super::mesh::TriangleMesh;
+use super::obj::{self, ObjError, ObjFile, ObjMaterial};
+use super::material::{Dielectric, Lambertian, MaterialRef, Metal};
+use super::camera::{Camera, CameraSettings};
+use super::tile_order::TileOrder;
+use super::denoise::DenoiseMode;
+
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    // rays traced through each pixel, each at a random spot inside it
+    pub samples_per_pixel : u32,
+
+    // maximum number of bounces along a path
+    pub max_depth : u32,
+
+    // same seed, same image
+    pub seed : u64,
+
+    // stop starting new passes after this many seconds, 0 (or infinity)
+    // for no limit
+    pub time_limit : f32,
+
+    // work is handed out in tile_size squares, in tile_order
+    pub tile_size : u32,
+    pub tile_order : TileOrder,
+
+    // when to run the denoiser over the accumulated image
+    pub denoise : DenoiseMode,
+
+    // light from the sky gradient; off for closed interiors lit only by emitters
+    pub sky : bool,
+
+    // aim a shadow ray at a light from every diffuse bounce, weighted against
+    // hitting lights by chance (MIS); off for plain path tracing, to compare.
+    // Punctual lights can't be hit, so they're sampled either way.
+    pub light_sampling : bool,
+
+    // pick which light to sample by how much each might give (a light
+    // tree), rather than all alike
+    pub light_tree : bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            samples_per_pixel : 16,
+            max_depth : 10,
+            seed : 0,
+            time_limit : 0.0,
+            tile_size : 32,
+            tile_order : TileOrder::Scanline,
+            denoise : DenoiseMode::Off,
+            sky : true,
+            light_sampling : true,
+            light_tree : true,
+        }
+    }
+}
 
 pub struct Scene {
 
-    // camera settings
-    camera_center : Vec3,
-    viewport_u : Vec3,
-    viewport_v : Vec3,
-    pixel_delta_u : Vec3,
-    pixel_delta_v : Vec3,
-    pixel00_loc : Vec3,
+    // everything that can be hit by a ray
+    pub world : HittableList,
+
+    // acceleration structure, built from world by build_bvh()
+    accel : Option<BvhNode>,
+
+    // emissive objects, also in world or accel
+    pub lights : LightList,
+
+    pub settings : RenderSettings,
+
+    pub camera : Camera,
+
+    // distinct materials in the order first added, material id n is materials[n-1]
+    materials : Vec<MaterialRef>,
+    num_objects : u32,
 }
 
 impl Scene {
 
     pub fn new( width: u32, height: u32 ) -> Scene
     {
-        let fwidth = width as f32;
-        let fheight = height as f32;
-        let aspect = fheight / fwidth;
-    
-        // camera
-        let focal_length  :f32   = 1.0;
-        let viewport_height : f32 = 2.0;
-        let viewport_width = viewport_height / aspect;
-        let camera_center = Vec3::ZERO;
-        println!("setup_camera: Aspect {} w {} h {}", aspect, viewport_width, viewport_height );
-    
-        let viewport_u = Vec3::new( viewport_width, 0.0, 0.0 );
-        let viewport_v = Vec3::new( 0.0, -viewport_height, 0.0);
-    
-        // upper left
-        let viewport_upper_left = camera_center
-                            - Vec3::new( 0.0,0.0, focal_length )
-                            - viewport_u/2.0
-                            - viewport_v/2.0;
-        let pixel_delta_u = viewport_u / fwidth;
-        let pixel_delta_v = viewport_v / fheight;
-    
-        let pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
-
-        // Create the scene object
         Scene {
-            camera_center : camera_center,
-            viewport_u : viewport_u,
-            viewport_v : viewport_v,
-            pixel_delta_u : pixel_delta_u,
-            pixel_delta_v : pixel_delta_v,
-            pixel00_loc : pixel00_loc,
-        }    
+            world : HittableList::new(),
+            accel : None,
+            lights : LightList::new(),
+            settings : RenderSettings::default(),
+            camera : Camera::new( width, height, &CameraSettings::default() ),
+            materials : Vec::new(),
+            num_objects : 0,
+        }
     }
 
-    pub fn ray_at_pixel_loc( &self, i : i32, j : i32 ) -> Ray 
+    // The default scene, three spheres (glass, diffuse, metal) on a big one
+    pub fn demo( width: u32, height: u32 ) -> Scene
     {
-        let ii = i as f32;
-        let jj = j as f32;
+        let mut scene = Scene::new( width, height );
+        scene.camera = Camera::new( width, height, &CameraSettings {
+            look_from : Vec3::new( -2.0, 2.0, 1.0 ),
+            look_at : Vec3::new( 0.0, 0.0, -1.0 ),
+            vfov : 30.0,
+            aperture : 0.1,
+            ..Default::default()
+        });
+
+        let ground : MaterialRef = Arc::new( Lambertian::new( Vec3::new( 0.8, 0.8, 0.0 ) ) );
+        let center : MaterialRef = Arc::new( Lambertian::new( Vec3::new( 0.1, 0.2, 0.5 ) ) );
+        let left : MaterialRef = Arc::new( Dielectric::new( 1.5 ) );
+        let right : MaterialRef = Arc::new( Metal::new( Vec3::new( 0.8, 0.6, 0.2 ), 0.0 ) );
 
-        let pixel_center = 
-            self.pixel00_loc + (ii * self.pixel_delta_u) + (jj * self.pixel_delta_v);
-        let ray_direction = pixel_center - self.camera_center;
+        scene.add( Box::new( Sphere::new( Vec3::new( 0.0, -100.5, -1.0 ), 100.0, ground ) ) );
+        scene.add( Box::new( Sphere::new( Vec3::new( 0.0, 0.0, -1.0 ), 0.5, center ) ) );
+        scene.add( Box::new( Sphere::new( Vec3::new( -1.0, 0.0, -1.0 ), 0.5, left ) ) );
+        scene.add( Box::new( Sphere::new( Vec3::new( 1.0, 0.0, -1.0 ), 0.5, right ) ) );
+        scene.build_bvh();
 
-        Ray { 
-            origin : self.camera_center, 
-            dir : ray_direction,
-         }
+        scene
     }
 
+    // Adds an object, numbering it and its material for the id AOVs. Objects
+    // with an emissive material are lights too, or their parts are.
+    pub fn add( &mut self, object : Box<dyn Hittable + Send + Sync> ) {
+        self.num_objects += 1;
+        let material_id = match object.material() {
+            Some(mat) => match self.materials.iter().position( |m| Arc::ptr_eq( m, mat ) ) {
+                Some(ndx) => ndx as u32 + 1,
+                None => {
+                    self.materials.push( mat.clone() );
+                    self.materials.len() as u32
+                },
+            },
+            None => 0,
+        };
+        let emissive = object.material().is_some_and( |mat| mat.is_emissive() );
+        let tagged = Tagged { object, object_id : self.num_objects, material_id };
+        let parts = if emissive { tagged.light_parts() } else { Vec::new() };
+        if emissive && parts.is_empty() {
+            let light = Arc::new( tagged );
+            self.lights.add( light.clone() );
+            self.world.add( Box::new( light ) );
+        } else {
+            for part in parts {
+                self.lights.add( part );
+            }
+            self.world.add( Box::new( tagged ) );
+        }
+    }
+
+    // Imports every mesh in an OBJ file. Returns the number of triangles added.
+    pub fn load_obj( &mut self, path : &std::path::Path ) -> Result<usize, ObjError> {
+        let obj = obj::load_obj( path )?;
+        Ok( self.add_obj( obj, None ) )
+    }
+
+    // Adds the meshes of an already loaded OBJ. If mat is given it's used for
+    // everything, otherwise materials come from the MTL file.
+    pub fn add_obj( &mut self, obj : ObjFile, mat : Option<MaterialRef> ) -> usize {
+        let num_tris = obj.num_triangles();
+
+        let materials : Vec<MaterialRef> = obj.materials.iter().map( material_from_obj ).collect();
+        let default_mat : MaterialRef = Arc::new( Lambertian::new( Vec3::from_float( 0.8 ) ) );
+        for mesh in obj.meshes {
+            let mesh_mat = match (&mat, mesh.material) {
+                (Some(mat), _) => mat.clone(),
+                (None, Some(ndx)) => materials[ndx].clone(),
+                (None, None) => default_mat.clone(),
+            };
+            self.add( Box::new( TriangleMesh::new( mesh.data, mesh_mat ) ) );
+        }
 
+        num_tris
+    }
+
+    // Adds a light with no size, which only lights the scene and can't be seen
+    pub fn add_light( &mut self, light : PunctualLight ) {
+        self.lights.add_punctual( light );
+    }
+
+    // Surrounds the scene with an environment, lighting it and shown
+    // wherever nothing is hit
+    pub fn set_environment( &mut self, env : Arc<dyn Environment> ) {
+        self.lights.environment = Some( env );
+    }
 
+    // With the sky off the environment gives no light, drop it so light
+    // sampling doesn't waste shadow rays on it. Call once the settings are final.
+    pub fn drop_environment_if_sky_off( &mut self ) {
+        if !self.settings.sky {
+            self.lights.environment = None;
+        }
+    }
+
+    // Moves everything added so far into a BVH. Call once the scene is
+    // populated; anything added afterwards is still hit, just not accelerated.
+    pub fn build_bvh( &mut self ) {
+        let mut objects = std::mem::take( &mut self.world.objects );
+        if let Some(accel) = self.accel.take() {
+            objects.push( Box::new( accel ) );
+        }
+        self.accel = Some( BvhNode::new( objects ) );
+    }
+
+    // Closest hit against everything in the scene
+    pub fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        let mut closest_so_far = ray_tmax;
+        let mut result = None;
+        if let Some(accel) = &self.accel {
+            result = accel.hit( ray, ray_tmin, closest_so_far );
+            if let Some(rec) = &result {
+                closest_so_far = rec.t;
+            }
+        }
+        if !self.world.is_empty() {
+            if let Some(rec) = self.world.hit( ray, ray_tmin, closest_so_far ) {
+                result = Some(rec);
+            }
+        }
+        result
+    }
+
+}
+
+// Best guess at a renderable material for an MTL description. Transparent
+// or refractive illum models become glass, reflective ones metal, anything
+// else diffuse.
+fn material_from_obj( mtl : &ObjMaterial ) -> MaterialRef {
+    let max_ks = mtl.specular.x.max( mtl.specular.y ).max( mtl.specular.z );
+    if mtl.dissolve < 1.0 || matches!( mtl.illum, 4 | 6 | 7 | 9 ) {
+        Arc::new( Dielectric::new( if mtl.ior > 1.0 { mtl.ior } else { 1.5 } ) )
+    } else if matches!( mtl.illum, 3 | 5 | 8 ) && max_ks > 0.0 {
+        // Ns runs 0..1000, higher is shinier
+        let fuzz = 1.0 - (mtl.shininess / 1000.0).clamp( 0.0, 1.0 ).sqrt();
+        Arc::new( Metal::new( mtl.specular, fuzz ) )
+    } else {
+        Arc::new( Lambertian::new( mtl.diffuse ) )
+    }
 }
@@ -0,0 +1,1063 @@
+// Text scene description
+//
+// A scene file is a list of blocks. Each block has a kind, an optional name
+// and a set of `key = value` entries:
+//
+//     render { spp = 64  max_depth = 12 }
+//
+//     camera {
+//         look_from = [-2, 2, 1]
+//         look_at = [0, 0, -1]
+//         vfov = 30
+//     }
+//
+//     material red { type = lambertian  albedo = [0.8, 0.1, 0.1] }
+//
+//     sphere { center = [0, 0, -1]  radius = 0.5  material = red }
+//
+// Rough metals and glass scatter off microfacets, roughness 0 to 1.
+// Conductors take a metal (gold, copper or aluminium) or its eta and k:
+//
+//     material brushed { type = conductor  metal = gold  roughness = 0.3 }
+//     material frosted { type = dielectric  ior = 1.5  roughness = 0.2 }
+//
+//     material lamp { type = emissive  emit = [1, 0.9, 0.8]  intensity = 15 }
+//     quad { corner = [-1, 2, -1]  u = [2, 0, 0]  v = [0, 0, 2]  material = lamp }
+//
+//     environment { file = "sky.hdr"  rotation = 90  intensity = 1.5 }
+//
+// or, instead of a map, daylight for a time of day:
+//
+//     sky { sun_elevation = 30  sun_azimuth = 120  turbidity = 3  ground_albedo = 0.2 }
+//
+// Lights with no size only light the scene. Point and spot lights can take
+// the distribution of a real fixture from an IES file, in candela, which
+// intensity then scales:
+//
+//     point_light { position = [0, 2.9, 0]  ies = "downlight.ies"  intensity = 0.01 }
+//     spot_light { position = [2, 3, 2]  look_at = [0, 0, 0]  angle = 25  blend = 5  intensity = 40 }
+//     directional_light { direction = [-1, -2, -1]  color = [1, 0.95, 0.9]  intensity = 3 }
+//
+// Values are numbers, strings ("..."), vectors ([x, y, z]) or bare words,
+// which are used for enum-like settings and to refer to materials by name.
+// `#` starts a comment. Errors report the file, line and column at fault.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use vec3::Vec3;
+
+use super::scene::Scene;
+use super::camera::{Camera, CameraSettings};
+use super::sphere::Sphere;
+use super::quad::Quad;
+use super::material::{Dielectric, DiffuseLight, Lambertian, MaterialRef, Metal};
+use super::microfacet::{RoughConductor, RoughDielectric};
+use super::obj::{self, ObjError};
+use super::hdr_image;
+use super::environment::EnvMap;
+use super::sky::{PreethamSky, SkySettings};
+use super::punctual::PunctualLight;
+use super::ies;
+use super::tile_order::TileOrder;
+use super::denoise::DenoiseMode;
+
+#[derive(Debug)]
+pub enum SceneError {
+    // couldn't read the scene file
+    Io { path : PathBuf, err : io::Error },
+
+    // syntax error, unknown key, wrong type, bad reference, ...
+    Parse { path : PathBuf, line : usize, col : usize, msg : String },
+
+    // a mesh referenced from the scene failed to load
+    Mesh { path : PathBuf, line : usize, col : usize, err : ObjError },
+
+    // so did an image
+    Image { path : PathBuf, line : usize, col : usize, err : io::Error },
+
+    // or a light's IES profile
+    Profile { path : PathBuf, line : usize, col : usize, err : io::Error },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match self {
+            SceneError::Io { path, err } =>
+                write!( f, "{}: {}", path.display(), err ),
+            SceneError::Parse { path, line, col, msg } =>
+                write!( f, "{}:{}:{}: {}", path.display(), line, col, msg ),
+            SceneError::Mesh { path, line, col, err } =>
+                write!( f, "{}:{}:{}: loading mesh: {}", path.display(), line, col, err ),
+            SceneError::Image { path, line, col, err } =>
+                write!( f, "{}:{}:{}: loading image: {}", path.display(), line, col, err ),
+            SceneError::Profile { path, line, col, err } =>
+                write!( f, "{}:{}:{}: loading IES profile: {}", path.display(), line, col, err ),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source( &self ) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { err, .. } => Some( err ),
+            SceneError::Mesh { err, .. } => Some( err ),
+            SceneError::Image { err, .. } => Some( err ),
+            SceneError::Profile { err, .. } => Some( err ),
+            _ => None,
+        }
+    }
+}
+
+// Line and column, both 1-based
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pos {
+    pub line : usize,
+    pub col : usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    // and the text it was read from
+    Num(f32, String),
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Comma,
+    Equals,
+}
+
+impl Token {
+    fn describe( &self ) -> String {
+        match self {
+            Token::Word(w) => format!( "'{}'", w ),
+            Token::Str(s) => format!( "string \"{}\"", s ),
+            Token::Num(n, _) => format!( "number {}", n ),
+            Token::LBrace => "'{'".to_string(),
+            Token::RBrace => "'}'".to_string(),
+            Token::LBracket => "'['".to_string(),
+            Token::RBracket => "']'".to_string(),
+            Token::Comma => "','".to_string(),
+            Token::Equals => "'='".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Num(f32),
+    Str(String),
+    Word(String),
+    List(Vec<f32>),
+}
+
+impl Value {
+    fn type_name( &self ) -> &'static str {
+        match self {
+            Value::Num(_) => "a number",
+            Value::Str(_) => "a string",
+            Value::Word(_) => "a name",
+            Value::List(_) => "a list",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub key : String,
+    pub key_pos : Pos,
+    pub value : Value,
+    pub value_pos : Pos,
+
+    // a number as written, which f32 may have rounded
+    pub text : String,
+}
+
+#[derive(Debug)]
+pub struct Block {
+    pub kind : String,
+    pub name : Option<String>,
+    pub pos : Pos,
+    pub entries : Vec<Entry>,
+}
+
+struct Parser<'a> {
+    path : &'a Path,
+    tokens : Vec<(Token, Pos)>,
+    next : usize,
+    eof : Pos,
+}
+
+fn tokenize( text : &str, path : &Path ) -> Result<(Vec<(Token, Pos)>, Pos), SceneError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    let mut col = 1;
+
+    let perr = |line, col, msg : String| SceneError::Parse { path : path.to_path_buf(), line, col, msg };
+
+    while let Some(&c) = chars.peek() {
+        let pos = Pos { line, col };
+
+        if c == '\n' {
+            chars.next();
+            line += 1;
+            col = 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            chars.next();
+            col += 1;
+            continue;
+        }
+        if c == '#' {
+            while chars.peek().is_some_and( |&c| c != '\n' ) {
+                chars.next();
+            }
+            continue;
+        }
+
+        let single = match c {
+            '{' => Some( Token::LBrace ),
+            '}' => Some( Token::RBrace ),
+            '[' => Some( Token::LBracket ),
+            ']' => Some( Token::RBracket ),
+            ',' => Some( Token::Comma ),
+            '=' => Some( Token::Equals ),
+            _ => None,
+        };
+        if let Some(tok) = single {
+            chars.next();
+            col += 1;
+            tokens.push( (tok, pos) );
+            continue;
+        }
+
+        if c == '"' {
+            chars.next();
+            col += 1;
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => { col += 1; break; },
+                    Some('\n') | None => return Err( perr( pos.line, pos.col, "unterminated string".to_string() ) ),
+                    Some(ch) => { s.push( ch ); col += 1; },
+                }
+            }
+            tokens.push( (Token::Str(s), pos) );
+            continue;
+        }
+
+        if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' {
+            let mut s = String::new();
+            while let Some(&ch) = chars.peek() {
+                let exp_sign = (ch == '-' || ch == '+') && s.ends_with( ['e', 'E'] );
+                if ch.is_ascii_digit() || ch == '.' || ch == 'e' || ch == 'E' || exp_sign || s.is_empty() {
+                    s.push( ch );
+                    chars.next();
+                    col += 1;
+                } else {
+                    break;
+                }
+            }
+            let n : f32 = s.parse().map_err( |_| perr( pos.line, pos.col, format!( "bad number '{}'", s ) ) )?;
+            tokens.push( (Token::Num(n, s), pos) );
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            let mut s = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch.is_alphanumeric() || ch == '_' || ch == '-' || ch == '.' {
+                    s.push( ch );
+                    chars.next();
+                    col += 1;
+                } else {
+                    break;
+                }
+            }
+            tokens.push( (Token::Word(s), pos) );
+            continue;
+        }
+
+        return Err( perr( line, col, format!( "unexpected character '{}'", c ) ) );
+    }
+
+    Ok( (tokens, Pos { line, col }) )
+}
+
+impl Parser<'_> {
+
+    fn error( &self, pos : Pos, msg : String ) -> SceneError {
+        SceneError::Parse { path : self.path.to_path_buf(), line : pos.line, col : pos.col, msg }
+    }
+
+    fn peek( &self ) -> Option<&(Token, Pos)> {
+        self.tokens.get( self.next )
+    }
+
+    fn take( &mut self, what : &str ) -> Result<(Token, Pos), SceneError> {
+        match self.tokens.get( self.next ) {
+            Some(tok) => {
+                self.next += 1;
+                Ok( tok.clone() )
+            },
+            None => Err( self.error( self.eof, format!( "expected {}, got end of file", what ) ) ),
+        }
+    }
+
+    fn expect( &mut self, expected : Token ) -> Result<Pos, SceneError> {
+        let (tok, pos) = self.take( &expected.describe() )?;
+        if tok != expected {
+            return Err( self.error( pos, format!( "expected {}, got {}", expected.describe(), tok.describe() ) ) );
+        }
+        Ok( pos )
+    }
+
+    fn parse_blocks( &mut self ) -> Result<Vec<Block>, SceneError> {
+        let mut blocks = Vec::new();
+        while self.peek().is_some() {
+            blocks.push( self.parse_block()? );
+        }
+        Ok( blocks )
+    }
+
+    fn parse_block( &mut self ) -> Result<Block, SceneError> {
+        let (tok, pos) = self.take( "a block" )?;
+        let Token::Word(kind) = tok else {
+            return Err( self.error( pos, format!( "expected a block like 'sphere {{ ... }}', got {}", tok.describe() ) ) );
+        };
+
+        let name = match self.peek() {
+            Some( (Token::Word(name), _) ) | Some( (Token::Str(name), _) ) => {
+                let name = name.clone();
+                self.next += 1;
+                Some( name )
+            },
+            _ => None,
+        };
+
+        self.expect( Token::LBrace )?;
+
+        let mut entries : Vec<Entry> = Vec::new();
+        loop {
+            let (tok, key_pos) = self.take( "a key or '}'" )?;
+            let key = match tok {
+                Token::RBrace => break,
+                Token::Word(key) => key,
+                other => return Err( self.error( key_pos, format!( "expected a key or '}}', got {}", other.describe() ) ) ),
+            };
+            if entries.iter().any( |e| e.key == key ) {
+                return Err( self.error( key_pos, format!( "'{}' is set twice", key ) ) );
+            }
+
+            self.expect( Token::Equals )?;
+            let (value, value_pos, text) = self.parse_value()?;
+            entries.push( Entry { key, key_pos, value, value_pos, text } );
+        }
+
+        Ok( Block { kind, name, pos, entries } )
+    }
+
+    // the value, where it is, and the number's text if it's a number
+    fn parse_value( &mut self ) -> Result<(Value, Pos, String), SceneError> {
+        let (tok, pos) = self.take( "a value" )?;
+        let value = match tok {
+            Token::Num(n, text) => return Ok( (Value::Num(n), pos, text) ),
+            Token::Str(s) => Value::Str(s),
+            Token::Word(w) => Value::Word(w),
+            Token::LBracket => {
+                let mut list = Vec::new();
+                loop {
+                    let (tok, item_pos) = self.take( "a number or ']'" )?;
+                    match tok {
+                        Token::RBracket => break,
+                        Token::Num(n, _) => list.push( n ),
+                        other => return Err( self.error( item_pos, format!( "expected a number, got {}", other.describe() ) ) ),
+                    }
+                    match self.take( "',' or ']'" )? {
+                        (Token::Comma, _) => {},
+                        (Token::RBracket, _) => break,
+                        (other, p) => return Err( self.error( p, format!( "expected ',' or ']', got {}", other.describe() ) ) ),
+                    }
+                }
+                Value::List(list)
+            },
+            other => return Err( self.error( pos, format!( "expected a value, got {}", other.describe() ) ) ),
+        };
+        Ok( (value, pos, String::new()) )
+    }
+}
+
+// Parses the text into blocks without interpreting them
+pub fn parse_blocks( text : &str, path : &Path ) -> Result<Vec<Block>, SceneError> {
+    let (tokens, eof) = tokenize( text, path )?;
+    let mut parser = Parser { path, tokens, next : 0, eof };
+    parser.parse_blocks()
+}
+
+// Typed, validated access to a block's entries
+struct BlockReader<'a> {
+    block : &'a Block,
+    path : &'a Path,
+}
+
+impl<'a> BlockReader<'a> {
+
+    fn new( block : &'a Block, path : &'a Path, allowed : &[&str] ) -> Result<BlockReader<'a>, SceneError> {
+        let reader = BlockReader { block, path };
+        for entry in &block.entries {
+            if !allowed.contains( &entry.key.as_str() ) {
+                return Err( reader.error( entry.key_pos, format!( "unknown key '{}' in {} (expected one of: {})",
+                                                                  entry.key, block.kind, allowed.join( ", " ) ) ) );
+            }
+        }
+        Ok( reader )
+    }
+
+    fn error( &self, pos : Pos, msg : String ) -> SceneError {
+        SceneError::Parse { path : self.path.to_path_buf(), line : pos.line, col : pos.col, msg }
+    }
+
+    fn entry( &self, key : &str ) -> Option<&'a Entry> {
+        self.block.entries.iter().find( |e| e.key == key )
+    }
+
+    fn type_error( &self, entry : &Entry, expected : &str ) -> SceneError {
+        self.error( entry.value_pos, format!( "'{}' should be {}, got {}", entry.key, expected, entry.value.type_name() ) )
+    }
+
+    fn missing( &self, key : &str ) -> SceneError {
+        self.error( self.block.pos, format!( "{} is missing '{}'", self.block.kind, key ) )
+    }
+
+    fn f32( &self, key : &str ) -> Result<Option<f32>, SceneError> {
+        match self.entry( key ) {
+            None => Ok( None ),
+            Some( Entry { value : Value::Num(n), .. } ) => Ok( Some( *n ) ),
+            Some(entry) => Err( self.type_error( entry, "a number" ) ),
+        }
+    }
+
+    fn u32( &self, key : &str ) -> Result<Option<u32>, SceneError> {
+        Ok( self.whole( key, u32::MAX as u64 )?.map( |n| n as u32 ) )
+    }
+
+    fn u64( &self, key : &str ) -> Result<Option<u64>, SceneError> {
+        self.whole( key, u64::MAX )
+    }
+
+    // Read from the text, as f32 can't hold every whole number above 2^24.
+    // Written any other way (64.0, 1e3) it has to be one f32 holds exactly.
+    fn whole( &self, key : &str, max : u64 ) -> Result<Option<u64>, SceneError> {
+        const EXACT : f32 = (1 << f32::MANTISSA_DIGITS) as f32;
+        match self.entry( key ) {
+            None => Ok( None ),
+            Some( entry @ Entry { value : Value::Num(n), .. } ) => {
+                let exact = entry.text.parse::<u64>().ok()
+                    .or( (*n >= 0.0 && n.fract() == 0.0 && *n <= EXACT).then_some( *n as u64 ) );
+                match exact {
+                    Some(n) if n <= max => Ok( Some( n ) ),
+                    _ => Err( self.type_error( entry, &format!( "a whole number up to {}", max ) ) ),
+                }
+            },
+            Some(entry) => Err( self.type_error( entry, "a whole number" ) ),
+        }
+    }
+
+    fn vec3( &self, key : &str ) -> Result<Option<Vec3>, SceneError> {
+        match self.entry( key ) {
+            None => Ok( None ),
+            Some( Entry { value : Value::List(v), .. } ) if v.len() == 3 => Ok( Some( Vec3::new( v[0], v[1], v[2] ) ) ),
+            Some(entry) => Err( self.type_error( entry, "a list of 3 numbers" ) ),
+        }
+    }
+
+    // colors and scales may be a single number, meaning the same on all three channels
+    fn vec3_or_f32( &self, key : &str ) -> Result<Option<Vec3>, SceneError> {
+        match self.entry( key ) {
+            None => Ok( None ),
+            Some( Entry { value : Value::Num(n), .. } ) => Ok( Some( Vec3::from_float( *n ) ) ),
+            Some( Entry { value : Value::List(v), .. } ) if v.len() == 3 => Ok( Some( Vec3::new( v[0], v[1], v[2] ) ) ),
+            Some(entry) => Err( self.type_error( entry, "a number or a list of 3 numbers" ) ),
+        }
+    }
+
+    fn string( &self, key : &str ) -> Result<Option<&'a str>, SceneError> {
+        match self.entry( key ) {
+            None => Ok( None ),
+            Some( Entry { value : Value::Str(s), .. } ) => Ok( Some( s ) ),
+            Some(entry) => Err( self.type_error( entry, "a string" ) ),
+        }
+    }
+
+    // a bare word, or a string; returns where it was for error reporting
+    fn name( &self, key : &str ) -> Result<Option<(&'a str, Pos)>, SceneError> {
+        match self.entry( key ) {
+            None => Ok( None ),
+            Some( Entry { value : Value::Word(s), value_pos, .. } ) |
+            Some( Entry { value : Value::Str(s), value_pos, .. } ) => Ok( Some( (s, *value_pos) ) ),
+            Some(entry) => Err( self.type_error( entry, "a name" ) ),
+        }
+    }
+
+    // on or off
+    fn on_off( &self, key : &str ) -> Result<Option<bool>, SceneError> {
+        match self.name( key )? {
+            None => Ok( None ),
+            Some( ("on", _) ) => Ok( Some( true ) ),
+            Some( ("off", _) ) => Ok( Some( false ) ),
+            Some( (name, pos) ) => Err( self.error( pos, format!( "'{}' should be on or off, got '{}'", key, name ) ) ),
+        }
+    }
+
+    fn required<T>( &self, key : &str, value : Option<T> ) -> Result<T, SceneError> {
+        value.ok_or_else( || self.missing( key ) )
+    }
+}
+
+pub fn load_scene( path : &Path ) -> Result<Scene, SceneError> {
+    let text = fs::read_to_string( path )
+        .map_err( |err| SceneError::Io { path : path.to_path_buf(), err } )?;
+    parse_scene( &text, path )
+}
+
+// Builds a scene from scene file text. `path` is used for error messages
+// and to resolve relative file names.
+pub fn parse_scene( text : &str, path : &Path ) -> Result<Scene, SceneError> {
+    let blocks = parse_blocks( text, path )?;
+    let base_dir = path.parent().unwrap_or( Path::new( "" ) );
+
+    let perr = |pos : Pos, msg : String| SceneError::Parse { path : path.to_path_buf(), line : pos.line, col : pos.col, msg };
+
+    // first pass: materials, so objects can refer to ones defined later in the file
+    let mut materials : HashMap<String, MaterialRef> = HashMap::new();
+    for block in blocks.iter().filter( |b| b.kind == "material" ) {
+        let Some(name) = &block.name else {
+            return Err( perr( block.pos, "material needs a name, like 'material red { ... }'".to_string() ) );
+        };
+        if materials.contains_key( name ) {
+            return Err( perr( block.pos, format!( "material '{}' is defined twice", name ) ) );
+        }
+        materials.insert( name.clone(), build_material( block, path )? );
+    }
+
+    let lookup_material = |reader : &BlockReader| -> Result<Option<MaterialRef>, SceneError> {
+        match reader.name( "material" )? {
+            None => Ok( None ),
+            Some( (name, pos) ) => match materials.get( name ) {
+                Some(mat) => Ok( Some( mat.clone() ) ),
+                None => Err( perr( pos, format!( "unknown material '{}'", name ) ) ),
+            },
+        }
+    };
+
+    // render settings first, the image size is needed for the camera
+    let mut scene = Scene::new( 320, 200 );
+    let mut camera_settings = CameraSettings::default();
+    let mut have_render = false;
+    let mut have_camera = false;
+
+    for block in &blocks {
+        match block.kind.as_str() {
+            "render" => {
+                if have_render {
+                    return Err( perr( block.pos, "only one render block allowed".to_string() ) );
+                }
+                have_render = true;
+
+                let r = BlockReader::new( block, path, &[ "width", "height", "spp", "max_depth", "seed", "time_limit",
+                                                               "tile_size", "tile_order", "denoise", "sky",
+                                                               "light_sampling", "light_tree" ] )?;
+                let settings = &mut scene.settings;
+                let width = r.u32( "width" )?.unwrap_or( scene.camera.width );
+                let height = r.u32( "height" )?.unwrap_or( scene.camera.height );
+                if width == 0 || height == 0 {
+                    return Err( perr( block.pos, "image size must be at least 1x1".to_string() ) );
+                }
+                settings.samples_per_pixel = r.u32( "spp" )?.unwrap_or( settings.samples_per_pixel );
+                settings.max_depth = r.u32( "max_depth" )?.unwrap_or( settings.max_depth );
+                settings.seed = r.u64( "seed" )?.unwrap_or( settings.seed );
+                settings.time_limit = r.f32( "time_limit" )?.unwrap_or( settings.time_limit );
+                settings.tile_size = r.u32( "tile_size" )?.unwrap_or( settings.tile_size );
+                if settings.tile_size == 0 {
+                    return Err( perr( r.entry( "tile_size" ).map_or( block.pos, |e| e.value_pos ), "tile_size must be at least 1".to_string() ) );
+                }
+                if let Some( (name, pos) ) = r.name( "tile_order" )? {
+                    settings.tile_order = TileOrder::from_name( name ).ok_or_else( || perr( pos, format!(
+                        "unknown tile order '{}' (expected scanline, spiral, hilbert or center-out)", name ) ) )?;
+                }
+                if let Some( (name, pos) ) = r.name( "denoise" )? {
+                    settings.denoise = DenoiseMode::from_name( name ).ok_or_else( || perr( pos, format!(
+                        "unknown denoise mode '{}' (expected off, final or every-pass)", name ) ) )?;
+                }
+                settings.sky = r.on_off( "sky" )?.unwrap_or( settings.sky );
+                settings.light_sampling = r.on_off( "light_sampling" )?.unwrap_or( settings.light_sampling );
+                settings.light_tree = r.on_off( "light_tree" )?.unwrap_or( settings.light_tree );
+                scene.camera = scene.camera.resized( width, height );
+            },
+            "camera" => {
+                if have_camera {
+                    return Err( perr( block.pos, "only one camera block allowed".to_string() ) );
+                }
+                have_camera = true;
+
+                let r = BlockReader::new( block, path, &[ "look_from", "look_at", "up", "vfov", "aperture", "focus_dist" ] )?;
+                let c = &mut camera_settings;
+                c.look_from = r.vec3( "look_from" )?.unwrap_or( c.look_from );
+                c.look_at = r.vec3( "look_at" )?.unwrap_or( c.look_at );
+                c.vup = r.vec3( "up" )?.unwrap_or( c.vup );
+                c.vfov = r.f32( "vfov" )?.unwrap_or( c.vfov );
+                c.aperture = r.f32( "aperture" )?.unwrap_or( c.aperture );
+                c.focus_dist = r.f32( "focus_dist" )?.unwrap_or( c.focus_dist );
+
+                let view = c.look_from - c.look_at;
+                if view.length_squared() == 0.0 {
+                    return Err( perr( block.pos, "look_from and look_at are the same point".to_string() ) );
+                }
+
+                // no way to tell which way is up looking straight along it
+                if Vec3::cross( &c.vup, &view ).length_squared() <= 1e-12 * c.vup.length_squared() * view.length_squared() {
+                    let pos = r.entry( "up" ).map_or( block.pos, |e| e.value_pos );
+                    return Err( perr( pos, "up is parallel to the view direction".to_string() ) );
+                }
+                if !(c.vfov > 0.0 && c.vfov < 180.0) {
+                    let pos = r.entry( "vfov" ).map_or( block.pos, |e| e.value_pos );
+                    return Err( perr( pos, "vfov must be between 0 and 180 degrees".to_string() ) );
+                }
+            },
+            _ => {},
+        }
+    }
+    scene.camera = Camera::new( scene.camera.width, scene.camera.height, &camera_settings );
+
+    // then geometry
+    for block in &blocks {
+        match block.kind.as_str() {
+            "render" | "camera" | "material" => {},
+            "sphere" => {
+                let r = BlockReader::new( block, path, &[ "center", "radius", "material" ] )?;
+                let center = r.required( "center", r.vec3( "center" )? )?;
+                let radius = r.required( "radius", r.f32( "radius" )? )?;
+                let mat = r.required( "material", lookup_material( &r )? )?;
+                scene.add( Box::new( Sphere::new( center, radius, mat ) ) );
+            },
+            "quad" => {
+                let r = BlockReader::new( block, path, &[ "corner", "u", "v", "material" ] )?;
+                let corner = r.required( "corner", r.vec3( "corner" )? )?;
+                let u = r.required( "u", r.vec3( "u" )? )?;
+                let v = r.required( "v", r.vec3( "v" )? )?;
+                let mat = r.required( "material", lookup_material( &r )? )?;
+                if Vec3::cross( &u, &v ).length_squared() == 0.0 {
+                    return Err( perr( block.pos, "quad edges u and v are parallel".to_string() ) );
+                }
+                scene.add( Box::new( Quad::new( corner, u, v, mat ) ) );
+            },
+            "mesh" => {
+                let r = BlockReader::new( block, path, &[ "file", "material", "scale", "translate" ] )?;
+                let file = r.required( "file", r.string( "file" )? )?;
+                let mat = lookup_material( &r )?;
+                let scale = r.vec3_or_f32( "scale" )?.unwrap_or( Vec3::ONE );
+                let translate = r.vec3( "translate" )?.unwrap_or( Vec3::ZERO );
+
+                // flattened triangles have no normal
+                if scale.x * scale.y * scale.z == 0.0 {
+                    let pos = r.entry( "scale" ).map_or( block.pos, |e| e.value_pos );
+                    return Err( perr( pos, "mesh scale can't be zero along any axis".to_string() ) );
+                }
+
+                let file_pos = r.entry( "file" ).map_or( block.pos, |e| e.value_pos );
+                let mut obj = obj::load_obj( &base_dir.join( file ) ).map_err( |err| SceneError::Mesh {
+                    path : path.to_path_buf(), line : file_pos.line, col : file_pos.col, err } )?;
+                for mesh in &mut obj.meshes {
+                    mesh.data.transform( scale, translate );
+                }
+                scene.add_obj( obj, mat );
+            },
+            "environment" | "sky" if scene.lights.environment.is_some() => {
+                return Err( perr( block.pos, "only one environment or sky block allowed".to_string() ) );
+            },
+            "environment" => {
+                let r = BlockReader::new( block, path, &[ "file", "rotation", "intensity" ] )?;
+                let file = r.required( "file", r.string( "file" )? )?;
+                let rotation = r.f32( "rotation" )?.unwrap_or( 0.0 );
+                let intensity = r.f32( "intensity" )?.unwrap_or( 1.0 );
+
+                let file_pos = r.entry( "file" ).map_or( block.pos, |e| e.value_pos );
+                let env_map = hdr_image::load( &base_dir.join( file ) )
+                    .and_then( |image| EnvMap::new( image, rotation, intensity ) )
+                    .map_err( |err| SceneError::Image { path : path.to_path_buf(), line : file_pos.line, col : file_pos.col, err } )?;
+                scene.set_environment( Arc::new( env_map ) );
+            },
+            "point_light" | "spot_light" | "directional_light" => {
+                scene.add_light( build_light( block, path, base_dir )? );
+            },
+            "sky" => {
+                let r = BlockReader::new( block, path, &[ "sun_elevation", "sun_azimuth", "turbidity", "ground_albedo",
+                                                               "sun_size", "intensity" ] )?;
+                let defaults = SkySettings::default();
+                let settings = SkySettings {
+                    sun_elevation : r.f32( "sun_elevation" )?.unwrap_or( defaults.sun_elevation ),
+                    sun_azimuth : r.f32( "sun_azimuth" )?.unwrap_or( defaults.sun_azimuth ),
+                    turbidity : r.f32( "turbidity" )?.unwrap_or( defaults.turbidity ),
+                    ground_albedo : r.vec3_or_f32( "ground_albedo" )?.unwrap_or( defaults.ground_albedo ),
+                    sun_size : r.f32( "sun_size" )?.unwrap_or( defaults.sun_size ),
+                    intensity : r.f32( "intensity" )?.unwrap_or( defaults.intensity ),
+                };
+                if !(1.7..=10.0).contains( &settings.turbidity ) {
+                    return Err( perr( r.entry( "turbidity" ).map_or( block.pos, |e| e.value_pos ),
+                                      format!( "turbidity should be between 1.7 and 10, got {}", settings.turbidity ) ) );
+                }
+                scene.set_environment( Arc::new( PreethamSky::new( &settings ) ) );
+            },
+            other => {
+                return Err( perr( block.pos, format!( "unknown block '{}' (expected render, camera, material, sphere, quad, mesh, \
+                                                     point_light, spot_light, directional_light, environment or sky)", other ) ) );
+            },
+        }
+    }
+
+    scene.drop_environment_if_sky_off();
+    scene.build_bvh();
+    Ok( scene )
+}
+
+fn build_light( block : &Block, path : &Path, base_dir : &Path ) -> Result<PunctualLight, SceneError> {
+    let keys : &[&str] = match block.kind.as_str() {
+        "point_light" => &[ "position", "color", "intensity", "ies", "aim" ],
+        "spot_light" => &[ "position", "direction", "look_at", "angle", "blend", "color", "intensity", "ies" ],
+        _ => &[ "direction", "color", "intensity" ],
+    };
+    let r = BlockReader::new( block, path, keys )?;
+    let intensity = r.vec3_or_f32( "color" )?.unwrap_or( Vec3::ONE ) * r.f32( "intensity" )?.unwrap_or( 1.0 );
+
+    let light = match block.kind.as_str() {
+        "point_light" => PunctualLight::point( r.required( "position", r.vec3( "position" )? )?, intensity ),
+        "spot_light" => {
+            let position = r.required( "position", r.vec3( "position" )? )?;
+            let direction = match (r.vec3( "direction" )?, r.vec3( "look_at" )?) {
+                (Some(_), Some(_)) => return Err( r.error( block.pos, "give spot_light a direction or a look_at, not both".to_string() ) ),
+                (Some(dir), None) => dir,
+                (None, Some(target)) => target - position,
+                (None, None) => Vec3::new( 0.0, -1.0, 0.0 ),
+            };
+            if direction.length_squared() == 0.0 {
+                return Err( r.error( block.pos, "spot_light points nowhere".to_string() ) );
+            }
+            PunctualLight::spot( position, direction, intensity, r.f32( "angle" )?.unwrap_or( 30.0 ), r.f32( "blend" )?.unwrap_or( 5.0 ) )
+        },
+        _ => {
+            let direction = r.required( "direction", r.vec3( "direction" )? )?;
+            if direction.length_squared() == 0.0 {
+                return Err( r.error( block.pos, "directional_light points nowhere".to_string() ) );
+            }
+            PunctualLight::directional( direction, intensity )
+        },
+    };
+
+    // the profile points along the spot, or down unless aimed elsewhere
+    let Some(file) = r.string( "ies" )? else {
+        return Ok( light );
+    };
+    let file_pos = r.entry( "ies" ).map_or( block.pos, |e| e.value_pos );
+    let profile = ies::load( &base_dir.join( file ) ).map_err( |err| SceneError::Profile {
+        path : path.to_path_buf(), line : file_pos.line, col : file_pos.col, err } )?;
+    let aim = r.vec3( "aim" )?.unwrap_or( light.direction );
+    if aim.length_squared() == 0.0 {
+        return Err( r.error( block.pos, "point_light aim points nowhere".to_string() ) );
+    }
+    Ok( light.with_profile( Arc::new( profile ), aim ) )
+}
+
+fn build_material( block : &Block, path : &Path ) -> Result<MaterialRef, SceneError> {
+    let type_reader = BlockReader { block, path };
+    let Some( (kind, kind_pos) ) = type_reader.name( "type" )? else {
+        return Err( type_reader.missing( "type" ) );
+    };
+
+    let mat : MaterialRef = match kind {
+        "lambertian" => {
+            let r = BlockReader::new( block, path, &[ "type", "albedo" ] )?;
+            Arc::new( Lambertian::new( r.vec3_or_f32( "albedo" )?.unwrap_or( Vec3::from_float( 0.8 ) ) ) )
+        },
+        "metal" => {
+            let r = BlockReader::new( block, path, &[ "type", "albedo", "fuzz" ] )?;
+            Arc::new( Metal::new( r.vec3_or_f32( "albedo" )?.unwrap_or( Vec3::from_float( 0.8 ) ),
+                                  r.f32( "fuzz" )?.unwrap_or( 0.0 ) ) )
+        },
+        "dielectric" => {
+            let r = BlockReader::new( block, path, &[ "type", "ior", "roughness" ] )?;
+            let ior = r.f32( "ior" )?.unwrap_or( 1.5 );
+            match r.f32( "roughness" )?.unwrap_or( 0.0 ) {
+                roughness if roughness > 0.0 => Arc::new( RoughDielectric::new( ior, roughness ) ),
+                _ => Arc::new( Dielectric::new( ior ) ),
+            }
+        },
+        "conductor" => {
+            // a named metal, or its complex index of refraction; eta and k
+            // override the named metal's
+            let r = BlockReader::new( block, path, &[ "type", "metal", "eta", "k", "roughness" ] )?;
+            let preset = match r.name( "metal" )? {
+                Some( (name, pos) ) => Some( RoughConductor::preset( name ).ok_or_else( || r.error( pos, format!(
+                    "unknown metal '{}' (expected gold, copper or aluminium)", name ) ) )? ),
+                None => None,
+            };
+            let eta = r.vec3_or_f32( "eta" )?.or( preset.map( |p| p.0 ) ).ok_or_else( || r.missing( "metal" ) )?;
+            let k = r.vec3_or_f32( "k" )?.or( preset.map( |p| p.1 ) ).ok_or_else( || r.missing( "k" ) )?;
+            Arc::new( RoughConductor::new( eta, k, r.f32( "roughness" )?.unwrap_or( 0.0 ) ) )
+        },
+        "emissive" => {
+            let r = BlockReader::new( block, path, &[ "type", "emit", "intensity" ] )?;
+            let emit = r.vec3_or_f32( "emit" )?.unwrap_or( Vec3::ONE ) * r.f32( "intensity" )?.unwrap_or( 1.0 );
+            Arc::new( DiffuseLight::new( emit ) )
+        },
+        other => {
+            return Err( type_reader.error( kind_pos, format!(
+                "unknown material type '{}' (expected lambertian, metal, conductor, dielectric or emissive)", other ) ) );
+        },
+    };
+
+    Ok( mat )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse( text : &str ) -> Result<Scene, SceneError> {
+        parse_scene( text, Path::new( "test.scene" ) )
+    }
+
+    fn error_at( text : &str ) -> (usize, usize, String) {
+        match parse( text ) {
+            Err( SceneError::Parse { line, col, msg, .. } ) => (line, col, msg),
+            Err(other) => panic!( "unexpected error {}", other ),
+            Ok(_) => panic!( "expected an error" ),
+        }
+    }
+
+    #[test]
+    fn full_scene() {
+        let scene = parse( r#"
+            # a comment
+            render { width = 64  height = 32  spp = 4  seed = 3  tile_order = center-out  denoise = final }
+            camera {
+                look_from = [0, 1, 2]
+                look_at = [0, 0, -1]
+                vfov = 45
+            }
+            sphere { center = [0, 0, -1]  radius = 0.5  material = "glass" }
+            sphere { center = [0, -100.5, -1]  radius = 1e2  material = ground }
+            material ground { type = lambertian  albedo = 0.5 }
+            material glass { type = dielectric  ior = 1.33 }
+        "# ).unwrap();
+
+        assert_eq!( scene.camera.width, 64 );
+        assert_eq!( scene.camera.height, 32 );
+        assert_eq!( scene.settings.samples_per_pixel, 4 );
+        assert_eq!( scene.settings.seed, 3 );
+        assert_eq!( scene.settings.tile_order, TileOrder::CenterOut );
+        assert_eq!( scene.settings.denoise, DenoiseMode::Final );
+        assert_eq!( scene.camera.settings.vfov, 45.0 );
+        assert!( scene.settings.sky );
+        assert!( scene.settings.light_sampling );
+        assert!( scene.settings.light_tree );
+        assert!( scene.lights.is_empty() );
+    }
+
+    #[test]
+    fn whole_numbers() {
+        // exact past what f32 holds, and written other ways while it does
+        let scene = parse( "render { seed = 18446744073709551615  spp = 16777217  width = 64.0  height = 1e2 }" ).unwrap();
+        assert_eq!( scene.settings.seed, u64::MAX );
+        assert_eq!( scene.settings.samples_per_pixel, 16777217 );
+        assert_eq!( (scene.camera.width, scene.camera.height), (64, 100) );
+
+        let (_, col, msg) = error_at( "render { spp = 4294967296 }" );
+        assert_eq!( (col, msg.as_str()), (16, "'spp' should be a whole number up to 4294967295, got a number") );
+        let (_, _, msg) = error_at( "render { seed = 1e30 }" );
+        assert_eq!( msg, "'seed' should be a whole number up to 18446744073709551615, got a number" );
+        let (_, _, msg) = error_at( "render { spp = 2.5 }" );
+        assert_eq!( msg, "'spp' should be a whole number up to 4294967295, got a number" );
+    }
+
+    #[test]
+    fn microfacet_materials() {
+        let scene = parse( r#"
+            material brushed { type = conductor  metal = copper  roughness = 0.4 }
+            material custom { type = conductor  eta = [0.2, 0.9, 1.1]  k = 3  roughness = 0 }
+            material frosted { type = dielectric  roughness = 0.2 }
+            sphere { center = [0, 0, -2]  radius = 0.5  material = brushed }
+            sphere { center = [0, 0, -4]  radius = 0.5  material = custom }
+            sphere { center = [0, 0, -6]  radius = 0.5  material = frosted }
+        "# ).unwrap();
+
+        // rough surfaces can be lit by sampling lights, smooth ones can't
+        let ray = crate::ray::Ray::new( Vec3::ZERO, Vec3::new( 0.0, 0.0, -1.0 ) );
+        let rec = scene.hit( &ray, 0.001, f32::INFINITY ).unwrap();
+        assert!( rec.mat.eval( &ray, &rec, Vec3::new( 0.0, 0.0, 1.0 ) ).is_some() );
+        let copper = rec.mat.albedo( &rec );
+        assert!( copper.x > copper.z, "{:?}", copper );
+        let rec = scene.hit( &ray, 2.6, f32::INFINITY ).unwrap();
+        assert!( rec.mat.eval( &ray, &rec, Vec3::new( 0.0, 0.0, 1.0 ) ).is_none() );
+        let rec = scene.hit( &ray, 4.6, f32::INFINITY ).unwrap();
+        assert!( rec.mat.eval( &ray, &rec, Vec3::new( 0.0, 0.0, 1.0 ) ).is_some() );
+
+        let (line, col, msg) = error_at( "material m { type = conductor  metal = tin }" );
+        assert_eq!( (line, col), (1, 40) );
+        assert_eq!( msg, "unknown metal 'tin' (expected gold, copper or aluminium)" );
+        let (_, _, msg) = error_at( "material m { type = conductor  eta = 1.5 }" );
+        assert_eq!( msg, "material is missing 'k'" );
+    }
+
+    #[test]
+    fn lights() {
+        let scene = parse( r#"
+            render { sky = off  light_sampling = off  light_tree = off }
+            sky { sun_elevation = 30 }
+            material lamp { type = emissive  emit = [1, 0.5, 0.5]  intensity = 4 }
+            quad { corner = [-1, 2, -2]  u = [2, 0, 0]  v = [0, 0, 2]  material = lamp }
+        "# ).unwrap();
+        assert!( !scene.settings.sky );
+        assert!( scene.lights.environment.is_none() );
+        assert!( !scene.settings.light_sampling );
+        assert!( !scene.settings.light_tree );
+        assert_eq!( scene.lights.len(), 1 );
+
+        // the quad faces down, seen from below it glows
+        let ray = crate::ray::Ray::new( Vec3::ZERO, Vec3::new( 0.0, 1.0, -1.0 ) );
+        let rec = scene.hit( &ray, 0.001, f32::INFINITY ).unwrap();
+        assert_eq!( rec.mat.emitted( &rec ), Vec3::new( 4.0, 2.0, 2.0 ) );
+
+        let (line, col, msg) = error_at( "render { sky = maybe }" );
+        assert_eq!( (line, col), (1, 16) );
+        assert_eq!( msg, "'sky' should be on or off, got 'maybe'" );
+        let (_, _, msg) = error_at( "material m { type = emissive }\nquad { corner = [0,0,0] u = [1,0,0] v = [2,0,0] material = m }" );
+        assert_eq!( msg, "quad edges u and v are parallel" );
+
+        // cameras that can't make a basis, meshes that would lose their normals
+        let (line, col, msg) = error_at( "camera { look_from = [0, 2, 0]  look_at = [0, 0, 0]\n  up = [0, 5, 0] }" );
+        assert_eq!( (line, col, msg.as_str()), (2, 8, "up is parallel to the view direction") );
+        let (_, _, msg) = error_at( "camera { look_from = [0, 0, 1]  look_at = [0, 0, 0]  up = [0, 0, -1] }" );
+        assert_eq!( msg, "up is parallel to the view direction" );
+        let (_, _, msg) = error_at( "camera { look_from = [1, 1, 1]  look_at = [1, 1, 1] }" );
+        assert_eq!( msg, "look_from and look_at are the same point" );
+        let (line, col, msg) = error_at( "material m { type = lambertian }\nmesh { file = \"x.obj\"  material = m  scale = [1, 0, 1] }" );
+        assert_eq!( (line, col, msg.as_str()), (2, 46, "mesh scale can't be zero along any axis") );
+    }
+
+    #[test]
+    fn environment() {
+        let data = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "data" );
+        let text = r#"environment { file = "reference.hdr"  rotation = 45  intensity = 2 }"#;
+        let scene = parse_scene( text, &data.join( "test.scene" ) ).unwrap();
+        let env = scene.lights.environment.as_ref().unwrap();
+        assert_eq!( scene.lights.len(), 1 );
+        assert!( env.radiance( Vec3::new( 0.0, -1.0, 0.0 ) ).z > 2.0 );
+
+        match parse_scene( r#"environment { file = "missing.hdr" }"#, &data.join( "test.scene" ) ) {
+            Err( SceneError::Image { line, col, .. } ) => assert_eq!( (line, col), (1, 22) ),
+            other => panic!( "expected an image error, got {:?}", other.err() ),
+        }
+        let (_, _, msg) = error_at( "environment { rotation = 10 }" );
+        assert_eq!( msg, "environment is missing 'file'" );
+
+        // the file reads fine, but there's nothing in it
+        let dir = std::env::temp_dir();
+        std::fs::write( dir.join( "rtview_empty.pfm" ), b"PF\n0 0\n-1.0\n" ).unwrap();
+        match parse_scene( r#"environment { file = "rtview_empty.pfm" }"#, &dir.join( "test.scene" ) ) {
+            Err( SceneError::Image { err, .. } ) => assert_eq!( err.to_string(), "empty 0x0 environment map" ),
+            other => panic!( "expected an image error, got {:?}", other.err() ),
+        }
+
+        let scene = parse( "sky { sun_elevation = 20  sun_azimuth = 90  turbidity = 4 }" ).unwrap();
+        let sky = scene.lights.environment.as_ref().unwrap();
+        assert!( sky.radiance( Vec3::new( 1.0, 20f32.to_radians().tan(), 0.0 ) ).x > 1000.0 );
+        let (line, col, msg) = error_at( "sky { turbidity = 12 }" );
+        assert_eq!( (line, col), (1, 19) );
+        assert_eq!( msg, "turbidity should be between 1.7 and 10, got 12" );
+        let (_, _, msg) = error_at( "sky { }\nsky { }" );
+        assert_eq!( msg, "only one environment or sky block allowed" );
+    }
+
+    #[test]
+    fn punctual_lights() {
+        let data = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "data" );
+        let text = r#"
+            point_light { position = [0, 3, 0]  ies = "downlight.ies"  intensity = 0.01 }
+            spot_light { position = [2, 2, 0]  look_at = [2, 0, 0]  angle = 20  color = [1, 0.5, 0.25]  intensity = 4 }
+            directional_light { direction = [0, -1, 0]  intensity = 2 }
+        "#;
+        let scene = parse_scene( text, &data.join( "test.scene" ) ).unwrap();
+        let lights = &scene.lights.punctual;
+        assert_eq!( lights.len(), 3 );
+        assert_eq!( scene.lights.len(), 3 );
+
+        // 12 cd straight down from 3 units up
+        let (_, dist, light) = lights[0].illuminate( Vec3::ZERO );
+        assert_eq!( dist, 3.0 );
+        assert!( (light.x - 12.0 / 9.0).abs() < 1e-5 );
+        assert_eq!( lights[1].illuminate( Vec3::new( 2.0, 0.0, 0.0 ) ).2, Vec3::new( 1.0, 0.5, 0.25 ) );
+        assert_eq!( lights[1].illuminate( Vec3::new( 4.0, 0.0, 0.0 ) ).2, Vec3::ZERO );
+        assert_eq!( lights[2].illuminate( Vec3::ZERO ).0, Vec3::new( 0.0, 1.0, 0.0 ) );
+
+        let (_, _, msg) = error_at( "spot_light { position = [0,0,0]  direction = [0,-1,0]  look_at = [1,1,1] }" );
+        assert_eq!( msg, "give spot_light a direction or a look_at, not both" );
+        let (_, _, msg) = error_at( "directional_light { intensity = 2 }" );
+        assert_eq!( msg, "directional_light is missing 'direction'" );
+        let (_, _, msg) = error_at( "directional_light { direction = [0,0,0] }" );
+        assert_eq!( msg, "directional_light points nowhere" );
+        match parse_scene( r#"point_light { position = [0,0,0]  ies = "reference.hdr" }"#, &data.join( "test.scene" ) ) {
+            Err( SceneError::Profile { line, col, .. } ) => assert_eq!( (line, col), (1, 41) ),
+            other => panic!( "expected a profile error, got {:?}", other.err() ),
+        }
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let (line, col, msg) = error_at( "sphere {\n  centre = [0, 0, 0]\n}" );
+        assert_eq!( (line, col), (2, 3) );
+        assert!( msg.starts_with( "unknown key 'centre' in sphere" ), "{}", msg );
+
+        let (line, col, msg) = error_at( "material m { type = metal }\nsphere { center = [0,0,0] radius = \"big\" material = m }" );
+        assert_eq!( (line, col), (2, 36) );
+        assert_eq!( msg, "'radius' should be a number, got a string" );
+
+        let (line, col, msg) = error_at( "sphere { center = [0,0] radius = 1 material = m }" );
+        assert_eq!( (line, col), (1, 19) );
+        assert_eq!( msg, "'center' should be a list of 3 numbers, got a list" );
+
+        let (line, col, msg) = error_at( "render { tile_order = zigzag }" );
+        assert_eq!( (line, col), (1, 23) );
+        assert!( msg.starts_with( "unknown tile order 'zigzag'" ), "{}", msg );
+
+        let (line, col, msg) = error_at( "sphere { center = [0,0,0]\n  radius = 1\n  material = nope }" );
+        assert_eq!( (line, col), (3, 14) );
+        assert_eq!( msg, "unknown material 'nope'" );
+
+        let (line, col, msg) = error_at( "  sphere { radius = 1 }" );
+        assert_eq!( (line, col), (1, 3) );
+        assert_eq!( msg, "sphere is missing 'center'" );
+
+        let (line, col, _) = error_at( "camera { vfov = 30 " );
+        assert_eq!( (line, col), (1, 20) );
+
+        let (line, col, msg) = error_at( "material x { type = plastic }" );
+        assert_eq!( (line, col), (1, 21) );
+        assert!( msg.starts_with( "unknown material type 'plastic'" ) );
+
+        let (_, _, msg) = error_at( "teapot { }" );
+        assert!( msg.starts_with( "unknown block 'teapot'" ) );
+
+        let (line, col, msg) = error_at( "render { spp = 4 spp = 8 }" );
+        assert_eq!( (line, col), (1, 18) );
+        assert_eq!( msg, "'spp' is set twice" );
+    }
+}
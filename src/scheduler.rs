@@ -0,0 +1,283 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::render::Tile;
+
+// One thread per core unless told otherwise
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or( 1, |n| n.get() )
+}
+
+// A batch of tiles handed out by bumping an atomic index, so taking a tile
+// costs one fetch_add and no lock.
+struct Batch {
+    id : u64,
+    tiles : Vec<Tile>,
+    next : AtomicUsize,
+}
+
+impl Batch {
+    fn take( &self ) -> Option<Tile> {
+        let ndx = self.next.fetch_add( 1, Ordering::Relaxed );
+        self.tiles.get( ndx ).cloned()
+    }
+
+    // claims everything not handed out yet
+    fn take_rest( &self ) -> Vec<Tile> {
+        let ndx = self.next.swap( self.tiles.len(), Ordering::Relaxed );
+        self.tiles.get( ndx.. ).map_or( Vec::new(), |rest| rest.to_vec() )
+    }
+}
+
+// Makes the next batch when the current one runs dry, None once there are
+// no more
+pub type Feeder = Box<dyn FnMut() -> Option<Vec<Tile>> + Send>;
+
+struct QueueState {
+    batch : Option<Arc<Batch>>,
+    closed : bool,
+    feeder : Option<Feeder>,
+}
+
+// Where the workers get their tiles. The producer pushes a whole batch at a
+// time (a progressive pass), or leaves a feeder for the workers to make the
+// next one themselves as soon as they run out, without waiting for it.
+// Workers only take the lock when the batch they hold runs dry.
+pub struct TileQueue {
+    state : Mutex<QueueState>,
+    ready : Condvar,
+}
+
+impl TileQueue {
+
+    pub fn new() -> TileQueue {
+        TileQueue { state : Mutex::new( QueueState { batch : None, closed : false, feeder : None } ), ready : Condvar::new() }
+    }
+
+    // Has the first worker to run out of tiles ask feeder for the next
+    // batch. Once it returns None it's dropped, and only push_batch() adds
+    // tiles.
+    pub fn set_feeder( &self, feeder : Feeder ) {
+        self.state.lock().unwrap().feeder = Some( feeder );
+        self.ready.notify_all();
+    }
+
+    // Makes tiles the current batch. Anything left in the old one is kept,
+    // ahead of the new tiles.
+    pub fn push_batch( &self, tiles : Vec<Tile> ) {
+        let mut state = self.state.lock().unwrap();
+        let (id, mut all) = match &state.batch {
+            Some(old) => (old.id + 1, old.take_rest()),
+            None => (0, Vec::new()),
+        };
+        all.extend( tiles );
+        state.batch = Some( Arc::new( Batch { id, tiles : all, next : AtomicUsize::new( 0 ) } ) );
+        self.ready.notify_all();
+    }
+
+    // Takes a tile from the current batch without waiting
+    #[cfg(test)]
+    pub fn try_take( &self ) -> Option<Tile> {
+        self.state.lock().unwrap().batch.as_ref().and_then( |b| b.take() )
+    }
+
+    // No more batches. Workers finish the current one and exit.
+    pub fn close( &self ) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+
+    // Blocks until there's a batch newer than the one last_id, making it
+    // with the feeder if there is one, or returns None once the queue is
+    // closed and there isn't one.
+    fn wait_batch( &self, last_id : Option<u64> ) -> Option<Arc<Batch>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(batch) = &state.batch {
+                if Some( batch.id ) != last_id {
+                    return Some( batch.clone() );
+                }
+            }
+            if state.closed {
+                return None;
+            }
+            if let Some(feeder) = state.feeder.as_mut() {
+                match feeder() {
+                    Some(tiles) => {
+                        let id = state.batch.as_ref().map_or( 0, |b| b.id + 1 );
+                        state.batch = Some( Arc::new( Batch { id, tiles, next : AtomicUsize::new( 0 ) } ) );
+                        self.ready.notify_all();
+                    },
+                    None => state.feeder = None,
+                }
+                continue;
+            }
+            state = self.ready.wait( state ).unwrap();
+        }
+    }
+}
+
+impl Default for TileQueue {
+    fn default() -> TileQueue {
+        TileQueue::new()
+    }
+}
+
+#[derive(Default)]
+struct ThreadStats {
+    tiles : AtomicU32,
+    busy_nanos : AtomicU64,
+}
+
+// How hard one worker has been working
+#[derive(Clone, Copy, Debug)]
+pub struct Utilization {
+    pub tiles : u32,
+    pub busy : Duration,
+
+    // fraction of the pool's lifetime spent working, 0..1
+    pub fraction : f32,
+}
+
+pub struct WorkerPool {
+    handles : Vec<thread::JoinHandle<()>>,
+    stats : Arc<Vec<ThreadStats>>,
+    start : Instant,
+}
+
+impl WorkerPool {
+
+    // Starts num_threads workers taking tiles from queue and handing each to
+    // work( thread index, tile ). Workers exit when the queue is closed and
+    // drained, or when work returns false.
+    pub fn spawn<F>( num_threads : usize, queue : Arc<TileQueue>, work : F ) -> WorkerPool
+        where F : Fn( usize, Tile ) -> bool + Send + Sync + 'static
+    {
+        let work = Arc::new( work );
+        let stats : Arc<Vec<ThreadStats>> = Arc::new( (0..num_threads).map( |_| ThreadStats::default() ).collect() );
+
+        let mut handles = Vec::new();
+        for i in 0..num_threads {
+            let queue = queue.clone();
+            let work = work.clone();
+            let stats = stats.clone();
+            handles.push( thread::spawn( move || {
+
+                let mut batch : Option<Arc<Batch>> = None;
+                loop {
+                    let tile = batch.as_ref().and_then( |b| b.take() );
+                    let Some(tile) = tile else {
+                        // this batch is used up, wait for the next
+                        batch = queue.wait_batch( batch.as_ref().map( |b| b.id ) );
+                        if batch.is_none() {
+                            break;
+                        }
+                        continue;
+                    };
+
+                    let started = Instant::now();
+                    let keep_going = work( i, tile );
+                    stats[i].tiles.fetch_add( 1, Ordering::Relaxed );
+                    stats[i].busy_nanos.fetch_add( started.elapsed().as_nanos() as u64, Ordering::Relaxed );
+                    if !keep_going {
+                        break;
+                    }
+                }
+            }));
+        }
+
+        WorkerPool { handles, stats, start : Instant::now() }
+    }
+
+    pub fn num_threads( &self ) -> usize {
+        self.stats.len()
+    }
+
+    pub fn utilization( &self ) -> Vec<Utilization> {
+        let lifetime = self.start.elapsed().as_secs_f32().max( 1e-6 );
+        self.stats.iter().map( |s| {
+            let busy = Duration::from_nanos( s.busy_nanos.load( Ordering::Relaxed ) );
+            Utilization { tiles : s.tiles.load( Ordering::Relaxed ), busy, fraction : (busy.as_secs_f32() / lifetime).min( 1.0 ) }
+        }).collect()
+    }
+
+    // e.g. "4 threads, 97% busy (95%..99%)"
+    pub fn utilization_summary( &self ) -> String {
+        let util = self.utilization();
+        if util.is_empty() {
+            return "no threads".to_string();
+        }
+        let mean = util.iter().map( |u| u.fraction ).sum::<f32>() / util.len() as f32;
+        let lo = util.iter().map( |u| u.fraction ).fold( 1.0, f32::min );
+        let hi = util.iter().map( |u| u.fraction ).fold( 0.0, f32::max );
+        format!( "{} threads, {:.0}% busy ({:.0}%..{:.0}%)", util.len(), mean * 100.0, lo * 100.0, hi * 100.0 )
+    }
+
+    // Waits for the workers to exit
+    pub fn join( self ) -> Result<(), String> {
+        for handle in self.handles {
+            handle.join().map_err( |_| "render thread panicked".to_string() )?;
+        }
+        Ok( () )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn every_tile_taken_once() {
+        let queue = Arc::new( TileQueue::new() );
+        let (tx, rx) = mpsc::channel();
+        let pool = WorkerPool::spawn( 4, queue.clone(), move |_, tile : Tile| tx.send( (tile.pass, tile.x) ).is_ok() );
+
+        for pass in 0..3 {
+            queue.push_batch( (0..50).map( |x| Tile { pass, ..Tile::new( x, 0, 1, 1 ) } ).collect() );
+        }
+        queue.close();
+        pool.join().unwrap();
+
+        // earlier batches may be partly merged into later ones, but nothing is lost or repeated
+        let mut seen : Vec<(u32, u32)> = rx.try_iter().collect();
+        seen.sort();
+        let expected : Vec<(u32, u32)> = (0..3).flat_map( |p| (0..50).map( move |x| (p, x) ) ).collect();
+        assert_eq!( seen, expected );
+    }
+
+    #[test]
+    fn workers_feed_themselves() {
+        // nobody pushes anything after the first batch, the workers make
+        // the other two themselves
+        let queue = Arc::new( TileQueue::new() );
+        let mut left = 2;
+        queue.set_feeder( Box::new( move || {
+            left -= 1;
+            (left >= 0).then( || (0..10).map( |x| Tile { pass : 2 - left as u32, ..Tile::new( x, 0, 1, 1 ) } ).collect() )
+        }));
+        queue.push_batch( (0..10).map( |x| Tile::new( x, 0, 1, 1 ) ).collect() );
+
+        let (tx, rx) = mpsc::channel();
+        let pool = WorkerPool::spawn( 3, queue.clone(), move |_, tile : Tile| tx.send( (tile.pass, tile.x) ).is_ok() );
+        let mut seen : Vec<(u32, u32)> = rx.iter().take( 30 ).collect();
+        queue.close();
+        pool.join().unwrap();
+
+        seen.sort();
+        let expected : Vec<(u32, u32)> = (0..3).flat_map( |p| (0..10).map( move |x| (p, x) ) ).collect();
+        assert_eq!( seen, expected );
+    }
+
+    #[test]
+    fn closed_empty_queue_stops_workers() {
+        let queue = Arc::new( TileQueue::new() );
+        let pool = WorkerPool::spawn( 3, queue.clone(), |_, _| true );
+        queue.close();
+        assert_eq!( pool.num_threads(), 3 );
+        assert!( pool.utilization().iter().all( |u| u.tiles == 0 ) );
+        pool.join().unwrap();
+    }
+}
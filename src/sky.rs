@@ -0,0 +1,299 @@
+// Analytic daylight: the Preetham et al. (1999) clear sky with a sun disk,
+// for time-of-day lighting without an HDR map
+
+use std::f32::consts::PI;
+
+use vec3::Vec3;
+
+use super::rng::Rng;
+use super::environment::Environment;
+use super::tonemap::luminance;
+
+#[derive(Clone, Debug)]
+pub struct SkySettings {
+    // where the sun is, in degrees. Elevation is above the horizon, azimuth
+    // turns from -Z towards +X like an environment map's rotation.
+    pub sun_elevation : f32,
+    pub sun_azimuth : f32,
+
+    // haze, 2 is very clear, 10 is a murky summer day
+    pub turbidity : f32,
+
+    // the ground below the horizon, a diffuse plane lit by sun and sky
+    pub ground_albedo : Vec3,
+
+    // angular diameter of the sun in degrees, larger for softer shadows
+    pub sun_size : f32,
+
+    // scale on all of it
+    pub intensity : f32,
+}
+
+impl Default for SkySettings {
+    fn default() -> SkySettings {
+        SkySettings {
+            sun_elevation : 45.0,
+            sun_azimuth : 0.0,
+            turbidity : 3.0,
+            ground_albedo : Vec3::from_float( 0.3 ),
+            sun_size : 0.53,
+            intensity : 1.0,
+        }
+    }
+}
+
+// Luminance in kcd/m^2 is scaled by this to get radiance, which puts an
+// overhead sky somewhere around 1
+const SKY_SCALE : f32 = 0.05;
+
+// Luminance of the sun before the atmosphere dims it, in kcd/m^2
+const SUN_LUMINANCE : f32 = 1.6e6;
+
+// Perez et al. sky distribution, the five coefficients A..E
+struct Perez {
+    coeffs : [f32; 5],
+}
+
+impl Perez {
+    // theta from the zenith to the view direction, gamma from the sun to it
+    fn eval( &self, cos_theta : f32, gamma : f32 ) -> f32 {
+        let [a, b, c, d, e] = self.coeffs;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta.max( 0.01 )).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+pub struct PreethamSky {
+    sun_dir : Vec3,
+
+    // Y, x and y distributions and their values at the zenith
+    perez : [Perez; 3],
+    zenith : [f32; 3],
+    theta_sun : f32,
+
+    sun_radiance : Vec3,
+    sun_cos_max : f32,
+    sun_one_minus_cos : f32,
+
+    // what the ground below the horizon glows with
+    ground : Vec3,
+
+    // chance sample() aims at the sun rather than anywhere
+    sun_probability : f32,
+
+    intensity : f32,
+}
+
+impl PreethamSky {
+
+    pub fn new( settings : &SkySettings ) -> PreethamSky {
+        let t = settings.turbidity.clamp( 1.7, 10.0 );
+        let elevation = settings.sun_elevation.clamp( -90.0, 90.0 ).to_radians();
+        let azimuth = settings.sun_azimuth.to_radians();
+        let sun_dir = Vec3::new( elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos() );
+
+        // the model only holds with the sun up, below that keep it at the horizon
+        let theta_sun = (PI / 2.0 - elevation).min( PI / 2.0 );
+        let (t1, t2, t3) = (theta_sun, theta_sun * theta_sun, theta_sun * theta_sun * theta_sun);
+
+        let perez = [
+            Perez { coeffs : [ 0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703 ] },
+            Perez { coeffs : [ -0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452 ] },
+            Perez { coeffs : [ -0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529 ] },
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_lum = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max( 0.0 );
+        let zenith_x = t * t * (0.00166 * t3 - 0.00375 * t2 + 0.00209 * t1)
+            + t * (-0.02903 * t3 + 0.06377 * t2 - 0.03202 * t1 + 0.00394)
+            + (0.11693 * t3 - 0.21196 * t2 + 0.06052 * t1 + 0.25886);
+        let zenith_y = t * t * (0.00275 * t3 - 0.00610 * t2 + 0.00317 * t1)
+            + t * (-0.04214 * t3 + 0.08970 * t2 - 0.04153 * t1 + 0.00516)
+            + (0.15346 * t3 - 0.26756 * t2 + 0.06670 * t1 + 0.26688);
+
+        let half_size = (settings.sun_size.max( 0.01 ) * 0.5).to_radians();
+        let sun_one_minus_cos = 2.0 * (half_size * 0.5).sin().powi( 2 );
+        let sun_radiance = if settings.sun_elevation > 0.0 {
+            SUN_LUMINANCE * SKY_SCALE * sun_transmittance( theta_sun, t )
+        } else {
+            Vec3::ZERO
+        };
+
+        let mut sky = PreethamSky {
+            sun_dir,
+            perez,
+            zenith : [ zenith_lum, zenith_x, zenith_y ],
+            theta_sun,
+            sun_radiance,
+            sun_cos_max : 1.0 - sun_one_minus_cos,
+            sun_one_minus_cos,
+            ground : Vec3::ZERO,
+            sun_probability : 0.0,
+            intensity : settings.intensity,
+        };
+
+        // light falling on the ground, from the sky (a midpoint sum over the
+        // upper hemisphere) and from the sun
+        let (n_theta, n_phi) = (32, 64);
+        let mut sky_irradiance = Vec3::ZERO;
+        for i in 0..n_theta {
+            let theta = (i as f32 + 0.5) / n_theta as f32 * PI / 2.0;
+            for k in 0..n_phi {
+                let phi = (k as f32 + 0.5) / n_phi as f32 * 2.0 * PI;
+                let dir = Vec3::new( theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin() );
+                sky_irradiance += sky.sky_radiance( dir ) * (theta.cos() * theta.sin());
+            }
+        }
+        sky_irradiance = sky_irradiance * (PI / 2.0 / n_theta as f32) * (2.0 * PI / n_phi as f32);
+        let sun_irradiance = sun_radiance * (2.0 * PI * sun_one_minus_cos);
+        sky.ground = settings.ground_albedo * (sky_irradiance + sun_irradiance * sun_dir.y.max( 0.0 )) / PI;
+
+        // aim at the sun about as often as it outshines the rest
+        let (sun_power, sky_power) = (luminance( sun_irradiance ), luminance( sky_irradiance ) / PI);
+        if sun_power > 0.0 {
+            sky.sun_probability = (sun_power / (sun_power + sky_power)).clamp( 0.1, 0.9 );
+        }
+        sky
+    }
+
+    pub fn sun_direction( &self ) -> Vec3 {
+        self.sun_dir
+    }
+
+    // the sky alone, unit dir in the upper hemisphere
+    fn sky_radiance( &self, dir : Vec3 ) -> Vec3 {
+        let cos_theta = dir.y;
+        let gamma = Vec3::dot( &dir, &self.sun_dir ).clamp( -1.0, 1.0 ).acos();
+        let [lum, x, y] = [0, 1, 2].map( |k|
+            self.zenith[k] * self.perez[k].eval( cos_theta, gamma ) / self.perez[k].eval( 1.0, self.theta_sun ) );
+        SKY_SCALE * xyy_to_rgb( x, y, lum )
+    }
+}
+
+impl Environment for PreethamSky {
+    fn radiance( &self, dir : Vec3 ) -> Vec3 {
+        let dir = dir.normalize();
+        if dir.y < 0.0 {
+            return self.intensity * self.ground;
+        }
+        let mut radiance = self.sky_radiance( dir );
+        if Vec3::dot( &dir, &self.sun_dir ) > self.sun_cos_max {
+            radiance += self.sun_radiance;
+        }
+        self.intensity * radiance
+    }
+
+    // uniform over the sun's disk some of the time, else over the sphere
+    fn pdf( &self, dir : Vec3 ) -> f32 {
+        let dir = dir.normalize();
+        let mut pdf = (1.0 - self.sun_probability) / (4.0 * PI);
+        if Vec3::dot( &dir, &self.sun_dir ) > self.sun_cos_max {
+            pdf += self.sun_probability / (2.0 * PI * self.sun_one_minus_cos);
+        }
+        pdf
+    }
+
+    fn sample( &self, rng : &mut Rng ) -> Vec3 {
+        if rng.next_f32() >= self.sun_probability {
+            return rng.unit_vector();
+        }
+
+        // inside the cone, keeping 1 - cos(theta) rather than cos(theta)
+        // because the sun is so small
+        let one_minus_cos = rng.next_f32() * self.sun_one_minus_cos;
+        let cos_theta = 1.0 - one_minus_cos;
+        let sin_theta = (one_minus_cos * (2.0 - one_minus_cos)).sqrt();
+        let phi = 2.0 * PI * rng.next_f32();
+        let (t, s) = self.sun_dir.orthonormal_basis();
+        sin_theta * phi.cos() * t + sin_theta * phi.sin() * s + cos_theta * self.sun_dir
+    }
+}
+
+// Preetham's CIE xyY to linear sRGB
+fn xyy_to_rgb( x : f32, y : f32, lum : f32 ) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let cx = x / y * lum;
+    let cz = (1.0 - x - y) / y * lum;
+    Vec3::new(
+        ( 3.2406 * cx - 1.5372 * lum - 0.4986 * cz).max( 0.0 ),
+        (-0.9689 * cx + 1.8758 * lum + 0.0415 * cz).max( 0.0 ),
+        ( 0.0557 * cx - 0.2040 * lum + 1.0570 * cz).max( 0.0 ) )
+}
+
+// How much of the sun's light gets through the air, per channel: Rayleigh
+// scattering off the air and Angstrom's formula for the haze, over the
+// air mass along the sun's path (Kasten's formula).
+fn sun_transmittance( theta_sun : f32, turbidity : f32 ) -> Vec3 {
+    let zenith_deg = theta_sun.to_degrees();
+    let air_mass = 1.0 / (theta_sun.cos() + 0.15 * (93.885 - zenith_deg).powf( -1.253 ));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    // red, green and blue wavelengths in micrometres
+    let through = |lambda : f32| {
+        let rayleigh = (-0.008735 * lambda.powf( -4.08 ) * air_mass).exp();
+        let aerosol = (-beta * lambda.powf( -1.3 ) * air_mass).exp();
+        rayleigh * aerosol
+    };
+    Vec3::new( through( 0.68 ), through( 0.55 ), through( 0.44 ) )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sky_at( sun_elevation : f32 ) -> PreethamSky {
+        PreethamSky::new( &SkySettings { sun_elevation, sun_azimuth : 60.0, ..Default::default() } )
+    }
+
+    #[test]
+    fn daylight() {
+        let noon = sky_at( 70.0 );
+        let up = noon.radiance( Vec3::new( 0.0, 1.0, 0.0 ) );
+        assert!( up.z > up.x && up.x > 0.0, "{:?}", up );
+
+        // the sun is far brighter than the sky, and redder when low
+        let sun = noon.radiance( noon.sun_direction() );
+        assert!( luminance( sun ) > 1000.0 * luminance( up ) );
+        let dusk = sky_at( 4.0 );
+        let low_sun = dusk.radiance( dusk.sun_direction() );
+        assert!( low_sun.x / low_sun.z > 2.0 * sun.x / sun.z );
+        assert!( luminance( low_sun ) < luminance( sun ) );
+
+        // the ground is lit by both, and reflects in proportion to its albedo
+        let down = Vec3::new( 0.2, -1.0, 0.1 );
+        let ground = noon.radiance( down );
+        assert!( luminance( ground ) > 0.3 * luminance( up ) );
+        let white = PreethamSky::new( &SkySettings { sun_elevation : 70.0, sun_azimuth : 60.0, ground_albedo : Vec3::ONE, ..Default::default() } );
+        assert!( (white.radiance( down ) * 0.3 - ground).length() < 1e-4 * ground.length() );
+
+        // no sun at night
+        let night = sky_at( -10.0 );
+        assert_eq!( night.sun_radiance, Vec3::ZERO );
+        assert_eq!( night.sun_probability, 0.0 );
+    }
+
+    #[test]
+    fn sun_sampling() {
+        let sky = sky_at( 30.0 );
+        let mut rng = Rng::new( 7, 0 );
+
+        // samples land on the sun often, and always could have been picked
+        let mut on_sun = 0;
+        for _ in 0..1000 {
+            let dir = sky.sample( &mut rng );
+            assert!( sky.pdf( dir ) > 0.0 );
+            assert!( (dir.length() - 1.0).abs() < 1e-4 );
+            if Vec3::dot( &dir, &sky.sun_direction() ) > sky.sun_cos_max {
+                on_sun += 1;
+            }
+        }
+        assert!( on_sun > 100, "{}", on_sun );
+
+        // the sun part of the pdf integrates to its share over its disk
+        let solid_angle = 2.0 * PI * sky.sun_one_minus_cos;
+        let sun_pdf = sky.pdf( sky.sun_direction() ) - (1.0 - sky.sun_probability) / (4.0 * PI);
+        assert!( (sun_pdf * solid_angle / sky.sun_probability - 1.0).abs() < 1e-3 );
+    }
+}
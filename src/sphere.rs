@@ -0,0 +1,126 @@
+use std::f32::consts::PI;
+
+use vec3::Vec3;
+
+use super::ray::Ray;
+use super::rng::Rng;
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::material::MaterialRef;
+use super::light_tree::LightBounds;
+use super::tonemap::luminance;
+
+pub struct Sphere {
+    pub center : Vec3,
+    pub radius : f32,
+    pub mat : MaterialRef,
+}
+
+impl Sphere {
+    pub fn new( center : Vec3, radius : f32, mat : MaterialRef ) -> Sphere {
+        Sphere { center, radius, mat }
+    }
+}
+
+// Maps a point on the unit sphere to (u, v) in [0,1]. u goes around the
+// Y axis starting at -X, v goes from the south pole (y=-1) to the north.
+pub fn sphere_uv( p : Vec3 ) -> (f32, f32) {
+    let theta = (-p.y).clamp( -1.0, 1.0 ).acos();
+    let phi = (-p.z).atan2( p.x ) + std::f32::consts::PI;
+
+    (phi / (2.0 * std::f32::consts::PI), theta / std::f32::consts::PI)
+}
+
+impl Hittable for Sphere {
+    fn hit( &self, ray : &Ray, ray_tmin : f32, ray_tmax : f32 ) -> Option<HitRecord<'_>> {
+        let oc = ray.origin - self.center;
+        let a = ray.dir.length_squared();
+        let half_b = Vec3::dot( &oc, &ray.dir );
+        let c = oc.length_squared() - self.radius*self.radius;
+
+        let discriminant = half_b*half_b - a*c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrtd = discriminant.sqrt();
+
+        // find the nearest root in the acceptable range
+        let mut root = (-half_b - sqrtd) / a;
+        if root <= ray_tmin || ray_tmax <= root {
+            root = (-half_b + sqrtd) / a;
+            if root <= ray_tmin || ray_tmax <= root {
+                return None;
+            }
+        }
+
+        let p = ray.at( root );
+        let outward_n = (p - self.center) / self.radius;
+        let (normal, front_face) = HitRecord::face_normal( ray, outward_n );
+        let (u, v) = sphere_uv( outward_n );
+
+        Some(HitRecord {
+            t : root,
+            p,
+            front_face,
+            normal,
+            u,
+            v,
+            mat : self.mat.as_ref(),
+            object_id : 0,
+            material_id : 0,
+        })
+    }
+
+    fn bounding_box( &self ) -> Aabb {
+        let r = Vec3::from_float( self.radius.abs() );
+        Aabb::new( self.center - r, self.center + r )
+    }
+
+    fn material( &self ) -> Option<&MaterialRef> {
+        Some( &self.mat )
+    }
+
+    // Uniform over the cone the sphere covers seen from outside, or over
+    // its area from inside
+    fn pdf_value( &self, origin : Vec3, dir : Vec3 ) -> f32 {
+        let rec = match self.hit( &Ray::new( origin, dir ), 0.001, f32::INFINITY ) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+
+        let r_sq = self.radius * self.radius;
+        let dist_sq = (self.center - origin).length_squared();
+        if dist_sq > r_sq {
+            let cos_max = (1.0 - r_sq / dist_sq).sqrt();
+            1.0 / (2.0 * PI * (1.0 - cos_max))
+        } else {
+            let hit_sq = rec.t * rec.t * dir.length_squared();
+            let cosine = Vec3::dot( &dir, &rec.normal ).abs() / dir.length();
+            hit_sq / (cosine * 4.0 * PI * r_sq)
+        }
+    }
+
+    fn random_direction( &self, origin : Vec3, rng : &mut Rng ) -> Vec3 {
+        let to_center = self.center - origin;
+        let dist_sq = to_center.length_squared();
+        let r_sq = self.radius * self.radius;
+        if dist_sq <= r_sq {
+            return self.center + self.radius.abs() * rng.unit_vector() - origin;
+        }
+
+        let cos_max = (1.0 - r_sq / dist_sq).sqrt();
+        let cos_theta = 1.0 + rng.next_f32() * (cos_max - 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max( 0.0 ).sqrt();
+        let phi = 2.0 * PI * rng.next_f32();
+
+        let w = to_center / dist_sq.sqrt();
+        let (t, s) = w.orthonormal_basis();
+        sin_theta * phi.cos() * t + sin_theta * phi.sin() * s + cos_theta * w
+    }
+
+    // shines out every way from its 4 pi r^2 of surface
+    fn light_bounds( &self ) -> LightBounds {
+        let power = luminance( self.mat.emission() ) * PI * 4.0 * PI * self.radius * self.radius;
+        LightBounds::omni( self.bounding_box(), power )
+    }
+}
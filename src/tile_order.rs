@@ -0,0 +1,138 @@
+// The order tiles are handed to the render threads in
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileOrder {
+    Scanline,  // rows, top to bottom
+    Spiral,    // square rings around the center tile
+    Hilbert,   // along a Hilbert curve, neighbours render together
+    CenterOut, // nearest the image center first
+}
+
+impl TileOrder {
+
+    pub const ALL : [TileOrder; 4] = [ TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert, TileOrder::CenterOut ];
+
+    pub fn name( &self ) -> &'static str {
+        match self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Spiral => "spiral",
+            TileOrder::Hilbert => "hilbert",
+            TileOrder::CenterOut => "center-out",
+        }
+    }
+
+    pub fn from_name( name : &str ) -> Option<TileOrder> {
+        TileOrder::ALL.iter().copied().find( |o| o.name().eq_ignore_ascii_case( name ) )
+    }
+
+    // Sort key for the tile at column tx, row ty of a cols x rows grid.
+    // Ties keep scanline order.
+    fn key( &self, tx : u32, ty : u32, cols : u32, rows : u32 ) -> u64 {
+        match self {
+            TileOrder::Scanline => 0,
+            TileOrder::Spiral => spiral_index( tx as i64 - (cols as i64 - 1) / 2, ty as i64 - (rows as i64 - 1) / 2 ),
+            TileOrder::Hilbert => hilbert_index( cols.max( rows ).next_power_of_two(), tx, ty ),
+            TileOrder::CenterOut => {
+                // distance in doubled coordinates so the center is exact
+                let dx = 2 * tx as i64 + 1 - cols as i64;
+                let dy = 2 * ty as i64 + 1 - rows as i64;
+                (dx * dx + dy * dy) as u64
+            },
+        }
+    }
+
+    // Reorders a grid of tiles given in scanline order
+    pub fn apply<T>( &self, tiles : Vec<T>, cols : u32, rows : u32 ) -> Vec<T> {
+        debug_assert_eq!( tiles.len(), (cols * rows) as usize );
+        let mut keyed : Vec<(u64, T)> = tiles.into_iter().enumerate().map( |(i, tile)| {
+            let i = i as u32;
+            (self.key( i % cols, i / cols, cols, rows ), tile)
+        }).collect();
+        keyed.sort_by_key( |(key, _)| *key );
+        keyed.into_iter().map( |(_, tile)| tile ).collect()
+    }
+}
+
+// Position on a square spiral around (0, 0): ring by ring, each ring
+// clockwise starting from its top left corner.
+fn spiral_index( dx : i64, dy : i64 ) -> u64 {
+    let r = dx.abs().max( dy.abs() );
+    if r == 0 {
+        return 0;
+    }
+
+    // rings inside this one hold (2r-1)^2 cells
+    let before = (2 * r - 1) * (2 * r - 1);
+    let along = if dy == -r {
+        dx + r                 // top, left to right
+    } else if dx == r {
+        2 * r + (dy + r)       // right, top to bottom
+    } else if dy == r {
+        4 * r + (r - dx)       // bottom, right to left
+    } else {
+        6 * r + (r - dy)       // left, bottom to top
+    };
+    (before + along) as u64
+}
+
+// Distance of (x, y) along a Hilbert curve filling an n x n grid, n a power of two
+fn hilbert_index( n : u32, mut x : u32, mut y : u32 ) -> u64 {
+    let mut d = 0u64;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u32::from( x & s > 0 );
+        let ry = u32::from( y & s > 0 );
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+
+        // rotate the quadrant so the curve joins up
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap( &mut x, &mut y );
+        }
+        x &= s - 1;
+        y &= s - 1;
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid( cols : u32, rows : u32 ) -> Vec<(u32, u32)> {
+        (0..rows).flat_map( |y| (0..cols).map( move |x| (x, y) ) ).collect()
+    }
+
+    #[test]
+    fn orders_are_permutations() {
+        for order in TileOrder::ALL {
+            assert_eq!( TileOrder::from_name( order.name() ), Some( order ) );
+            for (cols, rows) in [ (1, 1), (5, 3), (4, 7), (10, 10) ] {
+                let mut tiles = order.apply( grid( cols, rows ), cols, rows );
+                tiles.sort_by_key( |&(x, y)| (y, x) );
+                assert_eq!( tiles, grid( cols, rows ), "{} {}x{}", order.name(), cols, rows );
+            }
+        }
+    }
+
+    #[test]
+    fn center_first() {
+        for order in [ TileOrder::Spiral, TileOrder::CenterOut ] {
+            assert_eq!( order.apply( grid( 5, 5 ), 5, 5 )[0], (2, 2) );
+        }
+        assert_eq!( TileOrder::Spiral.apply( grid( 3, 3 ), 3, 3 )[1..4], [ (0, 0), (1, 0), (2, 0) ] );
+    }
+
+    #[test]
+    fn hilbert_steps_to_neighbours() {
+        let tiles = TileOrder::Hilbert.apply( grid( 8, 8 ), 8, 8 );
+        for pair in tiles.windows( 2 ) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!( a.0.abs_diff( b.0 ) + a.1.abs_diff( b.1 ), 1, "{:?} -> {:?}", a, b );
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// Display transform: exposure, tone mapping and the sRGB transfer function.
+// Turns linear scene radiance into 8-bit display values. Only used when
+// producing something to look at; the film itself stays linear.
+
+use vec3::Vec3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    None,       // clamp to [0,1]
+    Reinhard,
+    Aces,
+    AgX,
+}
+
+impl ToneMap {
+    pub const ALL : [ToneMap; 4] = [ ToneMap::None, ToneMap::Reinhard, ToneMap::Aces, ToneMap::AgX ];
+
+    pub fn name( &self ) -> &'static str {
+        match self {
+            ToneMap::None => "none",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::Aces => "aces",
+            ToneMap::AgX => "agx",
+        }
+    }
+
+    pub fn from_name( name : &str ) -> Option<ToneMap> {
+        ToneMap::ALL.iter().copied().find( |tm| tm.name().eq_ignore_ascii_case( name ) )
+    }
+
+    // Maps linear radiance to linear display values in [0,1]
+    pub fn apply( &self, c : Vec3 ) -> Vec3 {
+        match self {
+            ToneMap::None => c,
+            ToneMap::Reinhard => Vec3::new( c.x / (1.0 + c.x), c.y / (1.0 + c.y), c.z / (1.0 + c.z) ),
+            ToneMap::Aces => Vec3::new( aces_fitted( c.x ), aces_fitted( c.y ), aces_fitted( c.z ) ),
+            ToneMap::AgX => agx( c ),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DisplayTransform {
+    // in stops, 0 leaves the image alone, +1 doubles it
+    pub exposure : f32,
+    pub tone_map : ToneMap,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> DisplayTransform {
+        DisplayTransform { exposure : 0.0, tone_map : ToneMap::None }
+    }
+}
+
+impl DisplayTransform {
+
+    // Linear display values in [0,1], before encoding
+    pub fn apply_linear( &self, c : Vec3 ) -> Vec3 {
+        let scaled = c * self.exposure.exp2();
+        let mapped = self.tone_map.apply( scaled );
+        Vec3::new( clamp01( mapped.x ), clamp01( mapped.y ), clamp01( mapped.z ) )
+    }
+
+    pub fn apply( &self, c : Vec3 ) -> [u8; 3] {
+        let c = self.apply_linear( c );
+        [ to_u8( srgb_oetf( c.x ) ), to_u8( srgb_oetf( c.y ) ), to_u8( srgb_oetf( c.z ) ) ]
+    }
+}
+
+// also maps NaN to 0, so one bad sample doesn't poison the display
+fn clamp01( x : f32 ) -> f32 {
+    if x > 0.0 { x.min( 1.0 ) } else { 0.0 }
+}
+
+fn to_u8( x : f32 ) -> u8 {
+    (x * 255.0 + 0.5) as u8
+}
+
+// Rec. 709 luminance of linear rgb
+pub fn luminance( c : Vec3 ) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// Linear to sRGB encoded, both in [0,1]
+pub fn srgb_oetf( x : f32 ) -> f32 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf( 1.0 / 2.4 ) - 0.055
+    }
+}
+
+// sRGB encoded to linear, the inverse of srgb_oetf
+pub fn srgb_eotf( x : f32 ) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf( 2.4 )
+    }
+}
+
+// Krzysztof Narkowicz's curve fit of the ACES filmic tone curve
+fn aces_fitted( x : f32 ) -> f32 {
+    let x = x.max( 0.0 );
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+// AgX (Troy Sobotka), using the polynomial fit of the default contrast
+// curve by Benjamin Wrensch. Inset into the AgX working space, log encode,
+// apply the sigmoid, then outset and undo the curve's 2.2 display gamma.
+fn agx( c : Vec3 ) -> Vec3 {
+    const MIN_EV : f32 = -12.47393;
+    const MAX_EV : f32 = 4.026069;
+
+    let inset = Vec3::new(
+        0.84247906 * c.x + 0.0784336 * c.y + 0.079223745 * c.z,
+        0.042328242 * c.x + 0.87846864 * c.y + 0.07916613 * c.z,
+        0.042375655 * c.x + 0.0784336 * c.y + 0.879143 * c.z );
+
+    let curve = |x : f32| {
+        let x = (x.max( 1e-10 ).log2().clamp( MIN_EV, MAX_EV ) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    };
+    let v = Vec3::new( curve( inset.x ), curve( inset.y ), curve( inset.z ) );
+
+    let outset = Vec3::new(
+        1.196879 * v.x - 0.09802088 * v.y - 0.09902974 * v.z,
+        -0.052896852 * v.x + 1.1519031 * v.y - 0.098961177 * v.z,
+        -0.052971636 * v.x - 0.09804345 * v.y + 1.1510737 * v.z );
+
+    Vec3::new( outset.x.max( 0.0 ).powf( 2.2 ), outset.y.max( 0.0 ).powf( 2.2 ), outset.z.max( 0.0 ).powf( 2.2 ) )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trip() {
+        assert_eq!( srgb_oetf( 0.0 ), 0.0 );
+        assert!( (srgb_oetf( 1.0 ) - 1.0).abs() < 1e-6 );
+        assert!( (srgb_oetf( 0.18 ) - 0.4614).abs() < 1e-3 );
+        for i in 0..=100 {
+            let x = i as f32 / 100.0;
+            assert!( (srgb_eotf( srgb_oetf( x ) ) - x).abs() < 1e-5 );
+        }
+    }
+
+    #[test]
+    fn display_values() {
+        let xform = DisplayTransform::default();
+        assert_eq!( xform.apply( Vec3::ZERO ), [0, 0, 0] );
+        assert_eq!( xform.apply( Vec3::ONE ), [255, 255, 255] );
+
+        // out of range values clamp instead of wrapping
+        assert_eq!( xform.apply( Vec3::new( 4.0, -1.0, f32::NAN ) ), [255, 0, 0] );
+
+        // one stop down halves linear radiance
+        let xform = DisplayTransform { exposure : -1.0, tone_map : ToneMap::None };
+        assert_eq!( xform.apply_linear( Vec3::ONE ), Vec3::from_float( 0.5 ) );
+    }
+
+    #[test]
+    fn curves_are_monotonic() {
+        for tm in ToneMap::ALL {
+            assert_eq!( ToneMap::from_name( tm.name() ), Some( tm ) );
+
+            let mut prev = -1.0;
+            for i in 0..200 {
+                let x = (i as f32 * 0.1 - 10.0).exp2();
+                let y = tm.apply( Vec3::from_float( x ) ).y;
+                assert!( y >= prev, "{} not monotonic at {}", tm.name(), x );
+                prev = y;
+            }
+        }
+    }
+}
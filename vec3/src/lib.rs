@@ -9,7 +9,7 @@ pub enum Axis {
     Z,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -137,6 +137,17 @@ impl Vec3 {
             z: a.x * b.y - a.y * b.x,
         };
     }
+
+    // Two unit vectors that make a right-handed orthonormal basis with this
+    // one, which must be unit length (Duff et al. 2017)
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let sign = 1.0f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        let t = Vec3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x);
+        let s = Vec3::new(b, sign + self.y * self.y * a, -self.y);
+        return (t, s);
+    }
 }
 
 // This macro helps us implement math operators on Vector3
@@ -439,6 +450,21 @@ mod tests {
         assert_eq!(Vec3::cross(&a, &b), Vec3::new(0.0, 0.0, 1.0));
     }
 
+    #[test]
+    fn orthonormal_basis() {
+        for n in [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 2.0, 3.0).normalize(),
+            Vec3::new(0.3, -0.9, -0.1).normalize(),
+        ] {
+            let (t, s) = n.orthonormal_basis();
+            assert!((t.length() - 1.0).abs() < 1e-5 && (s.length() - 1.0).abs() < 1e-5);
+            assert!(Vec3::dot(&t, &s).abs() < 1e-5 && Vec3::dot(&t, &n).abs() < 1e-5);
+            assert!((Vec3::cross(&t, &s) - n).length() < 1e-5);
+        }
+    }
+
     #[test]
     fn length() {
         let a = Vec3::new(3.0, 2.0, 1.0);